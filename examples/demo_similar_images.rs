@@ -5,6 +5,9 @@ use pbr::ProgressBar;
 use simplelog::{ColorChoice, CombinedLogger, Config, TermLogger, TerminalMode, WriteLogger};
 use std::fs::File;
 
+// number of processed images between two snapshots of the store
+const SNAPSHOT_INTERVAL: usize = 1000;
+
 /// Searches for similar images inside a directory or optionally also all it's subdirectories
 ///
 /// It can happen that it finds similarities that you will not see as such.
@@ -28,7 +31,8 @@ pub fn main() {
 
     let mut logfile = "demo_similar_images.txt".to_string();
     let mut recursive = false;
-    let mut sensitivity: f64 = -60.0;
+    // level between 0 and 100, see duplo_rs::sensitivity::Sensitivity::from
+    let mut sensitivity: f64 = 0.0;
     let curdir = std::env::current_dir().unwrap().as_os_str().to_owned();
     let mut directory = duplo_rs::files::osstring_to_string(&curdir);
     let matches = command!() // requires `cargo` feature
@@ -50,9 +54,8 @@ pub fn main() {
         directory = ret.clone();
     }
     if let Some(ret) = matches.get_one::<String>("sensitivity") {
-        let ret = i64::from_str_radix(ret, 10);
-        if ret.is_ok() {
-            sensitivity -= ret.unwrap() as f64;
+        if let Ok(value) = ret.parse::<i64>() {
+            sensitivity = value as f64;
         }
     }
     if let Some(ret) = matches.get_one::<bool>("recursive") {
@@ -85,35 +88,57 @@ pub fn main() {
     // create the directory where the user can compare the similar image pairs
     let dst: std::path::PathBuf = p.join("similar_images");
     let storepath = p.join("demo_example_images.store");
-    let mut store: duplo_rs::store::Store = duplo_rs::store::Store::new(sensitivity);
-    if storepath.is_file() {
-        let storefile = duplo_rs::files::osstring_to_string(storepath.as_os_str());
-        store.slurp_binary(&storefile);
+    let storefile = duplo_rs::files::osstring_to_string(storepath.as_os_str());
+    let cachepath = p.join("demo_example_images.hashcache");
+    let cachefile = duplo_rs::files::osstring_to_string(cachepath.as_os_str());
+    let mut store: duplo_rs::store::Store = duplo_rs::store::Store::new(duplo_rs::sensitivity::Sensitivity::from(sensitivity), duplo_rs::hashconfig::HashConfig::new());
+    // Read the last snapshot and replay the journal or start a new snapshot.
+    // From here on every change is journaled and survives a crash or Ctrl-C.
+    let ret = if storepath.is_file() {
+        store.slurp_binary(&storefile)
+    } else {
+        store.dump_binary(&storefile)
+    };
+    if let Err(error) = ret {
+        log::error!("Failed to open the store {}: {}", storefile, error);
+        std::process::exit(1);
     }
 
+    // Remember the hashes of all files, so a rerun only hashes new and changed files.
+    let mut cache = match duplo_rs::hashcache::HashCache::load(&cachefile) {
+        Ok(cache) => cache,
+        Err(error) => {
+            log::warn!("Failed to read the hash cache {}, starting a new one: {}", cachefile, error);
+            duplo_rs::hashcache::HashCache::new()
+        }
+    };
+
     // get the list of files to process
-    let filelist;
-    if recursive {
+    let ret = if recursive {
         // Consider all subdirectories --> will take a long time!
-        filelist = duplo_rs::files::walk_tree_images(&directory);
+        duplo_rs::files::walk_tree_images(&directory)
     } else {
-        filelist = duplo_rs::files::walk_dir_images(&directory);
-    }
+        duplo_rs::files::walk_dir_images(&directory)
+    };
+    let filelist = match ret {
+        Ok(filelist) => filelist,
+        Err(error) => {
+            log::error!("Failed to list the images in {}: {}", directory, error);
+            std::process::exit(1);
+        }
+    };
     let mut progressbar = ProgressBar::new(filelist.len() as u64);
 
-    // process the files
-    for file in filelist.iter() {
-        let hash = duplo_rs::files::process_image(file);
-        let filepath = duplo_rs::files::osstring_to_string(file.as_os_str());
-        let (matches, failedid, failedhash) =
-            duplo_rs::files::find_similar_images(&store, &filepath, &hash);
-        progressbar.inc();
-        for i in 0..matches.m.len() {
-            log::warn!("Match {} is similar to {}.", matches.m[i].id, filepath);
-            let retmatch = imagesize::size(matches.m[i].id.clone());
-            let retsource = imagesize::size(filepath.clone());
+    // Hash the files on all cores and compare them with the store one by one.
+    let options = duplo_rs::ingest::IngestOptions::new();
+    let on_match = |store: &mut duplo_rs::store::Store, filepath: &str, _hash: &duplo_rs::hash::Hash,
+                    matches: &duplo_rs::matches::Matches| {
+        for m in matches.m.iter() {
+            log::warn!("Match {} is similar to {}.", m.id, filepath);
+            let retmatch = imagesize::size(m.id.clone());
+            let retsource = imagesize::size(filepath);
             if retmatch.is_err() {
-                log::error!("Failed to read the size of the image {}!", matches.m[i].id);
+                log::error!("Failed to read the size of the image {}!", m.id);
                 continue;
             }
             if retsource.is_err() {
@@ -124,25 +149,40 @@ pub fn main() {
             let sourcesize = retsource.unwrap();
             if matchsize.width * matchsize.height > sourcesize.width * sourcesize.height {
                 // match is the *better* image, drop the new hash
-                duplo_rs::files::present_pairs(&dst, &filepath, &matches.m[i].id);
+                if let Err(error) = duplo_rs::files::present_pairs(&dst, filepath, &m.id) {
+                    log::error!("Failed to present the pair {} and {}: {}", filepath, m.id, error);
+                }
             } else {
                 // source is the *better* image, remove match from store, add the source and drop the rest of the matches
-                duplo_rs::files::present_pairs(&dst, &matches.m[i].id, &filepath);
-                store.delete(&matches.m[i].id);
-                store.add(&filepath, &hash);
-                break;
+                if let Err(error) = duplo_rs::files::present_pairs(&dst, &m.id, filepath) {
+                    log::error!("Failed to present the pair {} and {}: {}", m.id, filepath, error);
+                }
+                store.delete(&m.id)?;
+                return Ok(true);
             }
         }
-        // add the current file to the store
-        if matches.m.len() == 0 {
-            store.add(&failedid, &failedhash);
+        Ok(false)
+    };
+    // process the files, a snapshot after every SNAPSHOT_INTERVAL files keeps the journal short
+    for chunk in filelist.chunks(SNAPSHOT_INTERVAL) {
+        if let Err(error) = duplo_rs::ingest::ingest_images(chunk, &mut store, Some(&mut cache), &options, on_match) {
+            log::error!("Failed to add the images to the store: {}", error);
+            break;
         }
-    }
-    if storepath.is_file() {
-        let ret = std::fs::remove_file(storepath);
-        if ret.is_err() {
-            log::error!("Failed to delete store file");
+        progressbar.add(chunk.len() as u64);
+        if let Err(error) = store.dump_binary(&storefile) {
+            log::error!("Failed to write the store: {}", error);
+        }
+        if let Err(error) = cache.save(&cachefile) {
+            log::error!("Failed to write the hash cache: {}", error);
         }
     }
-    store.dump_binary("demo_example_images.store");
+    if let Err(error) = store.dump_binary(&storefile) {
+        log::error!("Failed to write the store: {}", error);
+    }
+    // files that were moved away to present the pairs are gone
+    cache.prune();
+    if let Err(error) = cache.save(&cachefile) {
+        log::error!("Failed to write the hash cache: {}", error);
+    }
 }
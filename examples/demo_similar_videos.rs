@@ -51,7 +51,8 @@ use std::thread;
 pub fn main() {
     let mut logfile = "demo_similar_videos.txt".to_string();
     let mut recursive = false;
-    let mut sensitivity: f64 = -60.0;
+    // level between 0 and 100, see duplo_rs::sensitivity::Sensitivity::from
+    let mut sensitivity: f64 = 0.0;
 
     let curdir = std::env::current_dir().unwrap().as_os_str().to_owned();
     let mut directory = duplo_rs::files::osstring_to_string(&curdir);
@@ -82,33 +83,28 @@ pub fn main() {
         directory = ret.clone();
     }
     if let Some(ret) = matches.get_one::<String>("sensitivity") {
-        let ret = i64::from_str_radix(ret, 10);
-        if ret.is_ok() {
-            sensitivity -= ret.unwrap() as f64;
+        if let Ok(value) = ret.parse::<i64>() {
+            sensitivity = value as f64;
         }
     }
     if let Some(ret) = matches.get_one::<String>("num_seconds_between_screenshots") {
-        let ret = i64::from_str_radix(ret, 10);
-        if ret.is_ok() {
-            num_seconds_between_screenshots = ret.unwrap() as u32;
+        if let Ok(value) = ret.parse::<i64>() {
+            num_seconds_between_screenshots = value as u32;
         }
     }
     if let Some(ret) = matches.get_one::<String>("min_similar_screenshots_in_sequence") {
-        let ret = i64::from_str_radix(ret, 10);
-        if ret.is_ok() {
-            min_similar_screenshots_in_sequence = ret.unwrap() as u32;
+        if let Ok(value) = ret.parse::<i64>() {
+            min_similar_screenshots_in_sequence = value as u32;
         }
     }
     if let Some(ret) = matches.get_one::<String>("max_candidates_in_cache") {
-        let ret = i64::from_str_radix(ret, 10);
-        if ret.is_ok() {
-            max_candidates_in_cache = ret.unwrap() as usize;
+        if let Ok(value) = ret.parse::<i64>() {
+            max_candidates_in_cache = value as usize;
         }
     }
     if let Some(ret) = matches.get_one::<String>("num_threads") {
-        let ret = i64::from_str_radix(ret, 10);
-        if ret.is_ok() {
-            num_threads = ret.unwrap() as u32;
+        if let Ok(value) = ret.parse::<i64>() {
+            num_threads = value as u32;
         }
     }
     println!("Sensitivity {}\nStartdirectory {}\nnum_threads {}\nnum sec betw. screenshots {}\nmin similar screenshots in sequence {}\nnum candidates in cache {}", 
@@ -149,13 +145,19 @@ pub fn main() {
     // create the directory where the user can compare the similar image pairs
     let dst: std::path::PathBuf = p.join("similar_videos");
     // get the list of files to process
-    let filelist;
-    if recursive {
+    let ret = if recursive {
         // Consider all subdirectories --> will take a long time!
-        filelist = duplo_rs::files::walk_tree_videos(&directory);
+        duplo_rs::files::walk_tree_videos(&directory)
     } else {
-        filelist = duplo_rs::files::walk_dir_videos(&directory);
-    }
+        duplo_rs::files::walk_dir_videos(&directory)
+    };
+    let filelist = match ret {
+        Ok(filelist) => filelist,
+        Err(error) => {
+            log::error!("Failed to list the videos in {}: {}", directory, error);
+            std::process::exit(1);
+        }
+    };
     let mut progressbar = ProgressBar::new(filelist.len() as u64);
     let homedir_opt = dirs::home_dir();
     if homedir_opt.is_none() {
@@ -165,18 +167,25 @@ pub fn main() {
     let dbpath = homedir_opt.unwrap().join("similar_videos.sqlite3");
     let dbpathstr = duplo_rs::files::osstring_to_string(dbpath.as_os_str());
     let sql_client_opt = duplo_rs::videostore::connect(&dbpathstr);
-    if sql_client_opt.is_ok() {
-        let mut sql_client = sql_client_opt.unwrap();
-        let mut store = duplo_rs::videostore::VideoStore::new(
+    if let Ok(mut sql_client) = sql_client_opt {
+        let store_opt = duplo_rs::videostore::VideoStore::new(
             &mut sql_client,
-            sensitivity,
+            duplo_rs::sensitivity::Sensitivity::from(sensitivity),
             &directory,
             num_threads,
             num_seconds_between_screenshots,
             min_similar_screenshots_in_sequence,
             max_candidates_in_cache,
+            duplo_rs::hashconfig::HashConfig::new(),
         );
-        
+        let mut store = match store_opt {
+            Ok(store) => store,
+            Err(error) => {
+                log::error!("Failed to open the video store {}: {}", dbpathstr, error);
+                return;
+            }
+        };
+
         let num_videos = filelist.len() as u32;
         //let prev_videos = store.num_candidates;
         let mut video_id_counter = store.num_candidates + 1;
@@ -193,7 +202,7 @@ pub fn main() {
 
                 let video_id = video_id_counter;
                 let filepath = filelist[filepos as usize].clone();
-                let filestring = duplo_rs::files::osstring_to_string(&filepath.as_os_str());
+                let filestring = duplo_rs::files::osstring_to_string(filepath.as_os_str());
                 if store.ids.contains_key(&filestring) {
                     filepos += 1;
                     progressbar.inc();
@@ -201,41 +210,58 @@ pub fn main() {
                 }
                 video_id_counter += 1;
                 let tx1 = mpsc::Sender::clone(&tx);
+                let config = store.config();
                 let handle = thread::spawn(move || {
                     // call function with
                     // sender as parameter
-                    parallel_processor(tx1, &filepath, video_id, num_videos, store.num_seconds_between_screenshots, filepos + 1);
+                    parallel_processor(tx1, &filepath, video_id, num_videos, store.num_seconds_between_screenshots, filepos + 1, config);
                 });
                 filepos += 1;
                 handles.push(handle);
             }
             for _ in 0..handles.len() {
-                let video_opt = rx.recv();
-                if video_opt.is_err() {
-                    log::error!("Failed to receive video data.");
-                    continue;
-                }
-                let video = video_opt.unwrap();
+                progressbar.inc();
+                let video = match rx.recv() {
+                    Ok(Ok(video)) => video,
+                    Ok(Err(error)) => {
+                        log::error!("Failed to process video: {}", error);
+                        continue;
+                    }
+                    Err(_) => {
+                        log::error!("Failed to receive video data.");
+                        continue;
+                    }
+                };
 
-                let (videomatches, _failedid, _failedhash) = duplo_rs::files::find_similar_videos(
+                let videomatches = match duplo_rs::files::find_similar_videos(
                     &mut store,
                     &mut sql_client,
                     &video.id,
                     &video,
-                );
+                ) {
+                    Ok((videomatches, _failedid, _failedhash)) => videomatches,
+                    Err(error) => {
+                        log::error!("Failed to search matches for video {}: {}", video.id, error);
+                        continue;
+                    }
+                };
                 log::warn!("Found {} matches for video {}.", videomatches.len(), video.id);
-                progressbar.inc();
-                let mut compare: Vec<duplo_rs::videocandidate::VideoCandidate> = Vec::new();
-                compare.push(video.clone());
-                for i in 0..videomatches.m.len() {
-                    log::warn!("Match {} is similar to {}.", videomatches.m[i].id, video.id);
-                    let index = store.ids[&videomatches.m[i].id];
-                    let (_, candidate) = store.return_candidate(&mut sql_client, index as u32);
-                    compare.push(candidate);
+                let mut compare: Vec<duplo_rs::videocandidate::VideoCandidate> = vec![video.clone()];
+                for m in videomatches.m.iter() {
+                    log::warn!("Match {} is similar to {}.", m.id, video.id);
+                    let index = store.ids[&m.id];
+                    match store.return_candidate(&mut sql_client, index as u32) {
+                        Ok((_, candidate)) => compare.push(candidate),
+                        Err(error) => log::error!("Failed to read video {}: {}", m.id, error),
+                    }
                 }
                 // add the current file to the store
-                store.add(&mut sql_client, &video.id, &video, video.runtime);
-                duplo_rs::files::present_video_matches(&dst, &compare);
+                if let Err(error) = store.add(&mut sql_client, &video.id, &video, video.runtime) {
+                    log::error!("Failed to add video {} to the store: {}", video.id, error);
+                }
+                if let Err(error) = duplo_rs::files::present_video_matches(&dst, &compare) {
+                    log::error!("Failed to present the matches of video {}: {}", video.id, error);
+                }
                 for handlepos in (0..handles.len()).rev() {
                     if handles[handlepos].is_finished() {
                         handles.remove(handlepos);
@@ -248,15 +274,15 @@ pub fn main() {
 }
 
 fn parallel_processor(
-    a: mpsc::Sender<duplo_rs::videocandidate::VideoCandidate>,
-    filepath: &std::path::PathBuf,
+    a: mpsc::Sender<duplo_rs::Result<duplo_rs::videocandidate::VideoCandidate>>,
+    filepath: &std::path::Path,
     video_id: u32,
     num_videos: u32,
     num_seconds_between_screenshots: u32,
     id_in_files_to_process: u32,
+    config: duplo_rs::hashconfig::HashConfig,
 ) {
-    let video = duplo_rs::files::process_video(filepath, video_id as usize, num_videos, num_seconds_between_screenshots, id_in_files_to_process);
+    let video = duplo_rs::files::process_video(filepath, video_id as usize, num_videos, num_seconds_between_screenshots, id_in_files_to_process, config);
     // send value
     a.send(video).unwrap();
-    return;
 }
@@ -0,0 +1,239 @@
+//! Package animation finds similar animated images (GIF, APNG and animated WebP).
+//! Hashing only the first frame misses animations that share their middle
+//! frames and reports animations that merely start alike. So every frame is
+//! hashed and an animation becomes a VideoCandidate with one Screenshot per
+//! frame. AnimationStore matches them in memory like VideoStore::query matches
+//! videos: two animations are similar if enough consecutive frames match.
+
+/// MIN_SIMILAR_FRAMES is the default number of consecutive frames that have to
+/// match. Shorter animations have to match with all their frames.
+pub const MIN_SIMILAR_FRAMES: usize = 3;
+
+/// decode_frames decodes all frames of an animated GIF, APNG or WebP file together
+/// with the time in milliseconds each frame is shown. Other files and still images
+/// have a single frame, turned upright according to the EXIF orientation with
+/// apply_orientation.
+pub fn decode_frames(p: &std::path::Path,
+                     apply_orientation: bool) -> crate::Result<Vec<(image::RgbaImage, u32)>> {
+    use image::AnimationDecoder;
+    let reader = || -> crate::Result<std::io::BufReader<std::fs::File>> {
+        Ok(std::io::BufReader::new(std::fs::File::open(p)?))
+    };
+    let frames = match crate::formats::Format::detect(p) {
+        Some(crate::formats::Format::Gif) => image::codecs::gif::GifDecoder::new(reader()?)?.into_frames().collect_frames()?,
+        Some(crate::formats::Format::Png) => {
+            let decoder = image::codecs::png::PngDecoder::new(reader()?)?;
+            if decoder.is_apng()? {
+                decoder.apng()?.into_frames().collect_frames()?
+            } else {
+                Vec::new()
+            }
+        }
+        Some(crate::formats::Format::WebP) => {
+            let decoder = image::codecs::webp::WebPDecoder::new(reader()?)?;
+            if decoder.has_animation() {
+                decoder.into_frames().collect_frames()?
+            } else {
+                Vec::new()
+            }
+        }
+        _ => Vec::new(),
+    };
+    if frames.is_empty() {
+        let (img, _) = crate::files::open_image(p, apply_orientation)?;
+        return Ok(vec![(img.into(), 0)]);
+    }
+    Ok(frames
+        .into_iter()
+        .map(|frame| {
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            (frame.into_buffer(), numer / denom.max(1))
+        })
+        .collect())
+}
+
+/// create_animation hashes the frames and returns them as a VideoCandidate with
+/// the given ID and index. The timecodes of the screenshots and the runtime are in
+/// milliseconds, the screenshot IDs start with 1.
+pub fn create_animation(id: &str, index: usize, frames: &[(image::RgbaImage, u32)],
+                        config: crate::hashconfig::HashConfig) -> crate::Result<crate::videocandidate::VideoCandidate> {
+    let mut animation = crate::videocandidate::VideoCandidate::from(id, index);
+    let mut timecode = 0;
+    for (position, (frame, delay)) in frames.iter().enumerate() {
+        let (hash, _) = crate::hash::create_hash(frame, config)?;
+        animation.screenshots.push(crate::videocandidate::Screenshot::from(id, index, position + 1, timecode, &hash));
+        timecode += delay;
+    }
+    if let Some((frame, _)) = frames.first() {
+        animation.width = frame.width();
+        animation.height = frame.height();
+    }
+    animation.runtime = timecode;
+    if timecode > 0 {
+        animation.framerate = frames.len() as f32 * 1000.0 / timecode as f32;
+    }
+    Ok(animation)
+}
+
+/// AnimationStore holds the frames of animations in a Store and finds animations
+/// with a sequence of similar frames.
+///
+/// frames:             the hashes of all frames. The ID of a frame is the ID of the
+///                     animation and the screenshot ID, separated by '#'.
+/// animations:         the animations by their ID, without the hashes of the frames.
+///                     Their index is the number they were added as, starting with 1.
+/// min_similar_frames: number of consecutive frames that have to match.
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct AnimationStore {
+    frames: crate::store::Store,
+    animations: std::collections::BTreeMap<String, crate::videocandidate::VideoCandidate>,
+    num_added: u32,
+    pub min_similar_frames: usize,
+}
+
+impl Default for AnimationStore {
+    fn default() -> AnimationStore {
+        AnimationStore {
+            frames: crate::store::Store::default(),
+            animations: std::collections::BTreeMap::new(),
+            num_added: 0,
+            min_similar_frames: MIN_SIMILAR_FRAMES,
+        }
+    }
+}
+
+impl AnimationStore {
+    pub fn new(sensitivity: crate::sensitivity::Sensitivity, config: crate::hashconfig::HashConfig) -> Self {
+        AnimationStore {
+            frames: crate::store::Store::new(sensitivity, config),
+            ..Default::default()
+        }
+    }
+
+    pub fn has(&self, id: &str) -> bool {
+        self.animations.contains_key(id)
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        self.animations.keys().cloned().collect()
+    }
+
+    /// size returns the number of animations in the store.
+    pub fn size(&self) -> usize {
+        self.animations.len()
+    }
+
+    /// add adds all frames of the animation under its ID. If the ID is already in
+    /// the store, it is not added again. Frames hashed with another config than the
+    /// store's are rejected. If a frame cannot be added, the frames added before are
+    /// removed again and the animation is not added.
+    pub fn add(&mut self, animation: &crate::videocandidate::VideoCandidate) -> crate::Result<()> {
+        for screenshot in animation.screenshots.iter() {
+            self.frames.config().check(&screenshot.hash.config)?;
+        }
+        if self.has(&animation.id) {
+            return Ok(());
+        }
+        for (added, screenshot) in animation.screenshots.iter().enumerate() {
+            if let Err(error) = self.frames.add(&frame_id(&animation.id, screenshot.screenshot_id), &screenshot.hash) {
+                // frames without their animation would match an animation that does not exist
+                for screenshot in animation.screenshots[..added].iter() {
+                    self.frames.delete(&frame_id(&animation.id, screenshot.screenshot_id))?;
+                }
+                return Err(error);
+            }
+        }
+        self.num_added += 1;
+        let mut stored = animation.clone();
+        stored.index = self.num_added;
+        for screenshot in stored.screenshots.iter_mut() {
+            screenshot.video_id = stored.index;
+            screenshot.hash = crate::hash::Hash::new();
+        }
+        self.animations.insert(animation.id.clone(), stored);
+        Ok(())
+    }
+
+    /// delete removes the animation with all its frames. If the ID could not be
+    /// found, nothing happens.
+    pub fn delete(&mut self, id: &str) -> crate::Result<()> {
+        if let Some(animation) = self.animations.remove(id) {
+            for screenshot in animation.screenshots.iter() {
+                self.frames.delete(&frame_id(id, screenshot.screenshot_id))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// query returns the animations that share a sequence of at least
+    /// min_similar_frames consecutive similar frames with the given one, or all of
+    /// its frames if one of both is shorter. The matches point to the first frame
+    /// of the longest sequence; the longer it is compared to the longer of both
+    /// animations, the better the score. The animation itself is never part of the
+    /// result.
+    pub fn query(&self, animation: &crate::videocandidate::VideoCandidate) -> crate::videomatches::VideoMatches {
+        // (ID, offset of the frame numbers) -> first query frame, length and first match
+        let mut runs: std::collections::BTreeMap<(String, i64), (usize, usize, crate::videomatches::VideoMatch)> =
+            std::collections::BTreeMap::new();
+        let mut best: std::collections::BTreeMap<String, (usize, crate::videomatches::VideoMatch)> =
+            std::collections::BTreeMap::new();
+        for (position, screenshot) in animation.screenshots.iter().enumerate() {
+            for m in self.frames.query(&screenshot.hash).m {
+                let Some((id, screenshot_id)) = split_frame_id(&m.id) else {
+                    continue;
+                };
+                if id == animation.id {
+                    continue;
+                }
+                let offset = screenshot_id as i64 - position as i64;
+                let run = runs.entry((id.to_string(), offset)).or_insert_with(|| {
+                    let mut first = crate::videomatches::VideoMatch::from(id, m.score, m.ratio_diff,
+                                                                         m.dhash_distance, m.histogram_distance);
+                    first.screenshot_id = screenshot_id;
+                    (position, 0, first)
+                });
+                if run.0 + run.1 != position {
+                    // the sequence was broken, start a new one
+                    run.0 = position;
+                    run.1 = 0;
+                    run.2.screenshot_id = screenshot_id;
+                    run.2.score = m.score;
+                    run.2.ratio_diff = m.ratio_diff;
+                    run.2.dhash_distance = m.dhash_distance;
+                    run.2.histogram_distance = m.histogram_distance;
+                }
+                run.1 += 1;
+                if best.get(id).is_none_or(|(length, _)| run.1 > *length) {
+                    best.insert(id.to_string(), (run.1, run.2.clone()));
+                }
+            }
+        }
+        let mut ms = crate::videomatches::VideoMatches::new();
+        for (id, (length, mut m)) in best {
+            let matched = &self.animations[&id];
+            let required = self.min_similar_frames.min(matched.screenshots.len()).min(animation.screenshots.len()).max(1);
+            if length < required {
+                continue;
+            }
+            m.video_id = matched.index;
+            if let Some(first) = matched.screenshots.iter().find(|first| first.screenshot_id == m.screenshot_id) {
+                m.timecode = first.timecode;
+            }
+            m.score = -60.0 - 100.0 * length as f64 / matched.screenshots.len().max(animation.screenshots.len()) as f64;
+            ms.m.push(m);
+        }
+        ms.sort();
+        ms
+    }
+}
+
+/// frame_id returns the ID of a frame in the store of the frames.
+fn frame_id(id: &str, screenshot_id: u32) -> String {
+    format!("{}#{}", id, screenshot_id)
+}
+
+/// split_frame_id returns the ID of the animation and the screenshot ID of a frame.
+fn split_frame_id(frame: &str) -> Option<(&str, u32)> {
+    let (id, screenshot_id) = frame.rsplit_once('#')?;
+    Some((id, screenshot_id.parse().ok()?))
+}
@@ -1,21 +1,37 @@
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Candidate {
     pub id: String,
     // scaleCoef is the scaling function coefficient, the coefficients at index
-	// (0,0) of the Haar matrix.
+	// (0,0) of the Haar matrix. It is empty if it is not known: for images of
+	// stores of format version 1 and for the tiles of a RegionIndex.
     pub scale_coeff: crate::haar::Coef,
-    // ratio is image width / image height.
+    // ratio is image width / image height, NAN if unknown (see STORE_FORMAT_VERSION).
 	pub ratio: f64,
     // The dHash bit vector (see Hash for more information).
 	pub dhash: Vec<u64>,
     pub histogram: u64,
-    histo_max: Vec<f32>,
+    pub(crate) histo_max: Vec<f32>,
+    // signature are the sorted index lists (positions and signs of the largest
+    // coefficients) the image was added to. It is not encoded but restored from
+    // the index lists of the store.
+    pub signature: Vec<u32>,
+    // perceptual are the perceptual hashes of the image (see Hash). They are not
+    // encoded with the candidate but stored separately by the store.
+    pub perceptual: Vec<u64>,
+    // orientation is the EXIF orientation of the image file (see Hash). It is not
+    // encoded with the candidate but stored separately by the store.
+    pub orientation: u8,
+    // format is the format of the image file (see Hash). It is not encoded with
+    // the candidate but stored separately by the store.
+    pub format: Option<crate::formats::Format>,
 }
 
 impl Candidate {
     pub fn new() -> Self {
-        let v: Candidate = Candidate{..Default::default()};
-        v
+        Candidate {
+            ..Default::default()
+        }
     }
     pub fn from(id: &str, h: &crate::hash::Hash) -> Self {
         let mut v: Candidate = Candidate{..Default::default()};
@@ -24,9 +40,31 @@ impl Candidate {
         v.dhash = h.dhash.clone();
         v.histogram = h.histogram;
         v.histo_max = h.histo_max.clone();
+        v.signature = crate::store::hash_locations(h);
+        v.signature.sort_unstable();
+        v.perceptual = h.perceptual.clone();
+        v.orientation = h.orientation;
+        v.format = h.format;
+        v.scale_coeff = h.matrix.coefs.first().cloned().unwrap_or_default();
         v
     }
 
+    /// hash returns a Hash with the metrics of the candidate, for comparing other
+    /// candidates with it. It only has the scaling function coefficient, use the
+    /// signature instead of the other coefficients.
+    pub(crate) fn hash(&self) -> crate::hash::Hash {
+        let mut hash = crate::hash::Hash::new();
+        hash.matrix.coefs = vec![self.scale_coeff.clone()];
+        hash.ratio = self.ratio;
+        hash.dhash = self.dhash.clone();
+        hash.histogram = self.histogram;
+        hash.histo_max = self.histo_max.clone();
+        hash.perceptual = self.perceptual.clone();
+        hash.orientation = self.orientation;
+        hash.format = self.format;
+        hash
+    }
+
     pub fn encode(&self, to: &mut Vec<u8>) {
         crate::marshal::store_string(&self.id, to);
         crate::marshal::store_f64(self.ratio, to);
@@ -35,12 +73,13 @@ impl Candidate {
         crate::marshal::store_vec_f32(&self.histo_max, to);
     }
 
-    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) {
-        self.id = crate::marshal::restore_string(from);
-        self.ratio = crate::marshal::restore_f64(from);
-        self.dhash.extend(crate::marshal::restore_vec_u64(from));
-        self.histogram = crate::marshal::restore_u64(from);
-        self.histo_max.extend(crate::marshal::restore_vec_f32(from));
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        self.id = crate::marshal::restore_string(from)?;
+        self.ratio = crate::marshal::restore_f64(from)?;
+        self.dhash.extend(crate::marshal::restore_vec_u64(from)?);
+        self.histogram = crate::marshal::restore_u64(from)?;
+        self.histo_max.extend(crate::marshal::restore_vec_f32(from)?);
+        Ok(())
     }
 }
 
@@ -53,6 +92,10 @@ impl Default for Candidate {
             dhash: Vec::new(),
             histogram: 0,
             histo_max: Vec::new(),
+            signature: Vec::new(),
+            perceptual: Vec::new(),
+            orientation: 1,
+            format: None,
        }
     }
 }
@@ -0,0 +1,107 @@
+//! Package dhashindex provides multi-index hashing over the 128 bit dHash of the
+//! candidates of a Store, for finding near-identical images (re-encodes and
+//! resizes) without scanning the Haar index lists. The dHash is split into
+//! NUM_SUBSTRINGS substrings of 16 bits, each with its own table. Two hashes
+//! within a Hamming distance of r have at least one substring within r /
+//! NUM_SUBSTRINGS (pigeonhole principle), so a query only looks at the table
+//! entries of the substrings of the query hash and their close neighbours.
+
+/// NUM_SUBSTRINGS is the number of 16 bit substrings of a dHash.
+pub const NUM_SUBSTRINGS: usize = 8;
+/// MAX_PROBE_RADIUS is the largest distance of the substrings that is probed in
+/// the tables. Larger query radii probe so many entries that scanning all
+/// candidates is faster.
+pub const MAX_PROBE_RADIUS: u32 = 2;
+
+/// DHashIndex maps the substrings of the dHash of every candidate to the indices
+/// of the candidates in the store.
+///
+/// tables: one table per substring, from the value of the substring to the
+///         sorted candidate indices.
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct DHashIndex {
+    tables: Vec<std::collections::BTreeMap<u16, Vec<u32>>>,
+}
+
+impl Default for DHashIndex {
+    fn default() -> DHashIndex {
+        DHashIndex {
+            tables: vec![std::collections::BTreeMap::new(); NUM_SUBSTRINGS],
+        }
+    }
+}
+
+impl DHashIndex {
+    pub fn new() -> Self {
+        DHashIndex {
+            ..Default::default()
+        }
+    }
+
+    /// from builds the index of the candidates, without the deleted ones.
+    pub fn from(candidates: &[crate::candidate::Candidate], deleted: &[bool]) -> Self {
+        let mut v = DHashIndex::new();
+        for (index, candidate) in candidates.iter().enumerate() {
+            if !deleted.get(index).copied().unwrap_or(true) {
+                v.add(index as u32, &candidate.dhash);
+            }
+        }
+        v
+    }
+
+    /// add adds the dHash of the candidate with the given index. Indices have to be
+    /// added in ascending order.
+    pub fn add(&mut self, index: u32, dhash: &[u64]) {
+        for (table, substring) in self.tables.iter_mut().zip(substrings(dhash)) {
+            table.entry(substring).or_default().push(index);
+        }
+    }
+
+    /// lookup returns the sorted indices of all candidates that may be within the
+    /// given Hamming distance of the dHash, or None if the distance is too large to
+    /// probe the tables. The distances have to be checked by the caller, deleted
+    /// candidates are not removed.
+    pub fn lookup(&self, dhash: &[u64], max_distance: i64) -> Option<Vec<u32>> {
+        let radius = u32::try_from(max_distance.max(0)).ok()? / NUM_SUBSTRINGS as u32;
+        if radius > MAX_PROBE_RADIUS {
+            return None;
+        }
+        let mut found = Vec::new();
+        for (table, substring) in self.tables.iter().zip(substrings(dhash)) {
+            for probe in neighbours(substring, radius) {
+                if let Some(indices) = table.get(&probe) {
+                    found.extend_from_slice(indices);
+                }
+            }
+        }
+        found.sort_unstable();
+        found.dedup();
+        Some(found)
+    }
+}
+
+/// substrings splits a dHash into its NUM_SUBSTRINGS 16 bit substrings.
+fn substrings(dhash: &[u64]) -> impl Iterator<Item = u16> + '_ {
+    (0..NUM_SUBSTRINGS).map(|n| {
+        let word = dhash.get(n / 4).copied().unwrap_or(0);
+        (word >> (16 * (n % 4))) as u16
+    })
+}
+
+/// neighbours returns all values within the given Hamming distance of value.
+fn neighbours(value: u16, radius: u32) -> Vec<u16> {
+    let mut v = vec![value];
+    let mut previous = vec![(value, 0_u32)];
+    for _ in 0..radius {
+        let mut next = Vec::new();
+        for (probe, first_bit) in previous {
+            // flip only bits above the ones flipped before, so every value comes once
+            for bit in first_bit..16 {
+                next.push((probe ^ (1 << bit), bit + 1));
+            }
+        }
+        v.extend(next.iter().map(|(probe, _)| *probe));
+        previous = next;
+    }
+    v
+}
@@ -0,0 +1,62 @@
+//! Package dihedral provides the 8 rotations and reflections of an image (the
+//! dihedral group of the square). The Haar coefficients, dHash and perceptual
+//! hashes depend on the orientation of an image, so a rotated scan or a mirrored
+//! repost is only found if the query probes the transformed versions of the image.
+
+/// Dihedral is one of the rotations and reflections of an image.
+///
+/// Identity:       the image as it is.
+/// Rotate90:       rotated by 90 degrees clockwise.
+/// Rotate180:      rotated by 180 degrees.
+/// Rotate270:      rotated by 270 degrees clockwise.
+/// FlipHorizontal: mirrored at the vertical axis (left and right swapped).
+/// FlipVertical:   mirrored at the horizontal axis (top and bottom swapped).
+/// Transpose:      mirrored at the diagonal from the top left corner.
+/// Transverse:     mirrored at the diagonal from the top right corner.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Dihedral {
+    #[default]
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    FlipHorizontal,
+    FlipVertical,
+    Transpose,
+    Transverse,
+}
+
+/// DIHEDRALS are all transforms, starting with the identity.
+pub const DIHEDRALS: [Dihedral; 8] = [
+    Dihedral::Identity,
+    Dihedral::Rotate90,
+    Dihedral::Rotate180,
+    Dihedral::Rotate270,
+    Dihedral::FlipHorizontal,
+    Dihedral::FlipVertical,
+    Dihedral::Transpose,
+    Dihedral::Transverse,
+];
+
+impl Dihedral {
+    /// swaps_sides tells whether the transform swaps width and height.
+    pub fn swaps_sides(&self) -> bool {
+        matches!(self, Dihedral::Rotate90 | Dihedral::Rotate270 | Dihedral::Transpose | Dihedral::Transverse)
+    }
+
+    /// apply returns the transformed image.
+    pub fn apply(&self, img: &image::RgbaImage) -> image::RgbaImage {
+        use image::imageops;
+        match self {
+            Dihedral::Identity => img.clone(),
+            Dihedral::Rotate90 => imageops::rotate90(img),
+            Dihedral::Rotate180 => imageops::rotate180(img),
+            Dihedral::Rotate270 => imageops::rotate270(img),
+            Dihedral::FlipHorizontal => imageops::flip_horizontal(img),
+            Dihedral::FlipVertical => imageops::flip_vertical(img),
+            Dihedral::Transpose => imageops::flip_horizontal(&imageops::rotate90(img)),
+            Dihedral::Transverse => imageops::flip_horizontal(&imageops::rotate270(img)),
+        }
+    }
+}
@@ -0,0 +1,205 @@
+/// DuplicatePair is a pair of similar images inside a DuplicateGroup. The ID of
+/// the second image and the metrics of the pair are in m. id is the smaller of the
+/// two IDs.
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DuplicatePair {
+    pub id: String,
+    pub m: crate::matches::Match,
+}
+
+/// DuplicateGroup is a set of images that are similar to each other, directly or
+/// through a chain of similar images (A is similar to B, B is similar to C).
+///
+/// ids:   the IDs of all images in the group, sorted.
+/// pairs: every pair of directly similar images in the group, sorted by their IDs.
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DuplicateGroup {
+    pub ids: Vec<String>,
+    pub pairs: Vec<DuplicatePair>,
+}
+
+impl DuplicateGroup {
+    pub fn new() -> Self {
+        DuplicateGroup {
+            ..Default::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+/// UnionFind keeps track of disjoint sets of candidate indices.
+struct UnionFind {
+    parent: Vec<usize>,
+    rank: Vec<u8>,
+}
+
+impl UnionFind {
+    fn new(size: usize) -> Self {
+        UnionFind {
+            parent: (0..size).collect(),
+            rank: vec![0; size],
+        }
+    }
+
+    fn find(&mut self, index: usize) -> usize {
+        let mut root = index;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        // compress the path
+        let mut index = index;
+        while self.parent[index] != root {
+            let next = self.parent[index];
+            self.parent[index] = root;
+            index = next;
+        }
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let a = self.find(a);
+        let b = self.find(b);
+        if a == b {
+            return;
+        }
+        match self.rank[a].cmp(&self.rank[b]) {
+            std::cmp::Ordering::Less => self.parent[a] = b,
+            std::cmp::Ordering::Greater => self.parent[b] = a,
+            std::cmp::Ordering::Equal => {
+                self.parent[b] = a;
+                self.rank[a] += 1;
+            }
+        }
+    }
+}
+
+/// similar_pairs compares the candidates of the given range with all candidates
+/// after them and returns the pairs that pass the threshold and the filters.
+/// The score of a pair is the score a query with either image would give the other.
+fn similar_pairs(
+    store: &crate::store::Store,
+    range: std::ops::Range<usize>,
+    threshold: f64,
+    options: &crate::queryoptions::QueryOptions,
+    excluded: Option<usize>,
+) -> Vec<(usize, usize, crate::matches::Match)> {
+    let initial = crate::store::initial_score();
+    let scale = store.config().scale;
+    let classic = store.scoring().is_classic();
+    let mut shared = vec![0.0_f64; store.candidates.len()];
+    let mut touched = Vec::new();
+    let mut pairs = Vec::new();
+    for first in range {
+        if Some(first) == excluded || store.is_deleted(first) {
+            continue;
+        }
+        for &location in store.candidates[first].signature.iter() {
+            let weight = crate::store::location_weight(location, scale);
+            let list = &store.indices[location as usize];
+            // the index lists are sorted, only look at the candidates after this one
+            let start = list.partition_point(|index| (*index as usize) <= first);
+            for &second in list[start..].iter() {
+                let second = second as usize;
+                if shared[second] == 0.0 {
+                    touched.push(second);
+                }
+                shared[second] += weight;
+            }
+        }
+        touched.sort_unstable();
+        for &second in touched.iter() {
+            let score = initial - shared[second];
+            shared[second] = 0.0;
+            // the classic score is final, skip the pair before making a hash for it
+            if (classic && score >= threshold) || Some(second) == excluded || store.is_deleted(second) {
+                continue;
+            }
+            // the image with the smaller ID is the query, so the pairs do not depend on
+            // the order the images were added in
+            let (query, candidate) = if store.candidates[first].id <= store.candidates[second].id {
+                (&store.candidates[first], &store.candidates[second])
+            } else {
+                (&store.candidates[second], &store.candidates[first])
+            };
+            let mut hash = query.hash();
+            hash.config = store.config();
+            let score = store.scoring().adjust(score, &candidate.scale_coeff, &candidate.histo_max, &hash);
+            if score >= threshold {
+                continue;
+            }
+            let mut m = crate::store::create_match(&candidate.id, candidate.ratio, &candidate.dhash,
+                                                   candidate.histogram, score, &hash);
+            m.perceptual_distances = store.config().perceptual.distances(&candidate.perceptual, &query.perceptual);
+            if options.accepts(&m) && store.sensitivity().accepts(m.dhash_distance, m.histogram_distance) {
+                pairs.push((first, second, m));
+            }
+        }
+        touched.clear();
+    }
+    pairs
+}
+
+/// find_duplicate_groups compares every image of the store with all others and
+/// merges similar images transitively into groups. See Store::find_duplicate_groups.
+pub(crate) fn find_duplicate_groups(
+    store: &crate::store::Store,
+    options: &crate::queryoptions::QueryOptions,
+) -> Vec<DuplicateGroup> {
+    let size = store.candidates.len();
+    let threshold = options.score_threshold.unwrap_or(store.sensitivity().score_threshold);
+    let excluded = options.exclude_id.as_ref().and_then(|id| store.ids.get(id).copied());
+    let num_threads = options
+        .num_threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+        .clamp(1, size.max(1));
+    let chunk = size.div_ceil(num_threads).max(1);
+    let pairs: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..size)
+            .step_by(chunk)
+            .map(|start| {
+                let range = start..(start + chunk).min(size);
+                scope.spawn(move || similar_pairs(store, range, threshold, options, excluded))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+            .collect()
+    });
+
+    let mut sets = UnionFind::new(size);
+    for (first, second, _) in pairs.iter() {
+        sets.union(*first, *second);
+    }
+    let mut groups: std::collections::BTreeMap<usize, DuplicateGroup> = std::collections::BTreeMap::new();
+    for (first, second, m) in pairs {
+        let group = groups.entry(sets.find(first)).or_default();
+        let (first, second) = (&store.candidates[first].id, &store.candidates[second].id);
+        group.ids.push(first.clone());
+        group.ids.push(second.clone());
+        group.pairs.push(DuplicatePair {
+            id: if first == &m.id { second.clone() } else { first.clone() },
+            m,
+        });
+    }
+    let mut groups: Vec<DuplicateGroup> = groups.into_values().collect();
+    for group in groups.iter_mut() {
+        group.ids.sort();
+        group.ids.dedup();
+        group.pairs.sort_by(|a, b| (&a.id, &a.m.id).cmp(&(&b.id, &b.m.id)));
+    }
+    groups.sort_by(|a, b| a.ids.cmp(&b.ids));
+    if let Some(max_results) = options.max_results {
+        groups.truncate(max_results);
+    }
+    groups
+}
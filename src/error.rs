@@ -0,0 +1,72 @@
+/// Error is the error type of all fallible operations of the library.
+///
+/// Io:      reading or writing a file failed.
+/// Decode:  a binary stream (store file, database blob) is truncated or malformed.
+/// Checksum: the checksum of a store file does not match its content.
+/// Version: a store file was written in a format version this library does not know.
+/// Image:   an image could not be opened or decoded.
+/// Sqlite:  the video database returned an error.
+/// Ffmpeg:  ffmpeg could not be executed or did not deliver the expected output.
+/// Conflict: two stores cannot be merged, e.g. because an ID is in both of them.
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Decode(String),
+    Checksum { expected: u32, found: u32 },
+    Version(u32),
+    Image(image::ImageError),
+    Sqlite(rusqlite::Error),
+    Ffmpeg(String),
+    Conflict(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "I/O error: {}", error),
+            Error::Decode(message) => write!(f, "decode error: {}", message),
+            Error::Checksum { expected, found } => write!(
+                f,
+                "checksum mismatch: expected {:08x}, found {:08x}",
+                expected, found
+            ),
+            Error::Version(version) => write!(f, "unsupported format version {}", version),
+            Error::Image(error) => write!(f, "image error: {}", error),
+            Error::Sqlite(error) => write!(f, "SQLite error: {}", error),
+            Error::Ffmpeg(message) => write!(f, "ffmpeg error: {}", message),
+            Error::Conflict(message) => write!(f, "conflict: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            Error::Image(error) => Some(error),
+            Error::Sqlite(error) => Some(error),
+            Error::Decode(_) | Error::Checksum { .. } | Error::Version(_) | Error::Ffmpeg(_)
+            | Error::Conflict(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(error: image::ImageError) -> Self {
+        Error::Image(error)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(error: rusqlite::Error) -> Self {
+        Error::Sqlite(error)
+    }
+}
@@ -23,174 +23,176 @@ pub fn osstring_to_string(input: &OsStr) -> String {
     s.to_string()
 }
 
-pub fn walk_dir_images(dirpath: &str) -> Vec<PathBuf> {
+/// lists the files directly inside dirpath for which is_wanted is true.
+fn walk_dir(dirpath: &str, is_wanted: fn(&std::path::Path) -> bool) -> crate::Result<Vec<PathBuf>> {
     let mut p = Vec::new();
-    let ret = fs::read_dir(dirpath);
-    if ret.is_err() {
-        return p;
-    }
-    let dir = ret.unwrap();
-    for f in dir {
-        if f.is_ok() {
-            let file = f.unwrap();
-            let filepathopt = file.path().canonicalize();
-            if filepathopt.is_err() {
+    for file in fs::read_dir(dirpath)?.flatten() {
+        let filepath = match file.path().canonicalize() {
+            Ok(filepath) => filepath,
+            Err(error) => {
                 log::error!(
-                    "Failed to make an absolute path for {}",
-                    file.path().display()
+                    "Failed to make an absolute path for {}: {}",
+                    file.path().display(),
+                    error
                 );
-                return p;
-            }
-            let filepath = filepathopt.unwrap();
-            if filepath.is_file() {
-                let e = filepath.extension();
-                if e.is_some() {
-                    let ext = e.unwrap();
-                    let extstring = osstring_to_string(ext).to_ascii_lowercase();
-                    if extstring == "png"
-                        || extstring == "jpg"
-                        || extstring == "jpeg"
-                        || extstring == "bmp"
-                        || extstring == "gif"
-                        || extstring == "webp"
-                        || extstring == "tif"
-                        || extstring == "tiff"
-                    {
-                        p.push(filepath);
-                    }
-                }
+                continue;
             }
+        };
+        if filepath.is_file() && is_wanted(&filepath) {
+            p.push(filepath);
         }
     }
-    p
+    Ok(p)
 }
 
-pub fn walk_tree_images(dirpath: &str) -> Vec<PathBuf> {
+/// lists the files in the directory tree starting at dirpath for which is_wanted is true.
+fn walk_tree(dirpath: &str, is_wanted: fn(&std::path::Path) -> bool) -> crate::Result<Vec<PathBuf>> {
     let mut p = Vec::new();
+    // fail early if the start directory is not accessible
+    fs::read_dir(dirpath)?;
     for entry in WalkDir::new(dirpath)
         .follow_links(true)
         .into_iter()
         .filter_map(|e| e.ok())
     {
-        let filepathopt = entry.path().canonicalize();
-        if filepathopt.is_err() {
-            log::error!(
-                "Failed to make an absolute path for {}",
-                entry.path().display()
-            );
-            return p;
-        }
-        let filepath = filepathopt.unwrap();
-        if filepath.is_file() {
-            let e = filepath.extension();
-            if e.is_some() {
-                let ext = e.unwrap();
-                let extstring = osstring_to_string(ext).to_ascii_lowercase();
-                if extstring == "png"
-                    || extstring == "jpg"
-                    || extstring == "jpeg"
-                    || extstring == "bmp"
-                    || extstring == "gif"
-                    || extstring == "webp"
-                    || extstring == "tif"
-                    || extstring == "tiff"
-                {
-                    p.push(filepath.into());
-                }
-            }
-        }
-    }
-    p
-}
-
-pub fn walk_dir_videos(dirpath: &str) -> Vec<PathBuf> {
-    let mut p = Vec::new();
-    let ret = fs::read_dir(dirpath);
-    if ret.is_err() {
-        return p;
-    }
-    let dir = ret.unwrap();
-    for f in dir {
-        if f.is_ok() {
-            let file = f.unwrap();
-            let filepathopt = file.path().canonicalize();
-            if filepathopt.is_err() {
+        let filepath = match entry.path().canonicalize() {
+            Ok(filepath) => filepath,
+            Err(error) => {
                 log::error!(
-                    "Failed to make an absolute path for {}",
-                    file.path().display()
+                    "Failed to make an absolute path for {}: {}",
+                    entry.path().display(),
+                    error
                 );
-                return p;
-            }
-            let filepath = filepathopt.unwrap();
-            if filepath.is_file() {
-                let e = filepath.extension();
-                if e.is_some() {
-                    let ext = e.unwrap();
-                    let extstring = osstring_to_string(ext).to_ascii_lowercase();
-                    if extstring == "mkv"
-                        || extstring == "mp4"
-                        || extstring == "avi"
-                        || extstring == "mov"
-                        || extstring == "webm"
-                    {
-                        p.push(filepath);
-                    }
-                }
+                continue;
             }
+        };
+        if filepath.is_file() && is_wanted(&filepath) {
+            p.push(filepath);
         }
     }
-    p
+    Ok(p)
 }
 
-pub fn walk_tree_videos(dirpath: &str) -> Vec<PathBuf> {
-    let mut p = Vec::new();
-    for entry in WalkDir::new(dirpath)
-        .follow_links(true)
+/// tells whether the content of the file is an image in a supported format.
+fn is_image(filepath: &std::path::Path) -> bool {
+    crate::formats::Format::detect(filepath).is_some()
+}
+
+/// tells whether the content of the file is a video in a known container format.
+fn is_video(filepath: &std::path::Path) -> bool {
+    crate::formats::VideoFormat::detect(filepath).is_some()
+}
+
+/// lists the images directly inside dirpath, recognized by their content.
+pub fn walk_dir_images(dirpath: &str) -> crate::Result<Vec<PathBuf>> {
+    walk_dir(dirpath, is_image)
+}
+
+/// lists the images in the directory tree starting at dirpath, recognized by their content.
+pub fn walk_tree_images(dirpath: &str) -> crate::Result<Vec<PathBuf>> {
+    walk_tree(dirpath, is_image)
+}
+
+/// lists the videos directly inside dirpath, recognized by their content.
+pub fn walk_dir_videos(dirpath: &str) -> crate::Result<Vec<PathBuf>> {
+    walk_dir(dirpath, is_video)
+}
+
+/// lists the videos in the directory tree starting at dirpath, recognized by their content.
+pub fn walk_tree_videos(dirpath: &str) -> crate::Result<Vec<PathBuf>> {
+    walk_tree(dirpath, is_video)
+}
+
+/// hashes the images directly inside dirpath like process_image. With a cache, files
+/// that did not change since they were cached are not decoded again and the hashes
+/// of the others are put into the cache. Images that cannot be hashed come with
+/// their error.
+pub fn hash_dir_images(dirpath: &str, config: crate::hashconfig::HashConfig,
+                       cache: Option<&mut crate::hashcache::HashCache>)
+                       -> crate::Result<Vec<(PathBuf, crate::Result<crate::hash::Hash>)>> {
+    Ok(hash_images(walk_dir_images(dirpath)?, config, cache))
+}
+
+/// hashes the images in the directory tree starting at dirpath like hash_dir_images.
+pub fn hash_tree_images(dirpath: &str, config: crate::hashconfig::HashConfig,
+                        cache: Option<&mut crate::hashcache::HashCache>)
+                        -> crate::Result<Vec<(PathBuf, crate::Result<crate::hash::Hash>)>> {
+    Ok(hash_images(walk_tree_images(dirpath)?, config, cache))
+}
+
+/// hashes the images at the given paths, unchanged files from the cache.
+fn hash_images(paths: Vec<PathBuf>, config: crate::hashconfig::HashConfig,
+               mut cache: Option<&mut crate::hashcache::HashCache>)
+               -> Vec<(PathBuf, crate::Result<crate::hash::Hash>)> {
+    paths
         .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let filepathopt = entry.path().canonicalize();
-        if filepathopt.is_err() {
-            log::error!(
-                "Failed to make an absolute path for {}",
-                entry.path().display()
-            );
-            return p;
-        }
-        let filepath = filepathopt.unwrap();
-        if filepath.is_file() {
-            let e = filepath.extension();
-            if e.is_some() {
-                let ext = e.unwrap();
-                let extstring = osstring_to_string(ext).to_ascii_lowercase();
-                if extstring == "mkv"
-                    || extstring == "mp4"
-                    || extstring == "avi"
-                    || extstring == "mov"
-                    || extstring == "webm"
-                {
-                    p.push(filepath.into());
-                }
-            }
-        }
-    }
-    p
+        .map(|path| {
+            let hash = match cache.as_deref_mut() {
+                Some(cache) => cache.process_image(&path, config),
+                None => process_image(&path, config),
+            };
+            (path, hash)
+        })
+        .collect()
 }
 
 /// reads an image, creates a hash and compares it with the existing hashes.
 /// Delivers existing Matches and the hash back to the calling program.
-pub fn process_image(p: &PathBuf) -> crate::hash::Hash {
-    let res = image::ImageReader::open(p);
-    if res.is_ok() {
-        let dynimg = res.unwrap().decode();
-        if dynimg.is_err() {
-            return crate::hash::Hash::new();
+/// The image is turned upright according to its EXIF orientation first, unless the
+/// config says otherwise. The orientation and the format of the file are kept in
+/// the hash.
+pub fn process_image(p: &std::path::Path, config: crate::hashconfig::HashConfig) -> crate::Result<crate::hash::Hash> {
+    let format = crate::formats::Format::detect(p);
+    let (img, orientation) = decode_image(p, format, config.apply_orientation)?;
+    let (mut hash, _smallimg) = crate::hash::create_hash(&img.into(), config)?;
+    hash.orientation = orientation.to_exif();
+    hash.format = format;
+    Ok(hash)
+}
+
+/// process_animation reads all frames of an animated GIF, APNG or WebP file and
+/// hashes them as a sequence for an AnimationStore. The path is the ID. Still
+/// images become an animation with a single frame.
+pub fn process_animation(p: &std::path::Path,
+                         config: crate::hashconfig::HashConfig) -> crate::Result<crate::videocandidate::VideoCandidate> {
+    let frames = crate::animation::decode_frames(p, config.apply_orientation)?;
+    crate::animation::create_animation(&osstring_to_string(p.as_os_str()), 0, &frames, config)
+}
+
+/// open_image decodes the image file and returns it together with its EXIF
+/// orientation. With apply_orientation, the image is turned upright. The format is
+/// detected from the content of the file, of camera RAW files the embedded JPEG
+/// preview is decoded.
+pub fn open_image(p: &std::path::Path,
+                  apply_orientation: bool) -> crate::Result<(image::DynamicImage, image::metadata::Orientation)> {
+    decode_image(p, crate::formats::Format::detect(p), apply_orientation)
+}
+
+/// decode_image is open_image with the format already detected.
+fn decode_image(p: &std::path::Path, format: Option<crate::formats::Format>,
+                apply_orientation: bool) -> crate::Result<(image::DynamicImage, image::metadata::Orientation)> {
+    use image::ImageDecoder;
+    if format == Some(crate::formats::Format::Raw) {
+        let bytes = fs::read(p)?;
+        let (preview, orientation) = crate::formats::raw_preview(&bytes)?;
+        let orientation = image::metadata::Orientation::from_exif(orientation).unwrap_or(image::metadata::Orientation::NoTransforms);
+        let mut img = image::load_from_memory_with_format(preview, image::ImageFormat::Jpeg)?;
+        if apply_orientation {
+            img.apply_orientation(orientation);
         }
-        let img = dynimg.unwrap();
-        let (hash, _smallimg) = crate::hash::create_hash(&img.into());
-        return hash;
+        return Ok((img, orientation));
+    }
+    let mut reader = image::ImageReader::open(p)?;
+    if let Some(format) = format.and_then(|format| format.image_format()) {
+        reader.set_format(format);
+    }
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut img = image::DynamicImage::from_decoder(decoder)?;
+    if apply_orientation {
+        img.apply_orientation(orientation);
     }
-    crate::hash::Hash::new()
+    Ok((img, orientation))
 }
 
 pub fn find_similar_images(
@@ -198,117 +200,77 @@ pub fn find_similar_images(
     id: &str,
     hash: &crate::hash::Hash,
 ) -> (crate::matches::Matches, String, crate::hash::Hash) {
-    let matches = store.query(&hash);
+    let matches = store.query(hash);
     (matches, id.to_string(), hash.clone())
 }
 
+/// returns the file name of path as a string or an error describing why there is none.
+fn file_name_string(path: &str) -> crate::Result<&str> {
+    let filename = std::path::Path::new(path).file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Path {} does not contain a valid file name!", path),
+        )
+    })?;
+    let filename = filename.to_str().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Path {} does contain illegal characters!", path),
+        )
+    })?;
+    Ok(filename)
+}
+
 /// presents the two files together renamed to have the same prefix
 /// The file containing KEEP is a hard link. You can delete it without removing the original.
 /// The file containing REMOVE is the original file. If you delete it, it will be gone!
 pub fn present_pairs(
-    destination_dir: &std::path::PathBuf,
+    destination_dir: &std::path::Path,
     remove_candidate: &str,
     keep_candidate: &str,
-) {
+) -> crate::Result<()> {
     if !destination_dir.is_dir() {
-        let ret = std::fs::create_dir(destination_dir.as_path());
-        if ret.is_err() {
-            log::error!(
-                "Unable to create the duplicates directory in {}!",
-                destination_dir.display()
-            );
-            std::process::exit(2);
-        }
+        std::fs::create_dir(destination_dir)?;
     }
     let prefix = generate_random_string(5);
-    let removefile_opt = std::path::Path::new(remove_candidate).file_name();
-    let keepfile_opt = std::path::Path::new(remove_candidate).file_name();
-    if removefile_opt.is_none() {
-        log::error!(
-            "Path {} does not contain a valid file name!",
-            remove_candidate
-        );
-        return;
-    }
-    if keepfile_opt.is_none() {
-        log::error!(
-            "Path {} does not contain a valid file name!",
-            keep_candidate
-        );
-        return;
-    }
-    let removefile_opt = removefile_opt.unwrap().to_str();
-    let keepfile_opt = keepfile_opt.unwrap().to_str();
-    if removefile_opt.is_none() {
-        log::error!("Path {} does contain illegal characters!", remove_candidate);
-        return;
-    }
-    if keepfile_opt.is_none() {
-        log::error!("Path {} does contain illegal characters!", keep_candidate);
-        return;
-    }
-    let removefile = removefile_opt.unwrap();
-    let keepfile = keepfile_opt.unwrap();
+    let removefile = file_name_string(remove_candidate)?;
+    let keepfile = file_name_string(keep_candidate)?;
     let remove_path = destination_dir.join(format!("{}_{}_{}", prefix, "REMOVE", removefile));
     let keep_path = destination_dir.join(format!("{}_{}_{}", prefix, "KEEP", keepfile));
-    let ret = std::fs::hard_link(keep_candidate, keep_path.clone());
-    if ret.is_err() {
-        log::error!(
-            "Failed to create a hard link from {} to {}!",
-            keep_candidate,
-            keep_path.display()
-        );
-        return;
-    }
-    let ret = std::fs::rename(remove_candidate, remove_path.clone());
-    if ret.is_err() {
-        log::error!(
-            "Failed to move a file from {} to {}!",
-            remove_candidate,
-            remove_path.display()
-        );
-        return;
-    }
+    std::fs::hard_link(keep_candidate, &keep_path)?;
+    std::fs::rename(remove_candidate, &remove_path)?;
+    Ok(())
 }
 
-fn prepare_video_table(compare: &Vec<crate::videocandidate::VideoCandidate>) -> String {
+fn prepare_video_table(compare: &[crate::videocandidate::VideoCandidate]) -> String {
     let mut v =
         String::from("<table><thead>\n<tr><th>Video</th><th>Info</th></tr>\n</thead><tbody>\n");
-    for i in 0..compare.len() {
-        let filepath = std::path::Path::new(&compare[i].id);
-        let fileformat;
-        let e = filepath.extension();
-        if e.is_some() {
-            let ext = e.unwrap();
-            let extstring = osstring_to_string(ext).to_ascii_lowercase();
-            if extstring == "mkv" || extstring == "webm" {
-                v = format!(
-                    r##"{}<tr><td><video controls width="320" src="{}"</video></td>"##,
-                    v, compare[i].id
-                );
-            } else if extstring == "mp4" || extstring == "mpv" {
-                fileformat = "mp4".to_string();
-                v = format!(
-                    r##"{}<tr><td><video controls width="320"><source src="{}" type="video/{}" />/td>"##,
-                    v, compare[i].id, fileformat
-                );
-            } else {
-                v = format!(
-                    r##"{}<tr><td><video controls width="320" src="{}"</video></td>"##,
-                    v, compare[i].id
-                );
-            }
-            
+    for video in compare.iter() {
+        // candidates of earlier releases have no recorded format
+        let format = video
+            .format
+            .or_else(|| crate::formats::VideoFormat::detect(std::path::Path::new(&video.id)));
+        if let Some(crate::formats::VideoFormat::Mp4) = format {
+            v = format!(
+                r##"{}<tr><td><video controls width="320"><source src="{}" type="{}" />/td>"##,
+                v, video.id, crate::formats::VideoFormat::Mp4.mime_type()
+            );
+        } else {
             v = format!(
-                r##"{}<td><a href="{}">{}</a><p>Resolution: {}x{}</p><p>Duration: {}</p></td></tr>"##,
-                v,
-                compare[i].id,
-                compare[i].id,
-                compare[i].width,
-                compare[i].height,
-                compare[i].runtime
+                r##"{}<tr><td><video controls width="320" src="{}"</video></td>"##,
+                v, video.id
             );
         }
+
+        v = format!(
+            r##"{}<td><a href="{}">{}</a><p>Resolution: {}x{}</p><p>Duration: {}</p></td></tr>"##,
+            v,
+            video.id,
+            video.id,
+            video.width,
+            video.height,
+            video.runtime
+        );
         v = format!("{}\n", v);
     }
     v = format!("{}\n</tbody></table>", v);
@@ -316,30 +278,22 @@ fn prepare_video_table(compare: &Vec<crate::videocandidate::VideoCandidate>) ->
 }
 
 pub fn present_video_matches(
-    destination_dir: &std::path::PathBuf,
-    compare: &Vec<crate::videocandidate::VideoCandidate>,
-) {
+    destination_dir: &std::path::Path,
+    compare: &[crate::videocandidate::VideoCandidate],
+) -> crate::Result<()> {
     use build_html::*;
     use std::io::Write;
     if compare.len() < 2 {
-        return;
-    }
-    let basefile_opt = std::path::Path::new(&compare[0].id).file_stem();
-    if basefile_opt.is_none() {
-        return;
+        return Ok(());
     }
+    let basefile = match std::path::Path::new(&compare[0].id).file_stem() {
+        Some(basefile) => osstring_to_string(basefile),
+        None => return Ok(()),
+    };
     if !destination_dir.is_dir() {
-        let ret = std::fs::create_dir(destination_dir.as_path());
-        if ret.is_err() {
-            log::error!(
-                "Unable to create the video matches directory in {}!",
-                destination_dir.display()
-            );
-            std::process::exit(2);
-        }
+        std::fs::create_dir(destination_dir)?;
     }
 
-    let basefile = osstring_to_string(&basefile_opt.unwrap());
     let title = format!("Comparing similar videos for {}", basefile);
     let table = prepare_video_table(compare);
     let html = build_html::HtmlPage::new()
@@ -352,26 +306,13 @@ pub fn present_video_matches(
         )
         .to_html_string();
     let storefile = destination_dir.join(format!("{}.html", basefile));
-    let path = std::path::Path::new(&storefile);
-    let display = path.display();
-    // Open a file in write-only mode, returns `io::Result<File>`
-    let mut write_file = match std::fs::File::create(&path) {
-        Err(why) => panic!("couldn't create {}: {}", display, why),
-        Ok(write_file) => write_file,
-    };
-    let ret = write_file.write(&html.as_bytes());
-    if ret.is_err() {
-        log::error!("failed to write html data to file {}!", storefile.display())
-    }
-    let ret = write_file.flush();
-    if ret.is_err() {
-        log::error!(
-            "failed to empty the write buffer for html data to file {}!",
-            &storefile.display()
-        )
-    }
+    let mut write_file = std::fs::File::create(&storefile)?;
+    write_file.write_all(html.as_bytes())?;
+    write_file.flush()?;
+    Ok(())
 }
 
+#[derive(Default)]
 struct VideoMetadata {
     duration: u32,
     width: u32,
@@ -379,23 +320,12 @@ struct VideoMetadata {
     framerate: f32,
 }
 
-impl Default for VideoMetadata {
-    fn default() -> VideoMetadata {
-        VideoMetadata {
-            duration: 0,
-            width: 0,
-            height: 0,
-            framerate: 0.0,
-        }
-    }
-}
-
 pub fn string_to_uint(mystring: &str) -> u32 {
     let u = 0;
-    if mystring.trim().len() == 0 {
+    if mystring.trim().is_empty() {
         return u;
     }
-    match u32::from_str_radix(mystring, 10) {
+    match mystring.parse::<u32>() {
         Ok(ret) => return ret,
         Err(_) => {
             log::warn!("Parsing of {} into Integer failed\n", mystring)
@@ -406,7 +336,7 @@ pub fn string_to_uint(mystring: &str) -> u32 {
 
 pub fn string_to_float(mystring: &str) -> f32 {
     let f = 0.0;
-    if mystring.trim().len() == 0 {
+    if mystring.trim().is_empty() {
         return f;
     }
     match mystring.parse::<f32>() {
@@ -418,41 +348,46 @@ pub fn string_to_float(mystring: &str) -> f32 {
     f
 }
 
-fn video_metadata(filepath: &str) -> VideoMetadata {
+/// runs ffmpeg with the given arguments and returns what it printed on stderr.
+fn run_ffmpeg(args: &[&str]) -> crate::Result<String> {
+    let ffmpeg_output = std::process::Command::new("ffmpeg")
+        .args(args)
+        .output()
+        .map_err(|error| crate::Error::Ffmpeg(format!("failed to execute ffmpeg: {}", error)))?;
+    Ok(String::from_utf8_lossy(&ffmpeg_output.stderr).into_owned())
+}
+
+fn video_metadata(filepath: &str) -> crate::Result<VideoMetadata> {
     let mut meta = VideoMetadata {
         ..Default::default()
     };
-    let ffmpeg_output = std::process::Command::new("ffmpeg")
-        .args(["-i", filepath])
-        .output()
-        .expect("failed to execute process");
-    match String::from_utf8(ffmpeg_output.stderr) {
-        Ok(message) => {
-            if let Ok(re_duration) = Regex::new(
-                r"(?i)\s*Duration:\s+(?P<hours>\d+):(?P<minutes>\d+):(?P<seconds>\d+).\d+\s*,",
-            ) {
-                if re_duration.is_match(&message) {
-                    let caps = re_duration.captures(&message).unwrap();
-                    let hours = string_to_uint(&caps["hours"]);
-                    let minutes = string_to_uint(&caps["minutes"]);
-                    let seconds = string_to_uint(&caps["seconds"]);
-                    meta.duration = hours * 3600 + minutes * 60 + seconds;
-                }
-            }
-            if let Ok(re_video) =
-                Regex::new(r"(?i), (?P<width>\d+)x(?P<height>\d+).*, (?P<fps>\d+) fps") {
-                if re_video.is_match(&message) {
-                    let caps = re_video.captures(&message).unwrap();
-                    meta.width = string_to_uint(&caps["width"]);
-                    meta.height = string_to_uint(&caps["height"]);
-                    meta.framerate = string_to_float(&caps["fps"]);
-                }
-            }
+    let message = run_ffmpeg(&["-i", filepath])?;
+    if let Ok(re_duration) = Regex::new(
+        r"(?i)\s*Duration:\s+(?P<hours>\d+):(?P<minutes>\d+):(?P<seconds>\d+).\d+\s*,",
+    ) {
+        if let Some(caps) = re_duration.captures(&message) {
+            let hours = string_to_uint(&caps["hours"]);
+            let minutes = string_to_uint(&caps["minutes"]);
+            let seconds = string_to_uint(&caps["seconds"]);
+            meta.duration = hours * 3600 + minutes * 60 + seconds;
+        }
+    }
+    if let Ok(re_video) =
+        Regex::new(r"(?i), (?P<width>\d+)x(?P<height>\d+).*, (?P<fps>\d+) fps") {
+        if let Some(caps) = re_video.captures(&message) {
+            meta.width = string_to_uint(&caps["width"]);
+            meta.height = string_to_uint(&caps["height"]);
+            meta.framerate = string_to_float(&caps["fps"]);
         }
-        Err(error) => log::error!("Error: {}", error),
+    }
+    if meta.duration == 0 {
+        return Err(crate::Error::Ffmpeg(format!(
+            "could not determine the duration of {}",
+            filepath
+        )));
     }
 
-    meta
+    Ok(meta)
 }
 
 fn create_screenshots(
@@ -461,10 +396,11 @@ fn create_screenshots(
     num_videos: u32,
     num_seconds_between_screenshots: u32,
     id_in_files_to_process: u32,
-) -> Result<(Vec<crate::videocandidate::Screenshot>, VideoMetadata), image::ImageError> {
+    config: crate::hashconfig::HashConfig,
+) -> crate::Result<(Vec<crate::videocandidate::Screenshot>, VideoMetadata)> {
     let mut v = Vec::new();
 
-    let meta = video_metadata(filepath);
+    let meta = video_metadata(filepath)?;
     let mut screenshot_id: usize = 1;
     let mut timecode = num_seconds_between_screenshots;
     let outputpattern = format!("{}_%03d.jpeg", filepath);
@@ -479,52 +415,45 @@ fn create_screenshots(
             }
         }
 
-        let ffmpeg_output = std::process::Command::new("ffmpeg")
-            .args([
-                "-ss",
-                &time,
-                "-i",
-                filepath,
-                "-frames:v",
-                "1",
-                "-q:v",
-                "2",
-                &outputpattern,
-            ])
-            .output()
-            .expect("failed to execute process");
-        match String::from_utf8(ffmpeg_output.stderr) {
-            Ok(_message) => log::warn!(
-                "Processing video {} of {} path {} timecode {}",
-                id_in_files_to_process,
-                num_videos,
-                filepath,
-                time
-            ),
-            Err(error) => log::error!("Error: {}", error),
-        }
+        run_ffmpeg(&[
+            "-ss",
+            &time,
+            "-i",
+            filepath,
+            "-frames:v",
+            "1",
+            "-q:v",
+            "2",
+            &outputpattern,
+        ])?;
+        log::warn!(
+            "Processing video {} of {} path {} timecode {}",
+            id_in_files_to_process,
+            num_videos,
+            filepath,
+            time
+        );
         if !outputpath.is_file() {
-            log::error!("Failed to create screenshot: {}", format!("ffmpeg -ss {} -i {} -frames:v 1 -q:v 2 ", time, filepath));
+            log::error!("Failed to create screenshot: ffmpeg -ss {} -i {} -frames:v 1 -q:v 2 ", time, filepath);
             log::error!("File {} seems to be defective from position {}%", filepath, (timecode / meta.duration) * 100);
             break;
         }
-        let res = image::ImageReader::open(outputpath);
-        if res.is_ok() {
-            let dynimg = res.unwrap().decode();
-            if dynimg.is_err() {
-                return Ok((v, meta));
+        let img = match image::ImageReader::open(outputpath)?.decode() {
+            Ok(img) => img,
+            Err(error) => {
+                log::error!("Failed to decode screenshot of {} at {}: {}", filepath, time, error);
+                break;
             }
-            let img = dynimg.unwrap();
-            let (hash, _smallimg) = crate::hash::create_hash(&img.into());
-            let ss = crate::videocandidate::Screenshot::from(
-                filepath,
-                video_id as usize,
-                screenshot_id,
-                timecode,
-                &hash,
-            );
-            v.push(ss);
-        }
+        };
+        let (hash, _smallimg) = crate::hash::create_hash(&img.into(), config)?;
+        let ss = crate::videocandidate::Screenshot::from(
+            filepath,
+            video_id as usize,
+            screenshot_id,
+            timecode,
+            &hash,
+        );
+        v.push(ss);
         timecode += num_seconds_between_screenshots;
         screenshot_id += 1;
     }
@@ -550,33 +479,31 @@ fn timecode_to_ffmpeg_time(timecode: u32) -> String {
 /// and compares it with the hashes of screenshots of existing videos.
 /// Delivers existing Matches and the new data structure back to the calling program.
 pub fn process_video(
-    path: &PathBuf,
+    path: &std::path::Path,
     video_id: usize,
     num_videos: u32,
     num_seconds_between_screenshots: u32,
     id_in_files_to_process: u32,
-) -> crate::videocandidate::VideoCandidate {
+    config: crate::hashconfig::HashConfig,
+) -> crate::Result<crate::videocandidate::VideoCandidate> {
     let id = osstring_to_string(path.as_os_str());
     let mut video = crate::videocandidate::VideoCandidate::from(&id, video_id);
+    video.format = crate::formats::VideoFormat::detect(path);
 
-    match create_screenshots(&id, video.index, num_videos, num_seconds_between_screenshots, id_in_files_to_process) {
-        Ok((v, meta)) => {
-            video.width = meta.width;
-            video.height = meta.height;
-            video.runtime = meta.duration;
-            video.framerate = meta.framerate;
-            for ss in v {
-                video.screenshots.push(ss);
-            }
-            // correct the runtime if the last screenshots could not be read ( possibly defective video )
-            let screentime = video.screenshots.len() as u32 * 10;
-            if video.runtime - screentime > 10 {
-                video.runtime = screentime + 5;
-            }
-        }
-        Err(error) => log::error!("Failed to create screenshots for {}: {}", path.display(), error),
+    let (v, meta) = create_screenshots(&id, video.index, num_videos, num_seconds_between_screenshots, id_in_files_to_process, config)?;
+    video.width = meta.width;
+    video.height = meta.height;
+    video.runtime = meta.duration;
+    video.framerate = meta.framerate;
+    for ss in v {
+        video.screenshots.push(ss);
+    }
+    // correct the runtime if the last screenshots could not be read ( possibly defective video )
+    let screentime = video.screenshots.len() as u32 * 10;
+    if video.runtime.saturating_sub(screentime) > 10 {
+        video.runtime = screentime + 5;
     }
-    video
+    Ok(video)
 }
 
 pub fn find_similar_videos(
@@ -584,11 +511,11 @@ pub fn find_similar_videos(
     client: &mut rusqlite::Connection,
     id: &str,
     video: &crate::videocandidate::VideoCandidate,
-) -> (
+) -> crate::Result<(
     crate::videomatches::VideoMatches,
     String,
     crate::videocandidate::VideoCandidate,
-) {
-    let matches = store.query(client, video);
-    (matches, id.to_string(), video.clone())
+)> {
+    let matches = store.query(client, video)?;
+    Ok((matches, id.to_string(), video.clone()))
 }
@@ -0,0 +1,391 @@
+//! Package formats lists the image file formats the library can read. PNG, JPEG,
+//! BMP, GIF, WebP and TIFF are always available, the others depend on the cargo
+//! features of the same name (all enabled by default). Camera RAW files are not
+//! developed, the JPEG preview embedded by the camera is hashed instead.
+//!
+//! AVIF files are recognized but not supported: the image crate only decodes them
+//! with the C library dav1d.
+//!
+//! Files are classified by their content (see Format::detect and VideoFormat::detect),
+//! the extension is only consulted for formats without magic bytes and to tell
+//! camera RAW files from plain TIFF files.
+
+/// SNIFF_LEN is the number of bytes at the start of a file read to detect its format.
+pub const SNIFF_LEN: usize = 64;
+
+/// Format is an image file format.
+///
+/// Qoi, Tga, Pnm, Hdr, OpenExr: need the cargo features qoi, tga, pnm, hdr and exr.
+/// Raw: camera RAW files (NEF, CR2, ARW, DNG), needs the cargo feature raw.
+/// Avif: recognized so AVIF files are not taken for videos, never supported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Format {
+    Png,
+    Jpeg,
+    Bmp,
+    Gif,
+    WebP,
+    Tiff,
+    Qoi,
+    Tga,
+    Pnm,
+    Hdr,
+    OpenExr,
+    Raw,
+    Avif,
+}
+
+/// FORMATS are all formats the library knows, supported by this build or not.
+pub const FORMATS: [Format; 13] = [
+    Format::Png,
+    Format::Jpeg,
+    Format::Bmp,
+    Format::Gif,
+    Format::WebP,
+    Format::Tiff,
+    Format::Qoi,
+    Format::Tga,
+    Format::Pnm,
+    Format::Hdr,
+    Format::OpenExr,
+    Format::Raw,
+    Format::Avif,
+];
+
+impl Format {
+    /// extensions returns the lower-case file extensions of the format.
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            Format::Png => &["png"],
+            Format::Jpeg => &["jpg", "jpeg"],
+            Format::Bmp => &["bmp"],
+            Format::Gif => &["gif"],
+            Format::WebP => &["webp"],
+            Format::Tiff => &["tif", "tiff"],
+            Format::Qoi => &["qoi"],
+            Format::Tga => &["tga"],
+            Format::Pnm => &["pbm", "pgm", "ppm", "pnm", "pam"],
+            Format::Hdr => &["hdr"],
+            Format::OpenExr => &["exr"],
+            Format::Raw => &["nef", "cr2", "arw", "dng"],
+            Format::Avif => &["avif"],
+        }
+    }
+
+    /// is_supported tells whether this build can read the format.
+    pub fn is_supported(&self) -> bool {
+        const UNSUPPORTED: &[Format] = &[
+            #[cfg(not(feature = "qoi"))]
+            Format::Qoi,
+            #[cfg(not(feature = "tga"))]
+            Format::Tga,
+            #[cfg(not(feature = "pnm"))]
+            Format::Pnm,
+            #[cfg(not(feature = "hdr"))]
+            Format::Hdr,
+            #[cfg(not(feature = "exr"))]
+            Format::OpenExr,
+            #[cfg(not(feature = "raw"))]
+            Format::Raw,
+            Format::Avif,
+        ];
+        !UNSUPPORTED.contains(self)
+    }
+
+    /// from_path returns the supported format of the file, judged by its extension.
+    pub fn from_path(p: &std::path::Path) -> Option<Format> {
+        let extension = crate::files::osstring_to_string(p.extension()?).to_ascii_lowercase();
+        supported_formats()
+            .into_iter()
+            .find(|format| format.extensions().contains(&extension.as_str()))
+    }
+
+    /// sniff returns the format of a file starting with the given bytes, supported
+    /// by this build or not. TGA files have no magic bytes and are not recognized,
+    /// RAW files other than CR2 are recognized as TIFF.
+    pub fn sniff(bytes: &[u8]) -> Option<Format> {
+        let starts = |magic: &[u8]| bytes.starts_with(magic);
+        let at = |offset: usize, magic: &[u8]| bytes.get(offset..offset + magic.len()) == Some(magic);
+        if starts(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]) {
+            Some(Format::Png)
+        } else if starts(&[0xff, 0xd8, 0xff]) {
+            Some(Format::Jpeg)
+        } else if starts(b"GIF87a") || starts(b"GIF89a") {
+            Some(Format::Gif)
+        } else if starts(b"RIFF") && at(8, b"WEBP") {
+            Some(Format::WebP)
+        } else if (starts(b"II*\0") || starts(b"MM\0*")) && at(8, b"CR") {
+            Some(Format::Raw)
+        } else if starts(b"II*\0") || starts(b"MM\0*") {
+            Some(Format::Tiff)
+        } else if starts(b"BM") {
+            Some(Format::Bmp)
+        } else if starts(b"qoif") {
+            Some(Format::Qoi)
+        } else if starts(&[0x76, 0x2f, 0x31, 0x01]) {
+            Some(Format::OpenExr)
+        } else if starts(b"#?RADIANCE") || starts(b"#?RGBE") {
+            Some(Format::Hdr)
+        } else if at(4, b"ftypavif") || at(4, b"ftypavis") {
+            Some(Format::Avif)
+        } else if bytes.len() > 2 && bytes[0] == b'P' && (b'1'..=b'7').contains(&bytes[1])
+            && bytes[2].is_ascii_whitespace() {
+            Some(Format::Pnm)
+        } else {
+            None
+        }
+    }
+
+    /// detect returns the supported format of the file, judged by its content. TIFF
+    /// files with a RAW extension are RAW files, files without magic bytes are judged
+    /// by their extension.
+    pub fn detect(p: &std::path::Path) -> Option<Format> {
+        let extension = p.extension().map(|ext| crate::files::osstring_to_string(ext).to_ascii_lowercase());
+        let has_extension = |format: Format| extension.as_ref().is_some_and(|ext| format.extensions().contains(&ext.as_str()));
+        let format = match Format::sniff(&read_header(p).ok()?) {
+            Some(Format::Tiff) if has_extension(Format::Raw) => Format::Raw,
+            Some(format) => format,
+            None if has_extension(Format::Tga) => Format::Tga,
+            None => return None,
+        };
+        format.is_supported().then_some(format)
+    }
+
+    /// code returns the number the format is encoded with, 0 is no format.
+    pub fn code(format: Option<Format>) -> u32 {
+        format.and_then(|format| FORMATS.iter().position(|f| *f == format)).map_or(0, |position| position as u32 + 1)
+    }
+
+    /// from_code returns the format encoded by code. Unknown codes, like the ones of
+    /// formats of later releases, give no format.
+    pub fn from_code(code: u32) -> Option<Format> {
+        FORMATS.get((code as usize).checked_sub(1)?).copied()
+    }
+
+    /// image_format returns the format for the image crate, None for RAW files.
+    pub fn image_format(&self) -> Option<image::ImageFormat> {
+        match self {
+            Format::Png => Some(image::ImageFormat::Png),
+            Format::Jpeg => Some(image::ImageFormat::Jpeg),
+            Format::Bmp => Some(image::ImageFormat::Bmp),
+            Format::Gif => Some(image::ImageFormat::Gif),
+            Format::WebP => Some(image::ImageFormat::WebP),
+            Format::Tiff => Some(image::ImageFormat::Tiff),
+            Format::Qoi => Some(image::ImageFormat::Qoi),
+            Format::Tga => Some(image::ImageFormat::Tga),
+            Format::Pnm => Some(image::ImageFormat::Pnm),
+            Format::Hdr => Some(image::ImageFormat::Hdr),
+            Format::OpenExr => Some(image::ImageFormat::OpenExr),
+            Format::Raw => None,
+            Format::Avif => Some(image::ImageFormat::Avif),
+        }
+    }
+}
+
+/// VideoFormat is a video container format.
+///
+/// Matroska:  MKV files.
+/// WebM:      the Matroska subset for the web.
+/// Mp4:       ISO base media files (MP4, M4V).
+/// QuickTime: MOV files.
+/// Avi:       RIFF AVI files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VideoFormat {
+    Matroska,
+    WebM,
+    Mp4,
+    QuickTime,
+    Avi,
+}
+
+/// VIDEO_FORMATS are all video formats the library knows.
+pub const VIDEO_FORMATS: [VideoFormat; 5] = [
+    VideoFormat::Matroska,
+    VideoFormat::WebM,
+    VideoFormat::Mp4,
+    VideoFormat::QuickTime,
+    VideoFormat::Avi,
+];
+
+impl VideoFormat {
+    /// extensions returns the lower-case file extensions of the format.
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            VideoFormat::Matroska => &["mkv"],
+            VideoFormat::WebM => &["webm"],
+            VideoFormat::Mp4 => &["mp4", "m4v"],
+            VideoFormat::QuickTime => &["mov"],
+            VideoFormat::Avi => &["avi"],
+        }
+    }
+
+    /// mime_type returns the MIME type of the format.
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            VideoFormat::Matroska => "video/x-matroska",
+            VideoFormat::WebM => "video/webm",
+            VideoFormat::Mp4 => "video/mp4",
+            VideoFormat::QuickTime => "video/quicktime",
+            VideoFormat::Avi => "video/x-msvideo",
+        }
+    }
+
+    /// sniff returns the format of a file starting with the given bytes. AVIF images
+    /// and image sequences are ISO base media files as well, but no videos.
+    pub fn sniff(bytes: &[u8]) -> Option<VideoFormat> {
+        if bytes.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) {
+            // the document type of the EBML header tells WebM from Matroska
+            if bytes.windows(4).any(|window| window == b"webm") {
+                Some(VideoFormat::WebM)
+            } else {
+                Some(VideoFormat::Matroska)
+            }
+        } else if bytes.get(4..8) == Some(b"ftyp") {
+            if matches!(bytes.get(8..12), Some(b"avif" | b"avis")) {
+                None
+            } else if bytes.get(8..12) == Some(b"qt  ") {
+                Some(VideoFormat::QuickTime)
+            } else {
+                Some(VideoFormat::Mp4)
+            }
+        } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"AVI ") {
+            Some(VideoFormat::Avi)
+        } else {
+            None
+        }
+    }
+
+    /// detect returns the format of the file, judged by its content.
+    pub fn detect(p: &std::path::Path) -> Option<VideoFormat> {
+        VideoFormat::sniff(&read_header(p).ok()?)
+    }
+
+    /// code returns the number the format is encoded with, 0 is no format.
+    pub fn code(format: Option<VideoFormat>) -> u32 {
+        format.and_then(|format| VIDEO_FORMATS.iter().position(|f| *f == format)).map_or(0, |position| position as u32 + 1)
+    }
+
+    /// from_code returns the format encoded by code. Unknown codes give no format.
+    pub fn from_code(code: u32) -> Option<VideoFormat> {
+        VIDEO_FORMATS.get((code as usize).checked_sub(1)?).copied()
+    }
+}
+
+/// read_header returns the first SNIFF_LEN bytes of the file, or all of a shorter one.
+fn read_header(p: &std::path::Path) -> crate::Result<Vec<u8>> {
+    use std::io::Read;
+    let mut header = Vec::with_capacity(SNIFF_LEN);
+    std::fs::File::open(p)?.take(SNIFF_LEN as u64).read_to_end(&mut header)?;
+    Ok(header)
+}
+
+/// supported_formats returns the formats this build can read.
+pub fn supported_formats() -> Vec<Format> {
+    FORMATS.iter().copied().filter(|format| format.is_supported()).collect()
+}
+
+/// supported_extensions returns the file extensions of all formats this build can read.
+pub fn supported_extensions() -> Vec<&'static str> {
+    supported_formats()
+        .iter()
+        .flat_map(|format| format.extensions().iter().copied())
+        .collect()
+}
+
+// TIFF tags of the IFDs of a RAW file
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014a;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+
+/// raw_preview returns the largest JPEG preview embedded in a TIFF based camera
+/// RAW file and the EXIF orientation of the file. The previews are found through
+/// the JPEG tags or, for JPEG compressed images, the strips of all IFDs and their
+/// sub-IFDs.
+pub fn raw_preview(bytes: &[u8]) -> crate::Result<(&[u8], u8)> {
+    let invalid = |message: &str| crate::Error::Decode(format!("RAW file {}", message));
+    let big_endian = match bytes.get(..4) {
+        Some([0x49, 0x49, 42, 0]) => false,
+        Some([0x4d, 0x4d, 0, 42]) => true,
+        _ => return Err(invalid("has no TIFF header")),
+    };
+    let u16_at = |offset: usize| -> Option<u16> {
+        let b = bytes.get(offset..offset + 2)?;
+        Some(if big_endian { u16::from_be_bytes([b[0], b[1]]) } else { u16::from_le_bytes([b[0], b[1]]) })
+    };
+    let u32_at = |offset: usize| -> Option<u32> {
+        let b = bytes.get(offset..offset + 4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Some(if big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    };
+    // the first value of an entry, SHORT or LONG
+    let value_at = |entry: usize| -> Option<u32> {
+        match u16_at(entry + 2)? {
+            3 => u16_at(entry + 8).map(u32::from),
+            _ => u32_at(entry + 8),
+        }
+    };
+
+    let mut orientation = 1;
+    let mut best: Option<(usize, usize)> = None;
+    let mut pending = vec![u32_at(4).ok_or_else(|| invalid("is truncated"))? as usize];
+    let mut visited = std::collections::BTreeSet::new();
+    while let Some(ifd) = pending.pop() {
+        // offsets point backwards in damaged files, do not loop forever
+        if ifd == 0 || !visited.insert(ifd) || visited.len() > 64 {
+            continue;
+        }
+        let Some(count) = u16_at(ifd) else {
+            continue;
+        };
+        let (mut compression, mut jpeg_offset, mut jpeg_length) = (0, 0, 0);
+        let (mut strip_offset, mut strip_length) = (0, 0);
+        for i in 0..count as usize {
+            let entry = ifd + 2 + 12 * i;
+            let (Some(tag), Some(value)) = (u16_at(entry), value_at(entry)) else {
+                break;
+            };
+            match tag {
+                TAG_COMPRESSION => compression = value,
+                TAG_STRIP_OFFSETS => strip_offset = value,
+                TAG_STRIP_BYTE_COUNTS => strip_length = value,
+                TAG_ORIENTATION if visited.len() == 1 => orientation = value.clamp(1, 8) as u8,
+                TAG_JPEG_OFFSET => jpeg_offset = value,
+                TAG_JPEG_LENGTH => jpeg_length = value,
+                TAG_SUB_IFDS => {
+                    let num = u32_at(entry + 4).unwrap_or(0) as usize;
+                    if num == 1 {
+                        pending.push(value as usize);
+                    } else {
+                        let array = value as usize;
+                        pending.extend((0..num.min(16)).filter_map(|n| u32_at(array + 4 * n)).map(|n| n as usize));
+                    }
+                }
+                _ => {}
+            }
+        }
+        let mut previews = vec![(jpeg_offset as usize, jpeg_length as usize)];
+        // old-style and new-style JPEG compression
+        if compression == 6 || compression == 7 {
+            previews.push((strip_offset as usize, strip_length as usize));
+        }
+        for (offset, length) in previews {
+            let is_jpeg = bytes.get(offset..offset + 3) == Some(&[0xff, 0xd8, 0xff]);
+            if length > 0 && offset + length <= bytes.len() && is_jpeg && best.is_none_or(|(_, l)| length > l) {
+                best = Some((offset, length));
+            }
+        }
+        if let Some(next) = u32_at(ifd + 2 + 12 * count as usize) {
+            pending.push(next as usize);
+        }
+    }
+    match best {
+        Some((offset, length)) => Ok((&bytes[offset..offset + length], orientation)),
+        None => Err(invalid("has no JPEG preview")),
+    }
+}
@@ -1,10 +1,10 @@
-/// Package haar provides a Haar wavelet function for bitmap images.
-use image;
+//! Package haar provides a Haar wavelet function for bitmap images.
 use image::Pixel;
 
 pub const COLOURCHANNELS: u32 = 3;
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Coef {
     pub c: Vec<f64>,
 }
@@ -30,10 +30,9 @@ impl Coef {
     }
 
     pub fn new() -> Self {
-        let v = Coef {
+        Coef {
             ..Default::default()
-        };
-        v
+        }
     }
 
     pub fn from(r: f64, g: f64, b: f64) -> Self {
@@ -50,8 +49,9 @@ impl Coef {
         crate::marshal::store_vec_f64(&self.c, to);
     }
 
-    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) {
-        self.c.extend(crate::marshal::restore_vec_f64(from));
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        self.c.extend(crate::marshal::restore_vec_f64(from)?);
+        Ok(())
     }
 }
 
@@ -63,7 +63,8 @@ impl Default for Coef {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CoefMatrix {
     pub coefs: Vec<Coef>,
     pub width: u32,
@@ -72,10 +73,9 @@ pub struct CoefMatrix {
 
 impl CoefMatrix {
     pub fn new() -> Self {
-        let v = CoefMatrix {
+        CoefMatrix {
             ..Default::default()
-        };
-        v
+        }
     }
 
     pub fn encode(&self, to: &mut Vec<u8>) {
@@ -88,29 +88,21 @@ impl CoefMatrix {
         crate::marshal::store_u32(self.height, to);
     }
 
-    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) {
-        let s = crate::marshal::restore_usize(from);
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        let s = crate::marshal::restore_usize(from)?;
         for _i in 0..s {
             let mut elem = Coef {
                 ..Default::default()
             };
-            elem.decode(from);
+            elem.decode(from)?;
             self.coefs.push(elem);
         }
-        self.width = crate::marshal::restore_u32(from);
-        self.height = crate::marshal::restore_u32(from);
+        self.width = crate::marshal::restore_u32(from)?;
+        self.height = crate::marshal::restore_u32(from)?;
+        Ok(())
     }
 }
 
-impl Default for CoefMatrix {
-    fn default() -> CoefMatrix {
-        CoefMatrix {
-            coefs: Vec::new(),
-            width: 0,
-            height: 0,
-        }
-    }
-}
 
 /// colorToCoef converts a native Color type into a YIQ Coef. We are using
 /// YIQ because we only have weights for them. (Apart from the score weights,
@@ -145,11 +137,11 @@ pub fn transform(img: &image::RgbaImage) -> CoefMatrix {
     };
     let mut width = img.width();
     let mut height = img.height();
-    if width % 2 != 0 {
-        width = width | 1;
+    if !width.is_multiple_of(2) {
+        width |= 1;
     }
-    if height % 2 != 0 {
-        height = height | 1;
+    if !height.is_multiple_of(2) {
+        height |= 1;
     }
     let reservelen: usize = width as usize * height as usize;
     for _ in 0..reservelen {
@@ -226,7 +218,6 @@ pub fn transform(img: &image::RgbaImage) -> CoefMatrix {
 }
 
 #[cfg(test)]
-
 // Whether or not the two coefficients are equal to an epsilon difference.
 fn equal(slice1: &Coef, slice2: &Coef) -> bool {
     for index in 0..slice1.c.len() {
@@ -234,7 +225,7 @@ fn equal(slice1: &Coef, slice2: &Coef) -> bool {
             return false;
         }
     }
-    return true;
+    true
 }
 
 // Whether or not the two matrices are equal (uses equal() function).
@@ -262,10 +253,10 @@ fn _equal_matrices(matrix1: &CoefMatrix, matrix2: &CoefMatrix) -> bool {
 }
 
 // Converts a slice of floats to a Coefs slice as found in a one-value matrix.
-fn _floats_to_coefs(floats: &Vec<f64>) -> CoefMatrix {
+fn _floats_to_coefs(floats: &[f64]) -> CoefMatrix {
     let mut coefs = CoefMatrix::new();
-    for index in 0..floats.len() {
-        coefs.coefs.push(Coef::from(floats[index], 0.0, 0.0));
+    for float in floats.iter() {
+        coefs.coefs.push(Coef::from(*float, 0.0, 0.0));
     }
     coefs
 }
@@ -275,17 +266,17 @@ fn _floats_to_coefs(floats: &Vec<f64>) -> CoefMatrix {
 fn test_coef() {
     let mut coef = Coef::from(1.0, 2.0, 3.0);
     let copy_coef = coef.clone();
-    assert_eq!(equal(&copy_coef, &Coef::from(1.0, 2.0, 3.0)), true);
+    assert!(equal(&copy_coef, &Coef::from(1.0, 2.0, 3.0)));
 
     let offset = Coef::from(2.0, 4.0, 6.0);
     coef.add(offset.clone());
-    assert_eq!(equal(&coef, &Coef::from(3.0, 6.0, 9.0)), true);
+    assert!(equal(&coef, &Coef::from(3.0, 6.0, 9.0)));
 
     coef.subtract(offset);
-    assert_eq!(equal(&coef, &Coef::from(1.0, 2.0, 3.0)), true);
+    assert!(equal(&coef, &Coef::from(1.0, 2.0, 3.0)));
 
     coef.divide(2.0);
-    assert_eq!(equal(&coef, &Coef::from(0.5, 1.0, 1.5)), true);
+    assert!(equal(&coef, &Coef::from(0.5, 1.0, 1.5)));
 }
 
 // Test the proper RGB-YIQ conversion.
@@ -293,10 +284,7 @@ fn test_coef() {
 fn test_color_conversion() {
     let rgb = image::Rgba::from([64_u8, 0, 128, 255]);
     let coef = color_to_coef(&rgb);
-    assert_eq!(
-        equal(&coef, &Coef::from(0.131975, -0.0117025, 0.2084315)),
-        true
-    );
+    assert!(equal(&coef, &Coef::from(0.131975, -0.0117025, 0.2084315)));
 }
 
 // Essentially a 1D Haar Wavelet test.
@@ -305,13 +293,11 @@ fn test_single_row() {
     // This is a rough approximation to a 4px by 1px YIQ image with pixels
     // .04, .02, .05, .05. Y, I, and Q all have the same value.
     let ret = image::load_from_memory(&[26_u8, 1, 16, 1, 13, 0, 8, 1, 33, 1, 20, 1, 33, 1, 20, 1]);
-    if ret.is_ok() {
-        let input = ret.unwrap();
-        let transopt = input.as_rgba8();
-        if transopt.is_some() {
-            let output = transform(transopt.unwrap());
-            let expected = _floats_to_coefs(&vec![0.08, -0.02, 0.02 / 2.0_f64.sqrt(), 0.0]);
-            assert_eq!(_equal_matrices(&output, &expected), true);
+    if let Ok(input) = ret {
+        if let Some(trans) = input.as_rgba8() {
+            let output = transform(trans);
+            let expected = _floats_to_coefs(&[0.08, -0.02, 0.02 / 2.0_f64.sqrt(), 0.0]);
+            assert!(_equal_matrices(&output, &expected));
         }
     }
 }
@@ -327,12 +313,10 @@ fn test_matrix4x4() {
         1, 53, 2, 33, 1, 59, 2, 37, 1, 66, 2, 41, 1, 72, 2, 45, 1, 79, 2, 49, 1, 85, 3, 53, 1, 92,
         3, 57, 1, 99, 3, 61, 1, 105, 3, 65, 1,
     ]);
-    if ret.is_ok() {
-        let input = ret.unwrap();
-        let transopt = input.as_rgba8();
-        if transopt.is_some() {
-            let output = transform(transopt.unwrap());
-            let expected = _floats_to_coefs(&vec![
+    if let Ok(input) = ret {
+        if let Some(trans) = input.as_rgba8() {
+            let output = transform(trans);
+            let expected = _floats_to_coefs(&[
                 0.34_f64,
                 -0.04,
                 -2.0_f64.sqrt() / 100.0,
@@ -350,7 +334,7 @@ fn test_matrix4x4() {
                 0.0,
                 0.0,
             ]);
-            assert_eq!(_equal_matrices(&output, &expected), true);
+            assert!(_equal_matrices(&output, &expected));
         }
     }
 }
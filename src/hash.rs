@@ -1,7 +1,11 @@
 
 use image::{self, Pixel};
 
+/// SIGNATURE_VERSION is the first byte of every signature made by Hash::to_signature.
+pub const SIGNATURE_VERSION: u8 = 1;
+
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Hash {
     pub matrix: crate::haar::CoefMatrix,
     // Thresholds contains the coefficient threholds. If you discard all
@@ -26,12 +30,30 @@ pub struct Hash {
 	// HistoMax is the maximum value of the histogram (for each channel Y, Cb,
 	// and Cr).
 	pub histo_max: Vec<f32>,
+
+    // Config are the parameters the hash was made with. It is not encoded, hashes
+    // inside a store always have the config of the store.
+    pub config: crate::hashconfig::HashConfig,
+
+    // Perceptual are the hashes of the perceptual hash algorithms of the config,
+    // in the order of perceptualhash::ALGORITHMS. They are not encoded.
+    pub perceptual: Vec<u64>,
+
+    // Orientation is the EXIF orientation (1 to 8) of the file the image was read
+    // from, before files::process_image turned it upright. Images without one are
+    // upright (1). It is not encoded.
+    pub orientation: u8,
+
+    // Format is the format of the file the image was read from, detected by
+    // files::process_image from its content. It is not encoded.
+    pub format: Option<crate::formats::Format>,
 }
 
 impl Hash {
     pub fn new() -> Self {
-        let v = Hash {..Default::default()};
-        v
+        Hash {
+            ..Default::default()
+        }
     }
 
     pub fn from(matrix: crate::haar::CoefMatrix, 
@@ -58,13 +80,82 @@ impl Hash {
         crate::marshal::store_vec_f32(&self.histo_max, to);
     }
 
-    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) {
-        self.matrix.decode(from);
-        self.thresholds.decode(from);
-        self.ratio = crate::marshal::restore_f64(from);
-        self.dhash.extend(crate::marshal::restore_vec_u64(from));
-        self.histogram = crate::marshal::restore_u64(from);
-        self.histo_max.extend(crate::marshal::restore_vec_f32(from));
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        self.matrix.decode(from)?;
+        self.thresholds.decode(from)?;
+        self.ratio = crate::marshal::restore_f64(from)?;
+        self.dhash.extend(crate::marshal::restore_vec_u64(from)?);
+        self.histogram = crate::marshal::restore_u64(from)?;
+        self.histo_max.extend(crate::marshal::restore_vec_f32(from)?);
+        Ok(())
+    }
+
+    /// to_signature encodes the parts of the hash a query needs as compact text
+    /// (URL-safe base64): the positions and signs of the largest coefficients, the
+    /// scaling function coefficient, ratio, dHash and histogram. The full
+    /// coefficient matrix is not kept.
+    pub fn to_signature(&self) -> String {
+        use base64::Engine as _;
+        let mut bytes = vec![SIGNATURE_VERSION];
+        self.config.encode(&mut bytes);
+        let scale_coeff = self.matrix.coefs.first().cloned().unwrap_or_default();
+        crate::marshal::store_vec_f64(&scale_coeff.c, &mut bytes);
+        crate::marshal::store_f64(self.ratio, &mut bytes);
+        crate::marshal::store_vec_u64(&self.dhash, &mut bytes);
+        crate::marshal::store_u64(self.histogram, &mut bytes);
+        crate::marshal::store_vec_f32(&self.histo_max, &mut bytes);
+        crate::marshal::store_vec_u32(&crate::store::hash_locations(self), &mut bytes);
+        if !self.config.perceptual.is_empty() {
+            crate::marshal::store_vec_u64(&self.perceptual, &mut bytes);
+        }
+        crate::marshal::store_u32(self.orientation as u32, &mut bytes);
+        crate::marshal::store_u32(crate::formats::Format::code(self.format), &mut bytes);
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    /// from_signature restores a hash from a signature made by to_signature. The
+    /// largest coefficients become +1 or -1 with a threshold of 1, all others 0, so
+    /// the hash gives the same query results as the original one.
+    pub fn from_signature(signature: &str) -> crate::Result<Self> {
+        use base64::Engine as _;
+        let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|error| crate::Error::Decode(format!("invalid signature: {}", error)))?;
+        match bytes.first() {
+            Some(&SIGNATURE_VERSION) => {}
+            Some(version) => return Err(crate::Error::Version(*version as u32)),
+            None => return Err(crate::Error::Decode("empty signature".to_string())),
+        }
+        let mut from = std::io::Cursor::new(bytes);
+        from.set_position(1);
+        let mut v = Hash::new();
+        v.config.decode(&mut from)?;
+        let scale = v.config.scale;
+        let channels = crate::haar::COLOURCHANNELS;
+        v.matrix.width = scale;
+        v.matrix.height = scale;
+        v.matrix.coefs = vec![crate::haar::Coef::new(); (scale * scale) as usize];
+        v.matrix.coefs[0].c = crate::marshal::restore_vec_f64(&mut from)?;
+        v.thresholds = crate::haar::Coef::from(1.0, 1.0, 1.0);
+        v.ratio = crate::marshal::restore_f64(&mut from)?;
+        v.dhash = crate::marshal::restore_vec_u64(&mut from)?;
+        v.histogram = crate::marshal::restore_u64(&mut from)?;
+        v.histo_max = crate::marshal::restore_vec_f32(&mut from)?;
+        for location in crate::marshal::restore_vec_u32(&mut from)? {
+            if location >= v.config.num_indices() {
+                return Err(crate::Error::Decode(format!("invalid signature location {}", location)));
+            }
+            let sign = if location >= scale * scale * channels { -1.0 } else { 1.0 };
+            let location = location % (scale * scale * channels);
+            let coef = &mut v.matrix.coefs[(location / channels) as usize];
+            coef.c[(location % channels) as usize] = sign;
+        }
+        if !v.config.perceptual.is_empty() {
+            v.perceptual = crate::marshal::restore_vec_u64(&mut from)?;
+        }
+        v.orientation = crate::marshal::restore_u32(&mut from)? as u8;
+        v.format = crate::formats::Format::from_code(crate::marshal::restore_u32(&mut from)?);
+        Ok(v)
     }
 }
 
@@ -77,65 +168,101 @@ impl Default for Hash {
             dhash: Vec::new(),
             histogram: 0,
             histo_max: Vec::new(),
+            config: crate::hashconfig::HashConfig::new(),
+            perceptual: Vec::new(),
+            orientation: 1,
+            format: None,
        }
     }
 }
 
 /// CreateHash calculates and returns the visual hash of the provided image as
-/// well as a resized version of it (ImageScale x ImageScale) which may be
+/// well as a resized version of it (scale x scale of the config) which may be
 /// ignored if not needed anymore.
-pub fn create_hash(img: &image::RgbaImage) -> (Hash, image::RgbaImage) {
+pub fn create_hash(img: &image::RgbaImage,
+                   config: crate::hashconfig::HashConfig) -> crate::Result<(Hash, image::RgbaImage)> {
+    config.validate()?;
     let mut h = Hash {..Default::default()};
-    let mut smallimg = image::RgbaImage::new(1, 1);
-    if img.height() == 0 {
-        return (h, smallimg);
+    h.config = config;
+    if img.width() == 0 || img.height() == 0 {
+        return Err(crate::Error::Image(image::ImageError::Parameter(
+            image::error::ParameterError::from_kind(
+                image::error::ParameterErrorKind::DimensionMismatch,
+            ),
+        )));
+    }
+    h.ratio = img.width() as f64 / img.height() as f64;
+    let smallimg = image::imageops::resize(img, 
+                config.scale, 
+                config.scale, 
+                config.filter.filter_type());
+    hash_resized(&mut h, &smallimg);
+
+    Ok((h, smallimg))
+}
+
+/// create_dihedral_hashes calculates the hashes of all 8 rotations and reflections
+/// of the provided image, for Store::query_dihedral. The image is resized only
+/// once, the resized image is transformed and hashed again for every transform.
+pub fn create_dihedral_hashes(img: &image::RgbaImage,
+                              config: crate::hashconfig::HashConfig)
+                              -> crate::Result<Vec<(crate::dihedral::Dihedral, Hash)>> {
+    let (hash, smallimg) = create_hash(img, config)?;
+    let mut hashes = Vec::with_capacity(crate::dihedral::DIHEDRALS.len());
+    for transform in crate::dihedral::DIHEDRALS {
+        if transform == crate::dihedral::Dihedral::Identity {
+            hashes.push((transform, hash.clone()));
+            continue;
+        }
+        let mut h = Hash {..Default::default()};
+        h.config = config;
+        h.ratio = if transform.swaps_sides() { 1.0 / hash.ratio } else { hash.ratio };
+        hash_resized(&mut h, &transform.apply(&smallimg));
+        hashes.push((transform, h));
     }
-    h.ratio = (img.width() / img.height()) as f64;
-    smallimg = image::imageops::resize(img, 
-                crate::store::IMAGESCALE, 
-                crate::store::IMAGESCALE, 
-                image::imageops::FilterType::Lanczos3);
-    // Then perform a 2D Haar Wavelet transform.
-    h.matrix = crate::haar::transform(&smallimg);
+    Ok(hashes)
+}
+
+/// hash_resized fills in all metrics but the ratio from the resized image.
+fn hash_resized(h: &mut Hash, smallimg: &image::RgbaImage) {
+    // Perform a 2D Haar Wavelet transform.
+    h.matrix = crate::haar::transform(smallimg);
     // Find the kth largest coefficients for each colour channel.
-    h.thresholds = coef_thresholds(&mut h.matrix.coefs, crate::store::TOPCOEFS);
+    h.thresholds = coef_thresholds(&h.matrix.coefs, h.config.top_coefs);
     // Create the dHash bit vector.
-    h.dhash = dhash(&smallimg);
-    (h.histogram, h.histo_max) = histogram(&smallimg);
-
-    (h, smallimg)
+    h.dhash = dhash(smallimg);
+    (h.histogram, h.histo_max) = histogram(smallimg);
+    // Create the additional perceptual hashes from the same image.
+    h.perceptual = h.config.perceptual.hashes(smallimg, h.config.filter);
 }
 
 /// coefThreshold returns, for the given coefficients, the kth largest absolute
 /// value. Only the nth element in each Coef is considered. If you discard all
 /// values v with abs(v) < threshold, you will end up with k values.
-pub fn coef_threshold(coefs: &Vec<crate::haar::Coef>, k: i32, n: usize) -> f64 {
-    if coefs.len() == 0 {
+pub fn coef_threshold(coefs: &[crate::haar::Coef], k: i32, n: usize) -> f64 {
+    if coefs.is_empty() {
         return 0.0;
     }
-    let mut v = Vec::new();
-    for i in 0..coefs.len() {
-        v.push(coefs[i].c[n]);
-    }
+    let mut v: Vec<f64> = coefs.iter().map(|coef| coef.c[n]).collect();
     v.sort_by(|a, b| a.partial_cmp(b).unwrap());
     if v.len() <= k as usize {
-        return v[0];
+        v[0]
     } else {
         let index = v.len() - k as usize - 1;
-        return v[index];
+        v[index]
     }
 }
 
 /// coefThresholds returns, for the given coefficients, the kth largest absolute
 /// values per colour channel. If you discard all values v with
 /// abs(v) < threshold, you will end up with k values.
-pub fn coef_thresholds(coefs: &mut Vec<crate::haar::Coef>, k: i32) -> crate::haar::Coef {
+pub fn coef_thresholds(coefs: &[crate::haar::Coef], k: i32) -> crate::haar::Coef {
     let mut thresholds = crate::haar::Coef::new();
-    if coefs.len() == 0 {
+    if coefs.is_empty() {
         return thresholds;
     }
-    for i in 0..thresholds.c.len() {
-        thresholds.c[i] = coef_threshold(coefs, k, i);
+    for (i, threshold) in thresholds.c.iter_mut().enumerate() {
+        *threshold = coef_threshold(coefs, k, i);
     }
     thresholds
 }
@@ -156,12 +283,11 @@ fn ycbcr(color: &image::Rgba<u8>) -> (u8, u8, u8) {
     let r = i32::from(rgb[0]);
     let g = i32::from(rgb[1]);
     let b = i32::from(rgb[2]);
-    let mut yuv = vec![0; 3];
-    yuv[0] = clamp((77 * r + 150 * g + 29 * b + 128) >> 8);
-    yuv[1] = clamp(((-43 * r - 84 * g + 127 * b + 128) >> 8) + 128);
-    yuv[2] = clamp(((127 * r - 106 * g - 21 * b + 128) >> 8) + 128);
+    let y = clamp((77 * r + 150 * g + 29 * b + 128) >> 8);
+    let cb = clamp(((-43 * r - 84 * g + 127 * b + 128) >> 8) + 128);
+    let cr = clamp(((127 * r - 106 * g - 21 * b + 128) >> 8) + 128);
 
-    (yuv[0], yuv[1], yuv[2])
+    (y, cb, cr)
 }
 
 /// dHash computes a 128 bit vector by comparing adjacent pixels of a downsized
@@ -230,9 +356,9 @@ fn histogram_median(v: &[i32]) -> (usize, f32) {
     let length = sorted.len();
     let half = length/2;
     if length %2 == 0 {
-        return (half, (sorted[half] + sorted[half+1]) as f32 /2.0);
+        (half, (sorted[half] + sorted[half+1]) as f32 /2.0)
     } else {
-        return (half, sorted[half] as f32);
+        (half, sorted[half] as f32)
     }
 }
 
@@ -243,16 +369,16 @@ fn histogram_median(v: &[i32]) -> (usize, f32) {
 pub fn histogram(img: &image::RgbaImage) -> (u64, Vec<f32>) {
     let mut bits =  0_u64;
     let mut histo_max = vec![0.0_f32; 64];
-    let mut h = vec![0_i32; 64];
+    let mut h = [0_i32; 64];
 
     for y in 0..img.height() {
         for x in 0..img.width() {
             let (ytr, cbtr, crtr) = ycbcr(img.get_pixel(x, y));
             let index = ytr as usize >> 3;
             h[index] += 1;
-            let index = 32 + cbtr as usize >> 4;
+            let index = (32 + cbtr as usize) >> 4;
             h[index] += 1;
-            let index = 48 + crtr as usize >> 4;
+            let index = (48 + crtr as usize) >> 4;
             h[index] += 1;
         }
     }
@@ -266,22 +392,16 @@ pub fn histogram(img: &image::RgbaImage) -> (u64, Vec<f32>) {
     histo_max[2] = crmax;
 
     // Quantize histogram.
-    for index in 0..h.len() {
-        let value = h[index];
-        if index < 32 {
-            if value as usize > my {
-                bits |= 1 << index
-            }
+    for (index, &value) in h.iter().enumerate() {
+        let median = if index < 32 {
+            my
         } else if index < 48 {
-            if value as usize > mcb {
-                //bits |= 1 << index-32;
-                bits |= 1 << index;
-            }
+            mcb
         } else {
-            if value as usize > mcr {
-                //bits |= 1 << index-32;
-                bits |= 1 << index;
-            }
+            mcr
+        };
+        if value as usize > median {
+            bits |= 1 << index;
         }
     }
 
@@ -0,0 +1,242 @@
+//! Package hashcache remembers the hashes of image files between runs, so files
+//! that did not change since they were hashed are not decoded and resized again.
+//! A file counts as unchanged while its canonical path, size, modification time
+//! and inode are the same. The hashes are kept as compact signatures (see
+//! Hash::to_signature).
+
+/// HASHCACHE_MAGIC are the first bytes of every file written by HashCache::save.
+pub const HASHCACHE_MAGIC: [u8; 8] = *b"DUPLOHCA";
+/// HASHCACHE_FORMAT_VERSION is the version of the file format written by HashCache::save.
+pub const HASHCACHE_FORMAT_VERSION: u32 = 1;
+// magic, version and payload length
+const HASHCACHE_HEADER_LEN: usize = 8 + 4 + 8;
+
+/// FileState identifies the content of a file without reading it.
+///
+/// size:  length of the file in bytes.
+/// mtime: modification time as seconds and nanoseconds since the UNIX epoch.
+/// inode: inode number of the file, 0 on platforms without inodes.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FileState {
+    pub size: u64,
+    pub mtime_secs: i64,
+    pub mtime_nanos: u32,
+    pub inode: u64,
+}
+
+impl FileState {
+    pub fn new() -> Self {
+        FileState {
+            ..Default::default()
+        }
+    }
+
+    /// from reads the state of the file at the given path.
+    pub fn from(p: &std::path::Path) -> crate::Result<Self> {
+        let metadata = std::fs::metadata(p)?;
+        let mut v = FileState::new();
+        v.size = metadata.len();
+        match metadata.modified()?.duration_since(std::time::UNIX_EPOCH) {
+            Ok(since) => {
+                v.mtime_secs = since.as_secs() as i64;
+                v.mtime_nanos = since.subsec_nanos();
+            }
+            Err(error) => {
+                // modified before 1970
+                let before = error.duration();
+                v.mtime_secs = -(before.as_secs() as i64);
+                v.mtime_nanos = before.subsec_nanos();
+            }
+        }
+        #[cfg(unix)]
+        {
+            v.inode = std::os::unix::fs::MetadataExt::ino(&metadata);
+        }
+        Ok(v)
+    }
+
+    pub fn encode(&self, to: &mut Vec<u8>) {
+        crate::marshal::store_u64(self.size, to);
+        crate::marshal::store_i64(self.mtime_secs, to);
+        crate::marshal::store_u32(self.mtime_nanos, to);
+        crate::marshal::store_u64(self.inode, to);
+    }
+
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        self.size = crate::marshal::restore_u64(from)?;
+        self.mtime_secs = crate::marshal::restore_i64(from)?;
+        self.mtime_nanos = crate::marshal::restore_u32(from)?;
+        self.inode = crate::marshal::restore_u64(from)?;
+        Ok(())
+    }
+}
+
+/// HashCache maps the canonical paths of image files to the state the file had
+/// when it was hashed and the signature of its hash.
+///
+/// modified tells whether entries were added or removed since the cache was
+/// loaded/created.
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+pub struct HashCache {
+    pub entries: std::collections::BTreeMap<String, (FileState, String)>,
+    pub modified: bool,
+}
+
+/// key returns the canonical path of the file as the key of the cache.
+fn key(p: &std::path::Path) -> crate::Result<String> {
+    Ok(p.canonicalize()?.to_string_lossy().to_string())
+}
+
+impl HashCache {
+    pub fn new() -> Self {
+        HashCache {
+            ..Default::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// changed tells whether the file has to be hashed (again), because it is not
+    /// in the cache or was modified since it was hashed.
+    pub fn changed(&self, p: &std::path::Path) -> crate::Result<bool> {
+        let state = FileState::from(p)?;
+        Ok(match self.entries.get(&key(p)?) {
+            Some((cached, _)) => *cached != state,
+            None => true,
+        })
+    }
+
+    /// get returns the cached hash of the file if the file did not change since.
+    pub fn get(&self, p: &std::path::Path) -> crate::Result<Option<crate::hash::Hash>> {
+        let state = FileState::from(p)?;
+        match self.entries.get(&key(p)?) {
+            Some((cached, signature)) if *cached == state => {
+                Ok(Some(crate::hash::Hash::from_signature(signature)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// insert remembers the hash of the file in its current state.
+    pub fn insert(&mut self, p: &std::path::Path, hash: &crate::hash::Hash) -> crate::Result<()> {
+        self.insert_signature(p, hash.to_signature())
+    }
+
+    /// insert_signature remembers the hash with the given signature.
+    pub(crate) fn insert_signature(&mut self, p: &std::path::Path, signature: String) -> crate::Result<()> {
+        let state = FileState::from(p)?;
+        self.entries.insert(key(p)?, (state, signature));
+        self.modified = true;
+        Ok(())
+    }
+
+    /// remove forgets the file, e.g. after it was deleted.
+    pub fn remove(&mut self, p: &std::path::Path) {
+        let key = match p.canonicalize() {
+            Ok(path) => path.to_string_lossy().to_string(),
+            Err(_) => p.to_string_lossy().to_string(),
+        };
+        if self.entries.remove(&key).is_some() {
+            self.modified = true;
+        }
+    }
+
+    /// prune forgets all files that do not exist anymore.
+    pub fn prune(&mut self) {
+        let before = self.entries.len();
+        self.entries.retain(|path, _| std::path::Path::new(path).is_file());
+        if self.entries.len() != before {
+            self.modified = true;
+        }
+    }
+
+    /// process_image returns the cached hash of the image or, if the file is new
+    /// or changed or was hashed with another config, hashes it like
+    /// files::process_image and caches the result.
+    pub fn process_image(&mut self, p: &std::path::Path,
+                         config: crate::hashconfig::HashConfig) -> crate::Result<crate::hash::Hash> {
+        if let Some(hash) = self.get(p)? {
+            if hash.config == config {
+                return Ok(hash);
+            }
+        }
+        let hash = crate::files::process_image(p, config)?;
+        self.insert(p, &hash)?;
+        Ok(hash)
+    }
+
+    pub fn encode(&self, to: &mut Vec<u8>) {
+        crate::marshal::store_usize(self.entries.len(), to);
+        for (path, (state, signature)) in self.entries.iter() {
+            crate::marshal::store_string(path, to);
+            state.encode(to);
+            crate::marshal::store_string(signature, to);
+        }
+    }
+
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        let s = crate::marshal::restore_usize(from)?;
+        for _i in 0..s {
+            let path = crate::marshal::restore_string(from)?;
+            let mut state = FileState::new();
+            state.decode(from)?;
+            let signature = crate::marshal::restore_string(from)?;
+            self.entries.insert(path, (state, signature));
+        }
+        Ok(())
+    }
+
+    /// load reads the cache written by save. A missing file gives an empty cache.
+    pub fn load(cachefile: &str) -> crate::Result<Self> {
+        let bytes = match std::fs::read(cachefile) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(HashCache::new()),
+            Err(error) => return Err(error.into()),
+        };
+        if !bytes.starts_with(&HASHCACHE_MAGIC) || bytes.len() < HASHCACHE_HEADER_LEN + 4 {
+            return Err(crate::Error::Decode(format!("{} is not a hash cache", cachefile)));
+        }
+        let mut header = std::io::Cursor::new(bytes[8..HASHCACHE_HEADER_LEN].to_vec());
+        let version = crate::marshal::restore_u32(&mut header)?;
+        if version != HASHCACHE_FORMAT_VERSION {
+            return Err(crate::Error::Version(version));
+        }
+        let length = crate::marshal::restore_usize(&mut header)?;
+        if bytes.len() - HASHCACHE_HEADER_LEN - 4 != length {
+            return Err(crate::Error::Decode(format!("hash cache {} is truncated", cachefile)));
+        }
+        let end = HASHCACHE_HEADER_LEN + length;
+        let mut trailer = std::io::Cursor::new(bytes[end..].to_vec());
+        let expected = crate::marshal::restore_u32(&mut trailer)?;
+        let found = crate::marshal::checksum(&bytes[..end]);
+        if expected != found {
+            return Err(crate::Error::Checksum { expected, found });
+        }
+        let mut v = HashCache::new();
+        v.decode(&mut std::io::Cursor::new(bytes[HASHCACHE_HEADER_LEN..end].to_vec()))?;
+        Ok(v)
+    }
+
+    /// save writes the cache with a header and a CRC-32 like Store::dump_binary.
+    /// The file is replaced atomically.
+    pub fn save(&mut self, cachefile: &str) -> crate::Result<()> {
+        let mut payload = Vec::new();
+        self.encode(&mut payload);
+        let mut to = Vec::new();
+        to.extend_from_slice(&HASHCACHE_MAGIC);
+        crate::marshal::store_u32(HASHCACHE_FORMAT_VERSION, &mut to);
+        crate::marshal::store_usize(payload.len(), &mut to);
+        to.extend_from_slice(&payload);
+        let checksum = crate::marshal::checksum(&to);
+        crate::marshal::store_u32(checksum, &mut to);
+        crate::journal::write_atomic(std::path::Path::new(cachefile), &to)?;
+        self.modified = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+//! Package hashconfig holds the parameters hashes are made with. Hashes can only
+//! be compared with hashes made with the same parameters, so every store records
+//! the config of its hashes and rejects others.
+
+/// ResizeFilter is the filter images are resized with before they are hashed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    #[default]
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    pub fn filter_type(&self) -> image::imageops::FilterType {
+        match self {
+            ResizeFilter::Nearest => image::imageops::FilterType::Nearest,
+            ResizeFilter::Triangle => image::imageops::FilterType::Triangle,
+            ResizeFilter::CatmullRom => image::imageops::FilterType::CatmullRom,
+            ResizeFilter::Gaussian => image::imageops::FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => image::imageops::FilterType::Lanczos3,
+        }
+    }
+
+    /// code is the number the filter is stored as.
+    pub fn code(&self) -> u32 {
+        match self {
+            ResizeFilter::Nearest => 0,
+            ResizeFilter::Triangle => 1,
+            ResizeFilter::CatmullRom => 2,
+            ResizeFilter::Gaussian => 3,
+            ResizeFilter::Lanczos3 => 4,
+        }
+    }
+
+    pub fn from_code(code: u32) -> crate::Result<Self> {
+        match code {
+            0 => Ok(ResizeFilter::Nearest),
+            1 => Ok(ResizeFilter::Triangle),
+            2 => Ok(ResizeFilter::CatmullRom),
+            3 => Ok(ResizeFilter::Gaussian),
+            4 => Ok(ResizeFilter::Lanczos3),
+            code => Err(crate::Error::Decode(format!("unknown resize filter {}", code))),
+        }
+    }
+}
+
+/// HashConfig are the parameters of create_hash. Smaller values make hashing
+/// faster and stores smaller but the results less accurate.
+///
+/// scale:     images are resized to scale x scale pixels before the Haar wavelet
+///            transform. Must be a power of two of at least 8.
+/// top_coefs: number of the largest coefficients per colour channel that are kept
+///            in the index.
+/// filter:    the filter images are resized with.
+/// perceptual: the perceptual hashes that are calculated in addition and kept with
+///            every image, so matches can be verified with them.
+/// apply_orientation: files::process_image turns images upright according to their
+///            EXIF orientation before they are hashed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HashConfig {
+    pub scale: u32,
+    pub top_coefs: i32,
+    pub filter: ResizeFilter,
+    pub perceptual: crate::perceptualhash::Algorithms,
+    pub apply_orientation: bool,
+}
+
+/// DEFAULT_SCALE is the scale of HashConfig::default, the one of earlier releases.
+pub const DEFAULT_SCALE: u32 = 128;
+/// DEFAULT_TOP_COEFS is the top_coefs of HashConfig::default.
+pub const DEFAULT_TOP_COEFS: i32 = 40;
+
+impl Default for HashConfig {
+    fn default() -> HashConfig {
+        HashConfig {
+            scale: DEFAULT_SCALE,
+            top_coefs: DEFAULT_TOP_COEFS,
+            filter: ResizeFilter::Lanczos3,
+            perceptual: crate::perceptualhash::Algorithms::new(),
+            apply_orientation: true,
+        }
+    }
+}
+
+impl HashConfig {
+    pub fn new() -> Self {
+        HashConfig {
+            ..Default::default()
+        }
+    }
+
+    pub fn from(scale: u32, top_coefs: i32, filter: ResizeFilter) -> Self {
+        HashConfig {
+            scale,
+            top_coefs,
+            filter,
+            ..Default::default()
+        }
+    }
+
+    /// validate checks that hashes can be made with the config.
+    pub fn validate(&self) -> crate::Result<()> {
+        if self.scale < 8 || !self.scale.is_power_of_two() || self.scale > 4096 {
+            return Err(crate::Error::Decode(format!(
+                "hash scale {} is not a power of two between 8 and 4096", self.scale)));
+        }
+        if self.top_coefs < 1 || self.top_coefs as u64 >= self.scale as u64 * self.scale as u64 {
+            return Err(crate::Error::Decode(format!(
+                "{} top coefficients do not fit a scale of {}", self.top_coefs, self.scale)));
+        }
+        Ok(())
+    }
+
+    /// num_indices is the number of index lists of a store with this config, one
+    /// per sign, coefficient and colour channel.
+    pub fn num_indices(&self) -> u32 {
+        2 * self.scale * self.scale * crate::haar::COLOURCHANNELS
+    }
+
+    /// check returns an error if a hash made with the other config cannot be used
+    /// with this one.
+    pub fn check(&self, other: &HashConfig) -> crate::Result<()> {
+        if self != other {
+            return Err(crate::Error::Conflict(format!(
+                "hash was made with {:?} but the store uses {:?}", other, self)));
+        }
+        Ok(())
+    }
+
+    pub fn encode(&self, to: &mut Vec<u8>) {
+        crate::marshal::store_u32(self.scale, to);
+        crate::marshal::store_u32(self.top_coefs as u32, to);
+        crate::marshal::store_u32(self.filter.code(), to);
+        crate::marshal::store_u32(self.perceptual.bits(), to);
+        crate::marshal::store_bool(self.apply_orientation, to);
+    }
+
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        self.scale = crate::marshal::restore_u32(from)?;
+        self.top_coefs = crate::marshal::restore_u32(from)? as i32;
+        self.filter = ResizeFilter::from_code(crate::marshal::restore_u32(from)?)?;
+        self.perceptual = crate::perceptualhash::Algorithms::from_bits(crate::marshal::restore_u32(from)?)?;
+        self.apply_orientation = crate::marshal::restore_bool(from)?;
+        self.validate()
+    }
+}
@@ -0,0 +1,225 @@
+//! Package ingest adds many image files to a Store at once. A pool of worker
+//! threads decodes and hashes the images while a single writer, the calling
+//! thread, queries the store and adds the images one by one in the order of the
+//! given paths. A worker only starts on an image once it is less than queue_size
+//! images ahead of the writer, so fast workers cannot run arbitrarily far ahead
+//! of a slow image and at most queue_size hashes wait for the writer.
+
+/// IngestOptions configure ingest_images.
+///
+/// num_threads: number of worker threads hashing images. By default one per CPU.
+/// queue_size:  number of hashed images that may wait for the writer, at least
+///              one. By default twice the number of workers.
+/// query:       options of the query every image is compared with the store with.
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+pub struct IngestOptions {
+    pub num_threads: Option<usize>,
+    pub queue_size: Option<usize>,
+    pub query: crate::queryoptions::QueryOptions,
+}
+
+impl IngestOptions {
+    pub fn new() -> Self {
+        IngestOptions {
+            ..Default::default()
+        }
+    }
+}
+
+/// IngestReport tells what ingest_images did with the paths.
+///
+/// added:   number of images added to the store.
+/// skipped: number of images that were already in the store or not added because
+///          the callback decided so.
+/// failed:  the paths of the images that could not be hashed and why.
+/// max_pending: the most hashed images that waited for the writer at the same
+///          time, never more than the queue size.
+#[derive(Debug, Default)]
+pub struct IngestReport {
+    pub added: usize,
+    pub skipped: usize,
+    pub failed: Vec<(std::path::PathBuf, crate::Error)>,
+    pub max_pending: usize,
+}
+
+impl IngestReport {
+    pub fn new() -> Self {
+        IngestReport {
+            ..Default::default()
+        }
+    }
+}
+
+/// ingest_images hashes the images at the given paths in parallel and adds them to
+/// the store under their path as ID. Before an image is added, the store is queried
+/// with it. If there are matches, on_match is called with the store, the ID, the
+/// hash and the matches and decides whether the image is added; it may change the
+/// store, e.g. delete a worse duplicate. Images without matches are always added.
+///
+/// The images are hashed with the config of the store. Images that are already in
+/// the store are skipped. With a cache, they are hashed again if the file changed
+/// and replace the old entry once the new hash exists, and unchanged files are not decoded at all unless their cached hash was made
+/// with another config; the hashes of all newly hashed files are put into the cache.
+///
+/// Images that cannot be hashed are reported, a changed image keeps its old entry
+/// then. Errors of the store or on_match stop
+/// the ingestion; the images before are in the store.
+///
+/// The journal of the store is flushed to disk once per queue_size images instead
+/// of after every change, and when ingest_images returns.
+pub fn ingest_images<F>(
+    paths: &[std::path::PathBuf],
+    store: &mut crate::store::Store,
+    cache: Option<&mut crate::hashcache::HashCache>,
+    options: &IngestOptions,
+    on_match: F,
+) -> crate::Result<IngestReport>
+where
+    F: FnMut(&mut crate::store::Store, &str, &crate::hash::Hash, &crate::matches::Matches) -> crate::Result<bool>,
+{
+    ingest_with(paths, store, cache, options, crate::files::process_image, on_match)
+}
+
+/// ingest_with is ingest_images with the function that hashes an image file.
+pub(crate) fn ingest_with<H, F>(
+    paths: &[std::path::PathBuf],
+    store: &mut crate::store::Store,
+    cache: Option<&mut crate::hashcache::HashCache>,
+    options: &IngestOptions,
+    hash_image: H,
+    mut on_match: F,
+) -> crate::Result<IngestReport>
+where
+    H: Fn(&std::path::Path, crate::hashconfig::HashConfig) -> crate::Result<crate::hash::Hash> + Sync,
+    F: FnMut(&mut crate::store::Store, &str, &crate::hash::Hash, &crate::matches::Matches) -> crate::Result<bool>,
+{
+    let mut report = IngestReport::new();
+    let mut todo = Vec::with_capacity(paths.len());
+    for path in paths {
+        let id = crate::files::osstring_to_string(path.as_os_str());
+        let stored = store.has(&id);
+        if stored {
+            let changed = match cache.as_deref() {
+                Some(cache) => cache.changed(path).unwrap_or(true),
+                None => false,
+            };
+            if !changed {
+                report.skipped += 1;
+                continue;
+            }
+        }
+        // a modified file is compared again, its old entry is replaced once it is hashed
+        todo.push((path.as_path(), id, stored));
+    }
+    if todo.is_empty() {
+        return Ok(report);
+    }
+
+    let num_threads = options
+        .num_threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+        .clamp(1, todo.len());
+    let queue_size = options.queue_size.unwrap_or(2 * num_threads).max(1);
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    // the number of images the writer is done with and whether it stopped
+    let window = (std::sync::Mutex::new((0_usize, false)), std::sync::Condvar::new());
+    let lookup = cache.as_deref();
+    let config = store.config();
+    let mut hashed = Vec::new();
+    let policy = store.sync_policy();
+    store.set_sync_policy(crate::journal::SyncPolicy::OnFlush)?;
+    let result = std::thread::scope(|scope| {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(queue_size);
+        for _ in 0..num_threads {
+            let sender = sender.clone();
+            let (todo, next, window, hash_image) = (&todo, &next, &window, &hash_image);
+            scope.spawn(move || loop {
+                let position = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let Some((path, _, _)) = todo.get(position) else {
+                    break;
+                };
+                // Wait until the image is less than queue_size ahead of the writer.
+                // Positions are taken in order, so the image the writer waits for
+                // is always taken by a worker that does not wait.
+                let (lock, moved) = window;
+                // nothing that can panic holds the lock
+                let guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                let guard = moved
+                    .wait_while(guard, |(written, stopped)| !*stopped && position >= *written + queue_size)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                if guard.1 {
+                    break;
+                }
+                drop(guard);
+                let hash = match lookup.map(|cache| cache.get(path)) {
+                    Some(Ok(Some(hash))) if hash.config == config => Ok((hash, true)),
+                    _ => hash_image(path, config).map(|hash| (hash, false)),
+                };
+                // the writer stopped early if it hung up
+                if sender.send((position, hash)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        // The writer takes the hashes in the order of the paths. Hashes that
+        // arrive early wait in pending, at most queue_size of them.
+        let write = || -> crate::Result<()> {
+            let mut pending = std::collections::BTreeMap::new();
+            let mut position = 0;
+            for (arrived, hash) in receiver {
+                pending.insert(arrived, hash);
+                report.max_pending = report.max_pending.max(pending.len());
+                while let Some(hash) = pending.remove(&position) {
+                    let (path, id, stored) = &todo[position];
+                    position += 1;
+                    let (lock, moved) = &window;
+                    lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).0 = position;
+                    moved.notify_all();
+                    let hash = match hash {
+                        Ok((hash, cached)) => {
+                            if !cached {
+                                hashed.push((*path, hash.to_signature()));
+                            }
+                            hash
+                        }
+                        Err(error) => {
+                            log::error!("Failed to process image {}: {}", path.display(), error);
+                            report.failed.push((path.to_path_buf(), error));
+                            continue;
+                        }
+                    };
+                    if *stored {
+                        store.delete(id)?;
+                    }
+                    let matches = store.query_with(&hash, &options.query);
+                    if !matches.is_empty() && !on_match(store, id, &hash, &matches)? {
+                        report.skipped += 1;
+                        continue;
+                    }
+                    store.add(id, &hash)?;
+                    report.added += 1;
+                    if report.added.is_multiple_of(queue_size) {
+                        store.flush()?;
+                    }
+                }
+            }
+            Ok(())
+        };
+        let written = write();
+        // release the workers that wait for room after an error
+        let (lock, moved) = &window;
+        lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).1 = true;
+        moved.notify_all();
+        written
+    });
+    // restoring the policy flushes the rest
+    let flushed = store.set_sync_policy(policy);
+    if let Some(cache) = cache {
+        for (path, signature) in hashed {
+            cache.insert_signature(path, signature)?;
+        }
+    }
+    result.and(flushed).map(|_| report)
+}
@@ -0,0 +1,315 @@
+//! Package journal provides crash-safe persistence for the Store: atomic
+//! snapshot writes and an append-only journal of the changes made since the
+//! last snapshot.
+use std::io::Write;
+
+/// JOURNAL_MAGIC are the first bytes of every journal file.
+pub const JOURNAL_MAGIC: [u8; 8] = *b"DUPLOJNL";
+/// JOURNAL_FORMAT_VERSION is the version of the record layout of a journal.
+pub const JOURNAL_FORMAT_VERSION: u32 = 1;
+// magic, version and the checksum of the snapshot the journal belongs to
+const JOURNAL_HEADER_LEN: usize = 8 + 4 + 4;
+// tag, payload length and checksum around the payload of a record
+const RECORD_OVERHEAD: usize = 1 + 8 + 4;
+
+const TAG_ADD: u8 = 1;
+const TAG_DELETE: u8 = 2;
+const TAG_EXCHANGE: u8 = 3;
+const TAG_ADD_REGION: u8 = 4;
+
+/// Record is a single change to a Store.
+///
+/// Add:      a candidate and the index lists (locations) it was added to. The
+///           perceptual hashes, the orientation, the file format and the
+///           scaling function coefficient of the candidate follow the locations.
+/// Delete:   the ID of the deleted image.
+/// Exchange: the old and the new ID of an image.
+/// AddRegion: a tile of an image (see region::RegionIndex), its bounding box and
+///           the index lists it was added to, followed by its perceptual hashes.
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub enum Record {
+    Add(crate::candidate::Candidate, Vec<u32>),
+    Delete(String),
+    Exchange(String, String),
+    AddRegion(crate::candidate::Candidate, crate::region::BoundingBox, Vec<u32>),
+}
+
+impl Record {
+    /// encode the record with its tag, length and a CRC-32 of both and the payload.
+    pub fn encode(&self, to: &mut Vec<u8>) {
+        let start = to.len();
+        let mut payload = Vec::new();
+        let tag = match self {
+            Record::Add(candidate, locations) => {
+                candidate.encode(&mut payload);
+                crate::marshal::store_vec_u32(locations, &mut payload);
+                crate::marshal::store_vec_u64(&candidate.perceptual, &mut payload);
+                crate::marshal::store_u32(candidate.orientation as u32, &mut payload);
+                crate::marshal::store_u32(crate::formats::Format::code(candidate.format), &mut payload);
+                crate::marshal::store_vec_f64(&candidate.scale_coeff.c, &mut payload);
+                TAG_ADD
+            }
+            Record::Delete(id) => {
+                crate::marshal::store_string(id, &mut payload);
+                TAG_DELETE
+            }
+            Record::Exchange(oldid, newid) => {
+                crate::marshal::store_string(oldid, &mut payload);
+                crate::marshal::store_string(newid, &mut payload);
+                TAG_EXCHANGE
+            }
+            Record::AddRegion(candidate, region, locations) => {
+                candidate.encode(&mut payload);
+                region.encode(&mut payload);
+                crate::marshal::store_vec_u32(locations, &mut payload);
+                crate::marshal::store_vec_u64(&candidate.perceptual, &mut payload);
+                TAG_ADD_REGION
+            }
+        };
+        to.push(tag);
+        crate::marshal::store_usize(payload.len(), to);
+        to.extend_from_slice(&payload);
+        let checksum = crate::marshal::checksum(&to[start..]);
+        crate::marshal::store_u32(checksum, to);
+    }
+
+    /// decode the record at the start of the given bytes. Returns the record and
+    /// the number of bytes it occupied or an error if it is truncated or damaged.
+    pub fn decode(bytes: &[u8]) -> crate::Result<(Record, usize)> {
+        let (tag, payload, length) = Record::unframe(bytes)?;
+        Ok((Record::decode_payload(tag, payload)?, length))
+    }
+
+    // unframe checks the length and the checksum of the record at the start of the
+    // given bytes and returns its tag, its payload and the number of bytes it occupied.
+    fn unframe(bytes: &[u8]) -> crate::Result<(u8, &[u8], usize)> {
+        if bytes.len() < RECORD_OVERHEAD {
+            return Err(crate::Error::Decode("journal record is truncated".to_string()));
+        }
+        let mut header = std::io::Cursor::new(bytes[1..9].to_vec());
+        let length = crate::marshal::restore_usize(&mut header)?;
+        if bytes.len() - RECORD_OVERHEAD < length {
+            return Err(crate::Error::Decode("journal record is truncated".to_string()));
+        }
+        let end = 9 + length;
+        let mut trailer = std::io::Cursor::new(bytes[end..end + 4].to_vec());
+        let expected = crate::marshal::restore_u32(&mut trailer)?;
+        let found = crate::marshal::checksum(&bytes[..end]);
+        if expected != found {
+            return Err(crate::Error::Checksum { expected, found });
+        }
+        Ok((bytes[0], &bytes[9..end], end + 4))
+    }
+
+    // decode_payload restores the record with the given tag from its payload, which
+    // has to be read completely.
+    fn decode_payload(tag: u8, payload: &[u8]) -> crate::Result<Record> {
+        let mut from = std::io::Cursor::new(payload.to_vec());
+        let record = match tag {
+            TAG_ADD => {
+                let mut candidate = crate::candidate::Candidate::new();
+                candidate.decode(&mut from)?;
+                let locations = crate::marshal::restore_vec_u32(&mut from)?;
+                candidate.perceptual = crate::marshal::restore_vec_u64(&mut from)?;
+                candidate.orientation = crate::marshal::restore_u32(&mut from)? as u8;
+                candidate.format = crate::formats::Format::from_code(crate::marshal::restore_u32(&mut from)?);
+                candidate.scale_coeff.c = crate::marshal::restore_vec_f64(&mut from)?;
+                Record::Add(candidate, locations)
+            }
+            TAG_DELETE => Record::Delete(crate::marshal::restore_string(&mut from)?),
+            TAG_EXCHANGE => {
+                let oldid = crate::marshal::restore_string(&mut from)?;
+                let newid = crate::marshal::restore_string(&mut from)?;
+                Record::Exchange(oldid, newid)
+            }
+            TAG_ADD_REGION => {
+                let mut candidate = crate::candidate::Candidate::new();
+                candidate.decode(&mut from)?;
+                let mut region = crate::region::BoundingBox::new();
+                region.decode(&mut from)?;
+                let locations = crate::marshal::restore_vec_u32(&mut from)?;
+                candidate.perceptual = crate::marshal::restore_vec_u64(&mut from)?;
+                Record::AddRegion(candidate, region, locations)
+            }
+            tag => {
+                return Err(crate::Error::Decode(format!("unknown journal record {}", tag)));
+            }
+        };
+        if from.position() as usize != payload.len() {
+            return Err(crate::Error::Decode(format!(
+                "journal record has {} bytes instead of {}", payload.len(), from.position())));
+        }
+        Ok(record)
+    }
+}
+
+/// SyncPolicy decides when the records appended to a journal are flushed to disk.
+/// Records that are written but not flushed survive a crash of the process, but
+/// not one of the system.
+///
+/// EveryRecord: every record is on disk before append returns.
+/// Every:       every n-th record is flushed together with the ones before it.
+/// OnFlush:     records are only flushed by Journal::flush (see Store::flush).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SyncPolicy {
+    #[default]
+    EveryRecord,
+    Every(usize),
+    OnFlush,
+}
+
+/// Journal is an append-only file of Records belonging to one snapshot of a
+/// Store. The snapshot is identified by the CRC-32 of the snapshot file, so a
+/// journal left over from an older snapshot is never replayed. The file stays
+/// open for appending; clones of a journal append to the same file.
+///
+/// policy:   when appended records are flushed to disk.
+/// file:     the journal file, opened for appending.
+/// unsynced: number of records appended since the last flush.
+#[derive(Clone, Debug)]
+pub struct Journal {
+    pub path: std::path::PathBuf,
+    pub snapshot: u32,
+    pub policy: SyncPolicy,
+    file: std::sync::Arc<std::fs::File>,
+    unsynced: usize,
+}
+
+// journals are the same if they are the same file for the same snapshot
+impl PartialEq for Journal {
+    fn eq(&self, other: &Self) -> bool {
+        (&self.path, self.snapshot, self.policy) == (&other.path, other.snapshot, other.policy)
+    }
+}
+
+impl PartialOrd for Journal {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        (&self.path, self.snapshot, self.policy).partial_cmp(&(&other.path, other.snapshot, other.policy))
+    }
+}
+
+impl Journal {
+    /// path_for returns the path of the journal that belongs to the given store file.
+    pub fn path_for(storefile: &str) -> std::path::PathBuf {
+        std::path::PathBuf::from(format!("{}.journal", storefile))
+    }
+
+    /// create writes a new, empty journal for the given snapshot, replacing any
+    /// journal at that path.
+    pub fn create(path: &std::path::Path, snapshot: u32, policy: SyncPolicy) -> crate::Result<Self> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&JOURNAL_MAGIC);
+        crate::marshal::store_u32(JOURNAL_FORMAT_VERSION, &mut header);
+        crate::marshal::store_u32(snapshot, &mut header);
+        write_atomic(path, &header)?;
+        Journal::append_to(path, snapshot, policy)
+    }
+
+    /// open returns the journal for the given snapshot together with the records
+    /// it contains. A missing journal or one that belongs to another snapshot is
+    /// replaced by an empty journal. A damaged tail, as left behind by a crash
+    /// during an append, is cut off. A record that is complete but cannot be read
+    /// is an error, like a journal of another format version.
+    pub fn open(path: &std::path::Path, snapshot: u32, policy: SyncPolicy) -> crate::Result<(Self, Vec<Record>)> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                return Ok((Journal::create(path, snapshot, policy)?, Vec::new()));
+            }
+            Err(error) => return Err(error.into()),
+        };
+        let mut header = std::io::Cursor::new(bytes.get(8..JOURNAL_HEADER_LEN).unwrap_or(&[]).to_vec());
+        let version = crate::marshal::restore_u32(&mut header).unwrap_or(0);
+        let owner = crate::marshal::restore_u32(&mut header).ok();
+        if !bytes.starts_with(&JOURNAL_MAGIC) || owner != Some(snapshot) {
+            log::warn!("Journal {} does not belong to the store, starting a new one.", path.display());
+            return Ok((Journal::create(path, snapshot, policy)?, Vec::new()));
+        }
+        if version != JOURNAL_FORMAT_VERSION {
+            return Err(crate::Error::Version(version));
+        }
+        let mut records = Vec::new();
+        let mut offset = JOURNAL_HEADER_LEN;
+        while offset < bytes.len() {
+            match Record::unframe(&bytes[offset..]) {
+                Ok((tag, payload, length)) => {
+                    let record = Record::decode_payload(tag, payload).map_err(|error| crate::Error::Decode(format!(
+                        "journal {} has an invalid record after {} records: {}", path.display(), records.len(), error)))?;
+                    records.push(record);
+                    offset += length;
+                }
+                Err(error) => {
+                    log::warn!(
+                        "Journal {} is damaged after {} records ({}), dropping the rest.",
+                        path.display(),
+                        records.len(),
+                        error
+                    );
+                    let file = std::fs::OpenOptions::new().write(true).open(path)?;
+                    file.set_len(offset as u64)?;
+                    file.sync_all()?;
+                    break;
+                }
+            }
+        }
+        Ok((Journal::append_to(path, snapshot, policy)?, records))
+    }
+
+    // append_to opens the existing journal file for appending.
+    fn append_to(path: &std::path::Path, snapshot: u32, policy: SyncPolicy) -> crate::Result<Self> {
+        let file = std::fs::OpenOptions::new().append(true).open(path)?;
+        Ok(Journal {
+            path: path.to_path_buf(),
+            snapshot,
+            policy,
+            file: std::sync::Arc::new(file),
+            unsynced: 0,
+        })
+    }
+
+    /// append writes the record to the end of the journal. It is flushed to disk
+    /// according to the sync policy.
+    pub fn append(&mut self, record: &Record) -> crate::Result<()> {
+        let mut buffer = Vec::new();
+        record.encode(&mut buffer);
+        (&*self.file).write_all(&buffer)?;
+        self.unsynced += 1;
+        match self.policy {
+            SyncPolicy::EveryRecord => self.flush(),
+            SyncPolicy::Every(n) if self.unsynced >= n => self.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    /// flush waits until all appended records are on disk.
+    pub fn flush(&mut self) -> crate::Result<()> {
+        if self.unsynced > 0 {
+            self.file.sync_data()?;
+            self.unsynced = 0;
+        }
+        Ok(())
+    }
+}
+
+/// write_atomic replaces the file at path with the given bytes. The bytes are
+/// written to a temporary file next to it, flushed to disk and renamed over the
+/// target, so readers see either the old or the new file but never a partial one.
+pub fn write_atomic(path: &std::path::Path, bytes: &[u8]) -> crate::Result<()> {
+    let mut tmppath = path.as_os_str().to_owned();
+    tmppath.push(".tmp");
+    let tmppath = std::path::PathBuf::from(tmppath);
+    let mut file = std::fs::File::create(&tmppath)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    std::fs::rename(&tmppath, path)?;
+    // make the rename itself durable
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    };
+    if let Ok(dir) = std::fs::File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
@@ -0,0 +1,246 @@
+//! Package lsh provides locality-sensitive hashing as an alternative way for a
+//! Store to find the candidates of a query. The index lists of the low-frequency
+//! coefficients hold a large share of all images, so scanning them takes time
+//! proportional to the size of the store. LshIndex instead looks up a bounded set
+//! of candidates: MinHash bands over the index lists an image belongs to (its
+//! signature, see Candidate::signature) and the 16 bit substrings of its dHash
+//! (see dhashindex). Only these candidates are scored with the weights of the
+//! index lists they share with the query, like Store::query scores all images.
+
+/// LshConfig are the parameters of an LshIndex.
+///
+/// num_bands:      number of MinHash bands. More bands find more candidates.
+/// rows_per_band:  number of MinHash values per band. More rows make a collision
+///                 of a band require a more similar signature.
+/// max_candidates: the most candidates a query scores. Candidates that collide in
+///                 more bands are preferred. It also caps the candidates taken from
+///                 every band and the dHash substrings: a larger bucket contributes a
+///                 pseudo-random sample of its candidates that does not depend on
+///                 the order they were added in. So queries stay fast with popular
+///                 buckets, at the price of recall: an image that only collides
+///                 with the query in such buckets is found with a probability of
+///                 about max_candidates / bucket size per bucket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LshConfig {
+    pub num_bands: usize,
+    pub rows_per_band: usize,
+    pub max_candidates: usize,
+}
+
+impl Default for LshConfig {
+    fn default() -> LshConfig {
+        LshConfig {
+            num_bands: 20,
+            rows_per_band: 3,
+            max_candidates: 1000,
+        }
+    }
+}
+
+impl LshConfig {
+    pub fn new() -> Self {
+        LshConfig {
+            ..Default::default()
+        }
+    }
+
+    pub fn from(num_bands: usize, rows_per_band: usize, max_candidates: usize) -> Self {
+        LshConfig {
+            num_bands,
+            rows_per_band,
+            max_candidates,
+        }
+    }
+}
+
+/// CandidateGeneration selects how a Store finds the candidates of a query. It is
+/// chosen when the store is constructed (see Store::from).
+///
+/// IndexLists: scan the index lists of the query, the exact search.
+/// Lsh:        look up a bounded set of candidates in an LshIndex. Similar images
+///             may be missed, but the time of a query does not grow with the store.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CandidateGeneration {
+    #[default]
+    IndexLists,
+    Lsh(LshConfig),
+}
+
+/// LshIndex maps the MinHash bands and dHash substrings of the candidates of a
+/// store to their indices.
+///
+/// config: the parameters of the index.
+/// bands:  one table per band, from the hash of the MinHash values of the band to
+///         the bucket of the candidates.
+/// dhash:  the dHash substrings of the candidates.
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+pub struct LshIndex {
+    config: LshConfig,
+    bands: Vec<std::collections::BTreeMap<u64, Bucket>>,
+    dhash: crate::dhashindex::DHashIndex,
+}
+
+/// Bucket holds the candidates that collide in a band.
+///
+/// indices: all candidates of the bucket in the order they were added. Deleted
+///          candidates stay until the index is built again.
+/// sample:  the at most max_candidates candidates that are not deleted and have
+///          the lowest priority in the band, ordered by their priority. Lookups
+///          only take these.
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+struct Bucket {
+    indices: Vec<u32>,
+    sample: Vec<u32>,
+}
+
+impl Bucket {
+    // offer puts the candidate into the sample if it is one of the max_candidates
+    // with the lowest priority.
+    fn offer(&mut self, index: u32, band: usize, max_candidates: usize) {
+        let key = priority(index, band);
+        if self.sample.len() >= max_candidates
+            && self.sample.last().is_none_or(|last| key >= priority(*last, band)) {
+            return;
+        }
+        let position = self.sample.partition_point(|other| priority(*other, band) < key);
+        self.sample.insert(position, index);
+        self.sample.truncate(max_candidates);
+    }
+}
+
+impl LshIndex {
+    pub fn new(config: LshConfig) -> Self {
+        LshIndex {
+            config,
+            bands: vec![std::collections::BTreeMap::new(); config.num_bands],
+            ..Default::default()
+        }
+    }
+
+    /// from builds the index of the candidates, without the deleted ones.
+    pub fn from(config: LshConfig, candidates: &[crate::candidate::Candidate], deleted: &[bool]) -> Self {
+        let mut v = LshIndex::new(config);
+        for (index, candidate) in candidates.iter().enumerate() {
+            if !deleted.get(index).copied().unwrap_or(true) {
+                v.add(index as u32, &candidate.signature, &candidate.dhash);
+            }
+        }
+        v
+    }
+
+    pub fn config(&self) -> LshConfig {
+        self.config
+    }
+
+    /// add adds the candidate with the given index, its signature and its dHash.
+    /// Indices have to be added in ascending order. It takes constant time for
+    /// buckets of any size.
+    pub fn add(&mut self, index: u32, signature: &[u32], dhash: &[u64]) {
+        let max_candidates = self.config.max_candidates;
+        for (band, (table, key)) in self.bands.iter_mut().zip(band_keys(signature, &self.config)).enumerate() {
+            let bucket = table.entry(key).or_default();
+            bucket.indices.push(index);
+            bucket.offer(index, band, max_candidates);
+        }
+        self.dhash.add(index, dhash);
+    }
+
+    /// delete takes the deleted candidate with the given index and signature out
+    /// of the samples of its buckets and refills them. deleted tells which
+    /// candidates are deleted, including this one.
+    pub fn delete(&mut self, index: u32, signature: &[u32], deleted: &[bool]) {
+        for (band, (table, key)) in self.bands.iter_mut().zip(band_keys(signature, &self.config)).enumerate() {
+            let Some(bucket) = table.get_mut(&key) else {
+                continue;
+            };
+            let Some(position) = bucket.sample.iter().position(|other| *other == index) else {
+                continue;
+            };
+            let full = bucket.sample.len() >= self.config.max_candidates;
+            let last = priority(bucket.sample[bucket.sample.len() - 1], band);
+            bucket.sample.remove(position);
+            if full {
+                // all candidates up to the last one of the sample are in it
+                let next = bucket
+                    .indices
+                    .iter()
+                    .filter(|other| !deleted[**other as usize] && priority(**other, band) > last)
+                    .min_by_key(|other| priority(**other, band));
+                if let Some(next) = next {
+                    bucket.sample.push(*next);
+                }
+            }
+        }
+    }
+
+    /// lookup returns the sorted indices of at most max_candidates candidates that
+    /// share a band or a dHash substring with the query, the ones that share the
+    /// most first. Of larger buckets, only the max_candidates candidates of the
+    /// lowest priority are taken (see LshConfig). deleted tells which candidates
+    /// are deleted, they are skipped and take no place of others.
+    pub fn lookup(&self, signature: &[u32], dhash: &[u64], deleted: &[bool]) -> Vec<u32> {
+        let max_candidates = self.config.max_candidates;
+        let dhash_band = self.config.num_bands;
+        let mut collisions: std::collections::BTreeMap<u32, u32> = std::collections::BTreeMap::new();
+        for (table, key) in self.bands.iter().zip(band_keys(signature, &self.config)) {
+            // popular buckets must not make the query slower than max_candidates allows
+            for index in table.get(&key).into_iter().flat_map(|bucket| bucket.sample.iter()) {
+                *collisions.entry(*index).or_default() += 1;
+            }
+        }
+        let mut substrings = self.dhash.lookup(dhash, 0).unwrap_or_default();
+        substrings.retain(|index| !deleted[*index as usize]);
+        if substrings.len() > max_candidates {
+            substrings.select_nth_unstable_by_key(max_candidates.max(1) - 1, |index| priority(*index, dhash_band));
+            substrings.truncate(max_candidates);
+        }
+        for index in substrings {
+            *collisions.entry(index).or_default() += 1;
+        }
+        let mut found: Vec<(u32, u32)> = collisions.into_iter().collect();
+        if found.len() > max_candidates {
+            found.select_nth_unstable_by(max_candidates.max(1) - 1, |a, b| {
+                b.1.cmp(&a.1).then(priority(a.0, dhash_band).cmp(&priority(b.0, dhash_band)))
+            });
+            found.truncate(max_candidates);
+        }
+        let mut found: Vec<u32> = found.into_iter().map(|(index, _)| index).collect();
+        found.sort_unstable();
+        found
+    }
+}
+
+/// band_keys returns the key of every band of the signature: the MinHash values of
+/// the rows of the band, hashed into one value. Empty signatures have no bands.
+fn band_keys(signature: &[u32], config: &LshConfig) -> Vec<u64> {
+    if signature.is_empty() {
+        return Vec::new();
+    }
+    (0..config.num_bands)
+        .map(|band| {
+            (0..config.rows_per_band).fold(band as u64, |key, row| {
+                let seed = mix((band * config.rows_per_band + row) as u64);
+                let minhash = signature.iter().map(|location| mix(*location as u64 ^ seed)).min().unwrap_or(0);
+                mix(key ^ minhash)
+            })
+        })
+        .collect()
+}
+
+/// priority orders the candidates of a bucket of the given band (num_bands for the
+/// dHash substrings). It is a hash of the index, so capped buckets keep a sample
+/// of their candidates that does not favour the ones added first, and every band
+/// keeps another sample.
+fn priority(index: u32, band: usize) -> u64 {
+    mix(index as u64 ^ mix(!(band as u64)))
+}
+
+/// mix is the finalizer of SplitMix64, a cheap hash function with good avalanche.
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
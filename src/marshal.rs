@@ -1,182 +1,111 @@
-use std::io::{Read, Write};
+use std::io::Read;
+
+/// read exactly N bytes from the stream or report which value could not be read.
+fn restore_bytes<const N: usize>(
+    from: &mut std::io::Cursor<Vec<u8>>,
+    what: &str,
+) -> crate::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    from.read_exact(&mut bytes)
+        .map_err(|why| crate::Error::Decode(format!("couldn't read {}: {}", what, why)))?;
+    Ok(bytes)
+}
 
 pub fn store_bool(val: bool, to: &mut Vec<u8>) {
     let u = val as u8;
-    let bytes = u.to_be_bytes();
-    match to.write(&bytes[..]) {
-        Err(why) => panic!("couldn't write bool: {}", why),
-        Ok(retval) => retval,
-    };
-}
-
-pub fn restore_bool(from: &mut std::io::Cursor<Vec<u8>>) -> bool {
-    let mut bytes = [0; 1];
-    match from.read_exact(&mut bytes) {
-        Err(why) => panic!("couldn't read bool: {}", why),
-        Ok(retval) => retval,
-    };
-    let u = u8::from_be_bytes(bytes);
-    let val: bool;
-    if u == 0 {
-        val = false;
-    } else {
-        val = true;
-    }
-    val
+    to.extend_from_slice(&u.to_be_bytes());
+}
+
+pub fn restore_bool(from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<bool> {
+    let bytes = restore_bytes::<1>(from, "bool")?;
+    Ok(u8::from_be_bytes(bytes) != 0)
 }
 
 pub fn store_usize(val: usize, to: &mut Vec<u8>) {
-    let bytes = &val.to_be_bytes();
-    match to.write(&bytes[..]) {
-        Err(why) => panic!("couldn't write usize: {}", why),
-        Ok(retval) => retval,
-    };
+    to.extend_from_slice(&val.to_be_bytes());
 }
 
-pub fn restore_usize(from: &mut std::io::Cursor<Vec<u8>>) -> usize {
-    let mut bytes = [0; 8];
-    match from.read_exact(&mut bytes) {
-        Err(why) => panic!("couldn't read usize: {}", why),
-        Ok(retval) => retval,
-    };
-    let val = usize::from_be_bytes(bytes);
-    val
+pub fn restore_usize(from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<usize> {
+    let bytes = restore_bytes::<8>(from, "usize")?;
+    Ok(usize::from_be_bytes(bytes))
 }
 
 pub fn store_u32(val: u32, to: &mut Vec<u8>) {
-    let bytes = &val.to_be_bytes();
-    match to.write(&bytes[..]) {
-        Err(why) => panic!("couldn't write u32: {}", why),
-        Ok(retval) => retval,
-    };
+    to.extend_from_slice(&val.to_be_bytes());
 }
 
-pub fn restore_u32(from: &mut std::io::Cursor<Vec<u8>>) -> u32 {
-    let mut bytes = [0; 4];
-    match from.read_exact(&mut bytes) {
-        Err(why) => panic!("couldn't read u32: {}", why),
-        Ok(retval) => retval,
-    };
-    let val = u32::from_be_bytes(bytes);
-    val
+pub fn restore_u32(from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<u32> {
+    let bytes = restore_bytes::<4>(from, "u32")?;
+    Ok(u32::from_be_bytes(bytes))
 }
 
 pub fn store_i64(val: i64, to: &mut Vec<u8>) {
-    let bytes = &val.to_be_bytes();
-    match to.write(&bytes[..]) {
-        Err(why) => panic!("couldn't write i32: {}", why),
-        Ok(retval) => retval,
-    };
+    to.extend_from_slice(&val.to_be_bytes());
 }
 
-pub fn restore_i64(from: &mut std::io::Cursor<Vec<u8>>) -> i64 {
-    let mut bytes = [0; 8];
-    match from.read_exact(&mut bytes) {
-        Err(why) => panic!("couldn't read u32: {}", why),
-        Ok(retval) => retval,
-    };
-    let val = i64::from_be_bytes(bytes);
-    val
+pub fn restore_i64(from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<i64> {
+    let bytes = restore_bytes::<8>(from, "i64")?;
+    Ok(i64::from_be_bytes(bytes))
 }
 
 pub fn store_u64(val: u64, to: &mut Vec<u8>) {
-    let bytes = &val.to_be_bytes();
-    match to.write(&bytes[..]) {
-        Err(why) => panic!("couldn't write u64: {}", why),
-        Ok(retval) => retval,
-    };
+    to.extend_from_slice(&val.to_be_bytes());
 }
 
-pub fn restore_u64(from: &mut std::io::Cursor<Vec<u8>>) -> u64 {
-    let mut bytes = [0; 8];
-    match from.read_exact(&mut bytes) {
-        Err(why) => panic!("couldn't read u64: {}", why),
-        Ok(retval) => retval,
-    };
-    let val = u64::from_be_bytes(bytes);
-    val
+pub fn restore_u64(from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<u64> {
+    let bytes = restore_bytes::<8>(from, "u64")?;
+    Ok(u64::from_be_bytes(bytes))
 }
 
 pub fn store_f32(val: f32, to: &mut Vec<u8>) {
-    let bytes = &val.to_be_bytes();
-    match to.write(&bytes[..]) {
-        Err(why) => panic!("couldn't write f32: {}", why),
-        Ok(retval) => retval,
-    };
+    to.extend_from_slice(&val.to_be_bytes());
 }
 
-pub fn restore_f32(from: &mut std::io::Cursor<Vec<u8>>) -> f32 {
-    let mut bytes = [0; 4];
-    match from.read_exact(&mut bytes) {
-        Err(why) => panic!("couldn't read f32: {}", why),
-        Ok(retval) => retval,
-    };
-    let val = f32::from_be_bytes(bytes);
-    val
+pub fn restore_f32(from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<f32> {
+    let bytes = restore_bytes::<4>(from, "f32")?;
+    Ok(f32::from_be_bytes(bytes))
 }
 
 pub fn store_f64(val: f64, to: &mut Vec<u8>) {
-    let bytes = &val.to_be_bytes();
-    match to.write(&bytes[..]) {
-        Err(why) => panic!("couldn't write f64: {}", why),
-        Ok(retval) => retval,
-    };
+    to.extend_from_slice(&val.to_be_bytes());
 }
 
-pub fn restore_f64(from: &mut std::io::Cursor<Vec<u8>>) -> f64 {
-    let mut bytes = [0; 8];
-    match from.read_exact(&mut bytes) {
-        Err(why) => panic!("couldn't read f64: {}", why),
-        Ok(retval) => retval,
-    };
-    let val = f64::from_be_bytes(bytes);
-    val
+pub fn restore_f64(from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<f64> {
+    let bytes = restore_bytes::<8>(from, "f64")?;
+    Ok(f64::from_be_bytes(bytes))
 }
 
-
 pub fn store_char(val: char, to: &mut Vec<u8>) {
     let i = val as u32;
-    let bytes = &i.to_be_bytes();
-    match to.write(&bytes[..]) {
-        Err(why) => panic!("couldn't write char: {}", why),
-        Ok(retval) => retval,
-    };
-}
-
-pub fn restore_char(from: &mut std::io::Cursor<Vec<u8>>) -> char {
-    let mut bytes = [0; 4];
-    match from.read_exact(&mut bytes) {
-        Err(why) => panic!("couldn't read char: {}", why),
-        Ok(retval) => retval,
-    };
-    let i = u32::from_be_bytes(bytes);
+    to.extend_from_slice(&i.to_be_bytes());
+}
 
-    let val = char::from_u32(i);
-    if val.is_none() {
-        return ' ';
-    }
-    val.unwrap()
+pub fn restore_char(from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<char> {
+    let bytes = restore_bytes::<4>(from, "char")?;
+    let i = u32::from_be_bytes(bytes);
+    Ok(char::from_u32(i).unwrap_or(' '))
 }
 
-pub fn store_string(val: &String, to: &mut Vec<u8>) {
-    let s = val.len();
+pub fn store_string(val: &str, to: &mut Vec<u8>) {
+    // the length is the number of chars that follow, not the number of UTF-8 bytes
+    let s = val.chars().count();
     store_usize(s, to);
     for c in val.chars() {
         store_char(c, to);
     }
 }
 
-pub fn restore_string(from: &mut std::io::Cursor<Vec<u8>>) -> String {
+pub fn restore_string(from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<String> {
     let mut val = String::new();
-    let s = restore_usize(from);
+    let s = restore_usize(from)?;
     for _i in 0..s {
-        let c = restore_char(from);
+        let c = restore_char(from)?;
         val.push(c);
     }
-    val
+    Ok(val)
 }
 
-pub fn store_vec_u32(val: &Vec<u32>, to: &mut Vec<u8>) {
+pub fn store_vec_u32(val: &[u32], to: &mut Vec<u8>) {
     let s = val.len();
     store_usize(s, to);
     for elem in val {
@@ -184,17 +113,17 @@ pub fn store_vec_u32(val: &Vec<u32>, to: &mut Vec<u8>) {
     }
 }
 
-pub fn restore_vec_u32(from: &mut std::io::Cursor<Vec<u8>>) -> Vec<u32> {
+pub fn restore_vec_u32(from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<Vec<u32>> {
     let mut val = Vec::new();
-    let s = restore_usize(from);
+    let s = restore_usize(from)?;
     for _i in 0..s {
-        let elem = restore_u32(from);
+        let elem = restore_u32(from)?;
         val.push(elem);
     }
-    val
+    Ok(val)
 }
 
-pub fn store_vec_u64(val: &Vec<u64>, to: &mut Vec<u8>) {
+pub fn store_vec_u64(val: &[u64], to: &mut Vec<u8>) {
     let s = val.len();
     store_usize(s, to);
     for elem in val {
@@ -202,17 +131,17 @@ pub fn store_vec_u64(val: &Vec<u64>, to: &mut Vec<u8>) {
     }
 }
 
-pub fn restore_vec_u64(from: &mut std::io::Cursor<Vec<u8>>) -> Vec<u64> {
+pub fn restore_vec_u64(from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<Vec<u64>> {
     let mut val = Vec::new();
-    let s = restore_usize(from);
+    let s = restore_usize(from)?;
     for _i in 0..s {
-        let elem = restore_u64(from);
+        let elem = restore_u64(from)?;
         val.push(elem);
     }
-    val
+    Ok(val)
 }
 
-pub fn store_vec_f32(val: &Vec<f32>, to: &mut Vec<u8>) {
+pub fn store_vec_f32(val: &[f32], to: &mut Vec<u8>) {
     let s = val.len();
     store_usize(s, to);
     for elem in val {
@@ -220,17 +149,17 @@ pub fn store_vec_f32(val: &Vec<f32>, to: &mut Vec<u8>) {
     }
 }
 
-pub fn restore_vec_f32(from: &mut std::io::Cursor<Vec<u8>>) -> Vec<f32> {
+pub fn restore_vec_f32(from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<Vec<f32>> {
     let mut val = Vec::new();
-    let s = restore_usize(from);
+    let s = restore_usize(from)?;
     for _i in 0..s {
-        let elem = restore_f32(from);
+        let elem = restore_f32(from)?;
         val.push(elem);
     }
-    val
+    Ok(val)
 }
 
-pub fn store_vec_f64(val: &Vec<f64>, to: &mut Vec<u8>) {
+pub fn store_vec_f64(val: &[f64], to: &mut Vec<u8>) {
     let s = val.len();
     store_usize(s, to);
     for elem in val {
@@ -238,34 +167,36 @@ pub fn store_vec_f64(val: &Vec<f64>, to: &mut Vec<u8>) {
     }
 }
 
-pub fn restore_vec_f64(from: &mut std::io::Cursor<Vec<u8>>) -> Vec<f64> {
+pub fn restore_vec_f64(from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<Vec<f64>> {
     let mut val = Vec::new();
-    let s = restore_usize(from);
+    let s = restore_usize(from)?;
     for _i in 0..s {
-        let elem = restore_f64(from);
+        let elem = restore_f64(from)?;
         val.push(elem);
     }
-    val
+    Ok(val)
 }
 
 pub fn store_hash_string_usize(val: &std::collections::BTreeMap<String, usize>, to: &mut Vec<u8>) {
     let t = val.len();
     store_usize(t, to);
     for (key, value) in val {
-        store_string(&key, to);
+        store_string(key, to);
         store_usize(*value, to);
     }
 }
 
-pub fn restore_hash_string_usize(from: &mut std::io::Cursor<Vec<u8>>) -> std::collections::BTreeMap<String, usize> {
+pub fn restore_hash_string_usize(
+    from: &mut std::io::Cursor<Vec<u8>>,
+) -> crate::Result<std::collections::BTreeMap<String, usize>> {
     let mut val = std::collections::BTreeMap::new();
-    let t = restore_usize(from);
+    let t = restore_usize(from)?;
     for _i in 0..t {
-        let key = restore_string(from);
-        let value = restore_usize(from);
+        let key = restore_string(from)?;
+        let value = restore_usize(from)?;
         val.insert(key, value);
     }
-    val
+    Ok(val)
 }
 
 pub fn store_hash_u32_usize(val: &std::collections::BTreeMap<u32, usize>, to: &mut Vec<u8>) {
@@ -277,13 +208,20 @@ pub fn store_hash_u32_usize(val: &std::collections::BTreeMap<u32, usize>, to: &m
     }
 }
 
-pub fn restore_hash_u32_usize(from: &mut std::io::Cursor<Vec<u8>>) -> std::collections::BTreeMap<u32, usize> {
+pub fn restore_hash_u32_usize(
+    from: &mut std::io::Cursor<Vec<u8>>,
+) -> crate::Result<std::collections::BTreeMap<u32, usize>> {
     let mut val = std::collections::BTreeMap::new();
-    let t = restore_usize(from);
+    let t = restore_usize(from)?;
     for _i in 0..t {
-        let key = restore_u32(from);
-        let value = restore_usize(from);
+        let key = restore_u32(from)?;
+        let value = restore_usize(from)?;
         val.insert(key, value);
     }
-    val
+    Ok(val)
+}
+
+/// checksum returns the CRC-32 of the given bytes.
+pub fn checksum(bytes: &[u8]) -> u32 {
+    crc32fast::hash(bytes)
 }
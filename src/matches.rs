@@ -1,16 +1,27 @@
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Match {
     pub id: String,
     pub score: f64, 
     pub ratio_diff: f64,
     pub dhash_distance: i64,
     pub histogram_distance: i64,
+    // perceptual_distances are the distances of the perceptual hashes the store
+    // keeps. They are not encoded.
+    pub perceptual_distances: Vec<(crate::perceptualhash::Algorithm, i64)>,
+    // transform is the rotation or reflection of the query image that matched, see
+    // Store::query_dihedral. It is not encoded.
+    pub transform: crate::dihedral::Dihedral,
+    // region is the approximate bounding box of the matching content, see
+    // Store::query_region and Store::query_contains. It is not encoded.
+    pub region: Option<crate::region::BoundingBox>,
 }
 
 impl Match {
     pub fn new() -> Self {
-        let v = Match{..Default::default()};
-        v
+        Match {
+            ..Default::default()
+        }
     }
 
     pub fn from(id: &str, score: f64, ratio_diff: f64, dhash_distance: i64, histogram_distance: i64) -> Self {
@@ -36,13 +47,13 @@ impl Match {
         crate::marshal::store_i64(self.histogram_distance, to);
     }
 
-    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) {
-        self.id = crate::marshal::restore_string(from);
-        self.score = crate::marshal::restore_f64(from);
-        self.ratio_diff = crate::marshal::restore_f64(from);
-        self.dhash_distance = crate::marshal::restore_i64(from);
-        self.histogram_distance = crate::marshal::restore_i64(from);
-
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        self.id = crate::marshal::restore_string(from)?;
+        self.score = crate::marshal::restore_f64(from)?;
+        self.ratio_diff = crate::marshal::restore_f64(from)?;
+        self.dhash_distance = crate::marshal::restore_i64(from)?;
+        self.histogram_distance = crate::marshal::restore_i64(from)?;
+        Ok(())
     }
 }
 
@@ -54,19 +65,24 @@ impl Default for Match {
             ratio_diff: 0.0,
             dhash_distance: 0,
             histogram_distance: 0,
+            perceptual_distances: Vec::new(),
+            transform: crate::dihedral::Dihedral::Identity,
+            region: None,
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Matches {
     pub m: Vec<Match>,
 }
 
 impl Matches {
     pub fn new() -> Self {
-        let v = Matches {..Default::default()};
-        v
+        Matches {
+            ..Default::default()
+        }
     }
 
     pub fn from(m: Vec<Match>) -> Self {
@@ -76,6 +92,8 @@ impl Matches {
     }
 
     pub fn len(&self) -> usize {self.m.len()}
+
+    pub fn is_empty(&self) -> bool {self.m.is_empty()}
     
     pub fn swap(&mut self, pos1: usize, pos2: usize) {
         let tmp = self.m[pos1].clone();
@@ -90,12 +108,12 @@ impl Matches {
         if testpos >= self.m.len() {
             return false;
         }
-        return self.m[testpos].score < self.m[comparepos].score;
+        self.m[testpos].score < self.m[comparepos].score
     }
 
     pub fn sort(&mut self) {
         // we use Bubble sort until someone wants to spend the time
-        if self.m.len() == 0 {
+        if self.m.is_empty() {
             return;
         }
         for i in 0..self.m.len() - 1 {
@@ -115,22 +133,39 @@ impl Matches {
         }
     }
 
-    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) {
-        let s = crate::marshal::restore_usize(from);
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        let s = crate::marshal::restore_usize(from)?;
         for _i in 0..s {
             let mut elem = Match {
                 ..Default::default()
             };
-            elem.decode(from);
+            elem.decode(from)?;
             self.m.push(elem);
         }
+        Ok(())
     }
 }
 
-impl Default for Matches {
-    fn default() -> Matches {
-        Matches {
-            m: Vec::new(),
+/// best_per_id merges the matches of several queries with the same image. Every ID
+/// is kept once, with its best match. Only the best max_results matches are kept,
+/// sorted so the best match is the first element.
+pub(crate) fn best_per_id<I>(matches: I, max_results: Option<usize>) -> Matches
+where
+    I: IntoIterator<Item = Match>,
+{
+    let mut best: std::collections::BTreeMap<String, Match> = std::collections::BTreeMap::new();
+    for m in matches {
+        match best.get(&m.id) {
+            Some(found) if found.score <= m.score => {}
+            _ => {
+                best.insert(m.id.clone(), m);
+            }
         }
     }
+    let mut ms = Matches::from(best.into_values().collect());
+    ms.sort();
+    if let Some(max_results) = max_results {
+        ms.m.truncate(max_results);
+    }
+    ms
 }
@@ -0,0 +1,257 @@
+//! Package perceptualhash provides perceptual hashes that are independent of the
+//! Haar wavelet coefficients, dHash and histogram of Hash. They are computed from
+//! the same resized image and can confirm Haar matches with a second metric.
+//! Every hash has up to 64 bits and is compared by its Hamming distance.
+
+/// PerceptualHash is an algorithm that turns an image into a hash of up to 64
+/// bits, so that similar images get hashes with a small distance.
+pub trait PerceptualHash {
+    /// algorithm identifies the implementation.
+    fn algorithm(&self) -> Algorithm;
+
+    /// hash calculates the hash of the image. Thumbnails are made with the given
+    /// filter.
+    fn hash(&self, img: &image::RgbaImage, filter: crate::hashconfig::ResizeFilter) -> u64;
+
+    /// distance returns the number of different bits of two hashes.
+    fn distance(&self, left: u64, right: u64) -> i64 {
+        (left ^ right).count_ones() as i64
+    }
+}
+
+/// Algorithm names the implementations of PerceptualHash.
+///
+/// PHash:         signs of the low frequencies of a discrete cosine transform.
+/// AverageHash:   pixels of an 8x8 thumbnail brighter than their mean.
+/// WaveletHash:   coefficients of the Haar low-pass band above their median.
+/// BlockMeanHash: 8x8 blocks of the image brighter than the median block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Algorithm {
+    PHash,
+    AverageHash,
+    WaveletHash,
+    BlockMeanHash,
+}
+
+/// ALGORITHMS are all algorithms in the order their hashes are stored in.
+pub const ALGORITHMS: [Algorithm; 4] = [
+    Algorithm::PHash,
+    Algorithm::AverageHash,
+    Algorithm::WaveletHash,
+    Algorithm::BlockMeanHash,
+];
+
+impl Algorithm {
+    /// hasher returns the implementation of the algorithm.
+    pub fn hasher(&self) -> &'static dyn PerceptualHash {
+        match self {
+            Algorithm::PHash => &PHash,
+            Algorithm::AverageHash => &AverageHash,
+            Algorithm::WaveletHash => &WaveletHash,
+            Algorithm::BlockMeanHash => &BlockMeanHash,
+        }
+    }
+
+    fn bit(&self) -> u32 {
+        1 << ALGORITHMS.iter().position(|algorithm| algorithm == self).unwrap_or(0)
+    }
+}
+
+/// Algorithms is a selection of perceptual hash algorithms.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Algorithms(u32);
+
+impl Algorithms {
+    pub fn new() -> Self {
+        Algorithms(0)
+    }
+
+    pub fn from(algorithms: &[Algorithm]) -> Self {
+        Algorithms(algorithms.iter().fold(0, |bits, algorithm| bits | algorithm.bit()))
+    }
+
+    /// from_bits restores a selection stored with bits.
+    pub fn from_bits(bits: u32) -> crate::Result<Self> {
+        if bits >> ALGORITHMS.len() != 0 {
+            return Err(crate::Error::Decode(format!("unknown perceptual hash algorithms {:#x}", bits)));
+        }
+        Ok(Algorithms(bits))
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(&self, algorithm: Algorithm) -> bool {
+        self.0 & algorithm.bit() != 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// iter returns the selected algorithms in the order of ALGORITHMS.
+    pub fn iter(&self) -> impl Iterator<Item = Algorithm> + '_ {
+        ALGORITHMS.iter().copied().filter(|algorithm| self.contains(*algorithm))
+    }
+
+    /// hashes calculates the hashes of the selected algorithms, with thumbnails made
+    /// with the given filter.
+    pub fn hashes(&self, img: &image::RgbaImage, filter: crate::hashconfig::ResizeFilter) -> Vec<u64> {
+        self.iter().map(|algorithm| algorithm.hasher().hash(img, filter)).collect()
+    }
+
+    /// distances compares two sets of hashes made with the selected algorithms.
+    pub fn distances(&self, left: &[u64], right: &[u64]) -> Vec<(Algorithm, i64)> {
+        self.iter()
+            .zip(left.iter().zip(right.iter()))
+            .map(|(algorithm, (left, right))| (algorithm, algorithm.hasher().distance(*left, *right)))
+            .collect()
+    }
+}
+
+/// luma resizes the image to size x size pixels with the filter and returns their
+/// brightness row by row.
+fn luma(img: &image::RgbaImage, size: u32, filter: crate::hashconfig::ResizeFilter) -> Vec<f64> {
+    let gray = image::imageops::grayscale(img);
+    let small = image::imageops::resize(&gray, size, size, filter.filter_type());
+    small.pixels().map(|pixel| pixel.0[0] as f64).collect()
+}
+
+fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let half = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[half - 1] + sorted[half]) / 2.0
+    } else {
+        sorted[half]
+    }
+}
+
+/// bits sets a bit for every value that is above the threshold.
+fn bits(values: &[f64], threshold: f64) -> u64 {
+    values
+        .iter()
+        .take(64)
+        .enumerate()
+        .fold(0, |bits, (i, value)| if *value > threshold { bits | 1 << i } else { bits })
+}
+
+/// PHash compares the 8x8 lowest frequencies of a discrete cosine transform of a
+/// 32x32 thumbnail with their median. The DC coefficient, the mean brightness, is
+/// left out, so the hash has 63 bits.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PHash;
+
+impl PerceptualHash for PHash {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::PHash
+    }
+
+    fn hash(&self, img: &image::RgbaImage, filter: crate::hashconfig::ResizeFilter) -> u64 {
+        const N: usize = 32;
+        let pixels = luma(img, N as u32, filter);
+        let cosines: Vec<f64> = (0..8 * N)
+            .map(|i| {
+                let (u, x) = (i / N, i % N);
+                (std::f64::consts::PI * u as f64 * (2 * x + 1) as f64 / (2 * N) as f64).cos()
+            })
+            .collect();
+        let mut low = Vec::with_capacity(64);
+        for v in 0..8 {
+            for u in 0..8 {
+                let mut sum = 0.0;
+                for y in 0..N {
+                    for x in 0..N {
+                        sum += pixels[y * N + x] * cosines[u * N + x] * cosines[v * N + y];
+                    }
+                }
+                low.push(sum);
+            }
+        }
+        bits(&low[1..], median(&low[1..]))
+    }
+}
+
+/// AverageHash compares the pixels of an 8x8 thumbnail with their mean.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AverageHash;
+
+impl PerceptualHash for AverageHash {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::AverageHash
+    }
+
+    fn hash(&self, img: &image::RgbaImage, filter: crate::hashconfig::ResizeFilter) -> u64 {
+        let pixels = luma(img, 8, filter);
+        let mean = pixels.iter().sum::<f64>() / pixels.len() as f64;
+        bits(&pixels, mean)
+    }
+}
+
+/// WaveletHash compares the 8x8 low-pass band of a Haar wavelet decomposition of
+/// a 64x64 thumbnail with its median, after the mean brightness was removed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WaveletHash;
+
+impl PerceptualHash for WaveletHash {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::WaveletHash
+    }
+
+    fn hash(&self, img: &image::RgbaImage, filter: crate::hashconfig::ResizeFilter) -> u64 {
+        let mut size = 64;
+        let mut band = luma(img, size as u32, filter);
+        // every level keeps the averages of 2x2 pixels
+        while size > 8 {
+            let half = size / 2;
+            band = (0..half * half)
+                .map(|i| {
+                    let (y, x) = (2 * (i / half), 2 * (i % half));
+                    (band[y * size + x] + band[y * size + x + 1]
+                        + band[(y + 1) * size + x] + band[(y + 1) * size + x + 1]) / 2.0
+                })
+                .collect();
+            size = half;
+        }
+        let mean = band.iter().sum::<f64>() / band.len() as f64;
+        let detail: Vec<f64> = band.iter().map(|value| value - mean).collect();
+        bits(&detail, median(&detail))
+    }
+}
+
+/// BlockMeanHash divides the image into 8x8 blocks and compares the mean
+/// brightness of every block with the median of all blocks. It needs no thumbnail.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BlockMeanHash;
+
+impl PerceptualHash for BlockMeanHash {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::BlockMeanHash
+    }
+
+    fn hash(&self, img: &image::RgbaImage, _filter: crate::hashconfig::ResizeFilter) -> u64 {
+        let gray = image::imageops::grayscale(img);
+        let (width, height) = (gray.width().max(1), gray.height().max(1));
+        let mut sums = [0.0_f64; 64];
+        let mut counts = [0_u32; 64];
+        for (x, y, pixel) in gray.enumerate_pixels() {
+            let block = (y * 8 / height * 8 + x * 8 / width) as usize;
+            sums[block] += pixel.0[0] as f64;
+            counts[block] += 1;
+        }
+        let means: Vec<f64> = sums
+            .iter()
+            .zip(counts.iter())
+            .map(|(sum, count)| if *count > 0 { sum / *count as f64 } else { 0.0 })
+            .collect();
+        bits(&means, median(&means))
+    }
+}
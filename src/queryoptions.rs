@@ -0,0 +1,103 @@
+/// QueryOptions make a query stricter than the sensitivity of the store.
+///
+/// max_results:              return at most this many matches, the best ones.
+/// score_threshold:          only return matches with a lower score. Replaces the
+///                           score threshold of the store's sensitivity for this query.
+/// max_dhash_distance:       only return matches with at most this dHash distance.
+/// max_histogram_distance:   only return matches with at most this histogram distance.
+/// max_ratio_diff:           only return matches whose width to height ratio differs
+///                           by at most this much (difference of the log10 ratios).
+///                           Images of stores migrated from format version 1 have
+///                           no known ratio and are left out.
+/// max_perceptual_distances: only return matches with at most this distance of the
+///                           given perceptual hashes. The store must keep them (see
+///                           HashConfig::perceptual), otherwise nothing is returned.
+/// exclude_id:               never return this ID, e.g. the ID of the query image itself.
+/// num_threads:              number of threads scanning the index lists. By default
+///                           large stores use one thread per CPU.
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QueryOptions {
+    pub max_results: Option<usize>,
+    pub score_threshold: Option<f64>,
+    pub max_dhash_distance: Option<i64>,
+    pub max_histogram_distance: Option<i64>,
+    pub max_ratio_diff: Option<f64>,
+    pub max_perceptual_distances: Vec<(crate::perceptualhash::Algorithm, i64)>,
+    pub exclude_id: Option<String>,
+    pub num_threads: Option<usize>,
+}
+
+impl QueryOptions {
+    pub fn new() -> Self {
+        QueryOptions {
+            ..Default::default()
+        }
+    }
+
+    /// accepts tells whether the match passes the filters on the secondary metrics.
+    pub fn accepts(&self, m: &crate::matches::Match) -> bool {
+        if let Some(max) = self.max_dhash_distance {
+            if m.dhash_distance > max {
+                return false;
+            }
+        }
+        if let Some(max) = self.max_histogram_distance {
+            if m.histogram_distance > max {
+                return false;
+            }
+        }
+        if let Some(max) = self.max_ratio_diff {
+            if m.ratio_diff.is_nan() || m.ratio_diff > max {
+                return false;
+            }
+        }
+        for (algorithm, max) in self.max_perceptual_distances.iter() {
+            match m.perceptual_distances.iter().find(|(found, _)| found == algorithm) {
+                Some((_, distance)) if distance <= max => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+/// select_matches creates the matches for all scored candidates that pass the
+/// sensitivity and the filters of the options. Candidates for which skip returns true
+/// are left out. create builds the match of the candidate with the given index and
+/// score. Only the best max_results matches are kept, sorted so the best match is
+/// the first element.
+pub(crate) fn select_matches<S, F>(
+    scores: &[f64],
+    sensitivity: &crate::sensitivity::Sensitivity,
+    options: &QueryOptions,
+    skip: S,
+    create: F,
+) -> crate::matches::Matches
+where
+    S: Fn(usize) -> bool,
+    F: Fn(usize, f64) -> crate::matches::Match,
+{
+    let threshold = options.score_threshold.unwrap_or(sensitivity.score_threshold);
+    let mut ms = crate::matches::Matches::new();
+    for (index, &score) in scores.iter().enumerate() {
+        if score.is_nan() || score >= threshold || skip(index) {
+            continue;
+        }
+        let m = create(index, score);
+        if options.accepts(&m) && sensitivity.accepts(m.dhash_distance, m.histogram_distance) {
+            ms.m.push(m);
+        }
+    }
+    if let Some(max_results) = options.max_results {
+        if max_results == 0 {
+            ms.m.clear();
+        } else if ms.m.len() > max_results {
+            ms.m.select_nth_unstable_by(max_results - 1, |a, b| a.score.total_cmp(&b.score));
+            ms.m.truncate(max_results);
+        }
+    }
+    // sort the vector so the first match is the one with the lowest value --> the best match
+    ms.sort();
+    ms
+}
@@ -0,0 +1,288 @@
+//! Package region provides crop-tolerant matching. The hash of an image is
+//! global, an image that was cropped or embedded in a larger screenshot gets
+//! different coefficients. So images are also hashed in overlapping tiles of
+//! several sizes. The tiles of stored images are kept in a RegionIndex next to
+//! the global index of the Store (see Store::add_regions and Store::query_region),
+//! the tiles of a query image are compared with the global hashes of the stored
+//! images (see Store::query_contains).
+
+/// REGION_SIZES are the side lengths of the tiles, as fractions of the width and
+/// height of the image. Tiles of one size overlap by half of their side length.
+pub const REGION_SIZES: [f64; 3] = [0.8, 0.6, 0.4];
+
+/// BoundingBox is a rectangle inside an image. All values are fractions of the
+/// width and height of the image, so they do not depend on its resolution.
+///
+/// left:   distance of the left edge from the left border of the image.
+/// top:    distance of the top edge from the top border of the image.
+/// width:  width of the rectangle.
+/// height: height of the rectangle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BoundingBox {
+    pub left: f64,
+    pub top: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl BoundingBox {
+    pub fn new() -> Self {
+        BoundingBox {
+            ..Default::default()
+        }
+    }
+
+    pub fn from(left: f64, top: f64, width: f64, height: f64) -> Self {
+        BoundingBox {
+            left,
+            top,
+            width,
+            height,
+        }
+    }
+
+    /// pixels returns the rectangle in pixels of an image of the given size as
+    /// x, y, width and height. The rectangle is at least one pixel large and
+    /// inside the image, unless the image has no pixels.
+    pub fn pixels(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let scale = |value: f64, size: u32| (value.clamp(0.0, 1.0) * size as f64).round() as u32;
+        let x = scale(self.left, width).min(width.saturating_sub(1));
+        let y = scale(self.top, height).min(height.saturating_sub(1));
+        let w = scale(self.width, width).max(1).min(width - x);
+        let h = scale(self.height, height).max(1).min(height - y);
+        (x, y, w, h)
+    }
+
+    /// crop returns the part of the image inside the rectangle.
+    pub fn crop(&self, img: &image::RgbaImage) -> image::RgbaImage {
+        let (x, y, width, height) = self.pixels(img.width(), img.height());
+        image::imageops::crop_imm(img, x, y, width, height).to_image()
+    }
+
+    pub fn encode(&self, to: &mut Vec<u8>) {
+        crate::marshal::store_f64(self.left, to);
+        crate::marshal::store_f64(self.top, to);
+        crate::marshal::store_f64(self.width, to);
+        crate::marshal::store_f64(self.height, to);
+    }
+
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        self.left = crate::marshal::restore_f64(from)?;
+        self.top = crate::marshal::restore_f64(from)?;
+        self.width = crate::marshal::restore_f64(from)?;
+        self.height = crate::marshal::restore_f64(from)?;
+        Ok(())
+    }
+}
+
+/// tiles returns the tiles of all REGION_SIZES. The last tile of every row and
+/// column ends at the border of the image.
+pub fn tiles() -> Vec<BoundingBox> {
+    let mut tiles = Vec::new();
+    for size in REGION_SIZES {
+        let mut offsets = Vec::new();
+        let mut offset = 0.0;
+        while offset + size < 1.0 - f64::EPSILON {
+            offsets.push(offset);
+            offset += size / 2.0;
+        }
+        offsets.push(1.0 - size);
+        for top in offsets.iter() {
+            for left in offsets.iter() {
+                tiles.push(BoundingBox::from(*left, *top, size, size));
+            }
+        }
+    }
+    tiles
+}
+
+/// create_region_hashes calculates the hashes of all tiles of the provided image.
+/// Images without pixels have no tiles and are rejected like by create_hash.
+pub fn create_region_hashes(img: &image::RgbaImage,
+                            config: crate::hashconfig::HashConfig)
+                            -> crate::Result<Vec<(BoundingBox, crate::hash::Hash)>> {
+    if img.width() == 0 || img.height() == 0 {
+        return Err(crate::Error::Image(image::ImageError::Parameter(
+            image::error::ParameterError::from_kind(
+                image::error::ParameterErrorKind::DimensionMismatch,
+            ),
+        )));
+    }
+    let mut hashes = Vec::new();
+    for tile in tiles() {
+        let (hash, _) = crate::hash::create_hash(&tile.crop(img), config)?;
+        hashes.push((tile, hash));
+    }
+    Ok(hashes)
+}
+
+/// RegionIndex holds the tiles of the images in a Store, indexed like the store
+/// itself (see Store::indices). Every tile is a candidate with the ID of the image
+/// it belongs to.
+///
+/// candidates: the tiles. Tiles of deleted images have an empty ID until compact
+///             removes them.
+/// regions:    the bounding box of every tile inside its image.
+/// indices:    the index lists of the tiles. They stay empty until the first tile
+///             is added.
+/// ids:        the indices of the tiles of every image.
+/// live:       the number of tiles of images that are not deleted.
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+pub struct RegionIndex {
+    pub candidates: Vec<crate::candidate::Candidate>,
+    pub regions: Vec<BoundingBox>,
+    pub indices: Vec<Vec<u32>>,
+    ids: std::collections::BTreeMap<String, Vec<u32>>,
+    live: usize,
+}
+
+impl RegionIndex {
+    pub fn new() -> Self {
+        RegionIndex {
+            ..Default::default()
+        }
+    }
+
+    /// len returns the number of tiles, without the ones of deleted images.
+    pub fn len(&self) -> usize {
+        self.live
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// tiles_of returns the tiles of the image with the given ID.
+    pub fn tiles_of(&self, id: &str) -> Vec<(&crate::candidate::Candidate, BoundingBox)> {
+        self.ids
+            .get(id)
+            .into_iter()
+            .flatten()
+            .map(|index| (&self.candidates[*index as usize], self.regions[*index as usize]))
+            .collect()
+    }
+
+    /// add adds a tile to the given index lists of a store with num_indices lists.
+    /// Tiles are scored the classic way, their scaling function coefficient is not kept.
+    pub(crate) fn add(&mut self, mut candidate: crate::candidate::Candidate, region: BoundingBox,
+                      locations: Vec<u32>, num_indices: u32) {
+        if self.indices.is_empty() {
+            self.indices = vec![Vec::new(); num_indices as usize];
+        }
+        candidate.scale_coeff.c.clear();
+        candidate.signature = locations.clone();
+        candidate.signature.sort_unstable();
+        let index = self.candidates.len() as u32;
+        self.ids.entry(candidate.id.clone()).or_default().push(index);
+        self.live += 1;
+        self.candidates.push(candidate);
+        self.regions.push(region);
+        for location in locations {
+            self.indices[location as usize].push(index);
+        }
+    }
+
+    /// delete marks all tiles of the image with the given ID as deleted.
+    pub(crate) fn delete(&mut self, id: &str) {
+        for index in self.ids.remove(id).unwrap_or_default() {
+            self.candidates[index as usize] = crate::candidate::Candidate::new();
+            self.live -= 1;
+        }
+    }
+
+    /// exchange moves all tiles of an image to its new ID.
+    pub(crate) fn exchange(&mut self, oldid: &str, newid: &str) {
+        let Some(indices) = self.ids.remove(oldid) else {
+            return;
+        };
+        for index in indices.iter() {
+            self.candidates[*index as usize].id = newid.to_string();
+        }
+        self.ids.entry(newid.to_string()).or_default().extend(indices);
+    }
+
+    /// compact removes the deleted tiles like Store::compact.
+    pub(crate) fn compact(&mut self) {
+        let mut renumber = vec![u32::MAX; self.candidates.len()];
+        let mut candidates = Vec::new();
+        let mut regions = Vec::new();
+        let tiles = std::mem::take(&mut self.candidates).into_iter().zip(self.regions.iter());
+        for (index, (candidate, region)) in tiles.enumerate() {
+            if !candidate.id.is_empty() {
+                renumber[index] = candidates.len() as u32;
+                candidates.push(candidate);
+                regions.push(*region);
+            }
+        }
+        for list in self.indices.iter_mut() {
+            list.retain(|index| renumber[*index as usize] != u32::MAX);
+            for index in list.iter_mut() {
+                *index = renumber[*index as usize];
+            }
+        }
+        for indices in self.ids.values_mut() {
+            for index in indices.iter_mut() {
+                *index = renumber[*index as usize];
+            }
+        }
+        self.candidates = candidates;
+        self.regions = regions;
+    }
+
+    pub fn encode(&self, to: &mut Vec<u8>) {
+        crate::marshal::store_usize(self.candidates.len(), to);
+        for (candidate, region) in self.candidates.iter().zip(self.regions.iter()) {
+            candidate.encode(to);
+            region.encode(to);
+            crate::marshal::store_vec_u64(&candidate.perceptual, to);
+        }
+        crate::marshal::store_usize(self.indices.len(), to);
+        for elem in &self.indices {
+            crate::marshal::store_vec_u32(elem, to);
+        }
+    }
+
+    /// decode restores the tiles and checks that the index lists are sorted and
+    /// point to existing tiles. The signatures are restored from the index lists.
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        let mut v = RegionIndex::new();
+        let s = crate::marshal::restore_usize(from)?;
+        for _i in 0..s {
+            let mut candidate = crate::candidate::Candidate::new();
+            candidate.decode(from)?;
+            let mut region = BoundingBox::new();
+            region.decode(from)?;
+            candidate.perceptual = crate::marshal::restore_vec_u64(from)?;
+            candidate.scale_coeff.c.clear();
+            v.candidates.push(candidate);
+            v.regions.push(region);
+        }
+        let s = crate::marshal::restore_usize(from)?;
+        for _i in 0..s {
+            v.indices.push(crate::marshal::restore_vec_u32(from)?);
+        }
+        for (location, list) in v.indices.iter().enumerate() {
+            if list.last().is_some_and(|index| *index as usize >= v.candidates.len()) {
+                return Err(crate::Error::Decode(format!(
+                    "region index list points to an invalid tile {}", list[list.len() - 1])));
+            }
+            if list.windows(2).any(|pair| pair[0] >= pair[1]) {
+                return Err(crate::Error::Decode("region index list is not sorted".to_string()));
+            }
+            for &index in list {
+                if !v.candidates[index as usize].id.is_empty() {
+                    v.candidates[index as usize].signature.push(location as u32);
+                }
+            }
+        }
+        for (index, candidate) in v.candidates.iter().enumerate() {
+            if !candidate.id.is_empty() {
+                v.ids.entry(candidate.id.clone()).or_default().push(index as u32);
+                v.live += 1;
+            }
+        }
+        *self = v;
+        Ok(())
+    }
+}
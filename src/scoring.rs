@@ -0,0 +1,89 @@
+//! Package scoring selects how a Store scores the images a query finds. Jacobs et
+//! al. score the difference of the scaling function coefficients (the average
+//! colour of the images) with the first weight of every colour channel. Earlier
+//! releases skip it and start every image with the sum of these weights, so images
+//! with the same structure but a different overall colour score the same. This
+//! classic scoring stays the default, the paper scoring can be selected per store.
+
+/// ScoringProfile are the terms added to the score of the index lists an image
+/// shares with the query.
+///
+/// scale_coefficient: score the difference of the average colours like the paper
+///                    instead of adding the sum of their weights.
+/// histo_max_penalty: weight of the difference of the histogram maxima (see
+///                    Hash::histo_max) relative to the number of pixels of the
+///                    resized images. 0 leaves them out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScoringProfile {
+    pub scale_coefficient: bool,
+    pub histo_max_penalty: f64,
+}
+
+impl ScoringProfile {
+    /// new returns the classic scoring of earlier releases.
+    pub fn new() -> Self {
+        ScoringProfile {
+            ..Default::default()
+        }
+    }
+
+    pub fn from(scale_coefficient: bool, histo_max_penalty: f64) -> Self {
+        ScoringProfile {
+            scale_coefficient,
+            histo_max_penalty,
+        }
+    }
+
+    /// paper returns the scoring of Jacobs et al., without histogram penalty.
+    pub fn paper() -> Self {
+        ScoringProfile::from(true, 0.0)
+    }
+
+    /// is_classic tells whether the profile scores like earlier releases.
+    pub fn is_classic(&self) -> bool {
+        !self.scale_coefficient && self.histo_max_penalty == 0.0
+    }
+
+    /// adjust turns the classic score of a candidate with the given scaling function
+    /// coefficient and histogram maxima into the score of this profile. Candidates
+    /// or queries without a scaling function coefficient (an empty one, see
+    /// Candidate::scale_coeff) keep the classic term. Candidates that share no
+    /// index list with the query (NAN) stay that way.
+    pub(crate) fn adjust(&self, score: f64, scale_coeff: &crate::haar::Coef, histo_max: &[f32],
+                         hash: &crate::hash::Hash) -> f64 {
+        if score.is_nan() {
+            return score;
+        }
+        let mut score = score;
+        let query_coeff = hash.matrix.coefs.first().filter(|coeff| !coeff.c.is_empty());
+        if self.scale_coefficient && !scale_coeff.c.is_empty() {
+            if let Some(query_coeff) = query_coeff {
+                score += crate::store::scale_coefficient_score(query_coeff, scale_coeff, hash.config.scale)
+                         - crate::store::initial_score();
+            }
+        }
+        if self.histo_max_penalty != 0.0 {
+            let pixels = (hash.config.scale * hash.config.scale) as f64;
+            let distance: f64 = histo_max
+                .iter()
+                .zip(hash.histo_max.iter())
+                .take(crate::haar::COLOURCHANNELS as usize)
+                .map(|(a, b)| (*a as f64 - *b as f64).abs())
+                .sum();
+            score += self.histo_max_penalty * distance / pixels;
+        }
+        score
+    }
+
+    pub fn encode(&self, to: &mut Vec<u8>) {
+        crate::marshal::store_bool(self.scale_coefficient, to);
+        crate::marshal::store_f64(self.histo_max_penalty, to);
+    }
+
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        self.scale_coefficient = crate::marshal::restore_bool(from)?;
+        self.histo_max_penalty = crate::marshal::restore_f64(from)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+//! Package sensitivity decides how similar two images or screenshots have to be
+//! to be reported as a match. A level between 0 and 100 is calibrated to the
+//! thresholds of the score and the secondary metrics:
+//!
+//!   level 0:   score below -60, dHash distance up to 64 of 128 bits and histogram
+//!              distance up to 32 of 64 bits. Allows quite heavy modifications.
+//!   level 100: score below -160, dHash distance up to 8 and histogram distance up
+//!              to 4. Only resized images are seen as similar.
+//!
+//! All thresholds change linearly in between.
+
+/// SCORE_THRESHOLD_LOOSE is the score threshold of level 0.
+pub const SCORE_THRESHOLD_LOOSE: f64 = -60.0;
+/// SCORE_THRESHOLD_STRICT is the score threshold of level 100.
+pub const SCORE_THRESHOLD_STRICT: f64 = -160.0;
+
+/// Sensitivity are the thresholds a match has to pass.
+///
+/// score_threshold:        only candidates with a lower score are matches.
+/// max_dhash_distance:     only candidates with at most this dHash distance are matches.
+/// max_histogram_distance: only candidates with at most this histogram distance are matches.
+///
+/// Use from to get calibrated thresholds for a level between 0 and 100.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sensitivity {
+    pub score_threshold: f64,
+    pub max_dhash_distance: i64,
+    pub max_histogram_distance: i64,
+}
+
+impl Default for Sensitivity {
+    fn default() -> Sensitivity {
+        Sensitivity::from(0.0)
+    }
+}
+
+impl Sensitivity {
+    pub fn new() -> Self {
+        Sensitivity {
+            ..Default::default()
+        }
+    }
+
+    /// from calibrates the thresholds for the given level. 0 allows the most
+    /// differences, 100 the least. Levels outside are clamped.
+    pub fn from(level: f64) -> Self {
+        let level = if level.is_nan() { 0.0 } else { level.clamp(0.0, 100.0) } / 100.0;
+        Sensitivity {
+            score_threshold: SCORE_THRESHOLD_LOOSE + level * (SCORE_THRESHOLD_STRICT - SCORE_THRESHOLD_LOOSE),
+            max_dhash_distance: (64.0 - level * 56.0).round() as i64,
+            max_histogram_distance: (32.0 - level * 28.0).round() as i64,
+        }
+    }
+
+    /// from_score_threshold uses the raw score threshold of earlier releases and
+    /// does not limit the secondary metrics.
+    pub fn from_score_threshold(score_threshold: f64) -> Self {
+        Sensitivity {
+            score_threshold,
+            max_dhash_distance: i64::MAX,
+            max_histogram_distance: i64::MAX,
+        }
+    }
+
+    /// accepts tells whether the distances of the secondary metrics pass.
+    pub fn accepts(&self, dhash_distance: i64, histogram_distance: i64) -> bool {
+        dhash_distance <= self.max_dhash_distance && histogram_distance <= self.max_histogram_distance
+    }
+
+    pub fn encode(&self, to: &mut Vec<u8>) {
+        crate::marshal::store_f64(self.score_threshold, to);
+        crate::marshal::store_i64(self.max_dhash_distance, to);
+        crate::marshal::store_i64(self.max_histogram_distance, to);
+    }
+
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        self.score_threshold = crate::marshal::restore_f64(from)?;
+        self.max_dhash_distance = crate::marshal::restore_i64(from)?;
+        self.max_histogram_distance = crate::marshal::restore_i64(from)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+/// SharedStore is a Store that can be shared between threads. Any number of
+/// threads can query it at the same time while changes wait for exclusive
+/// access. Cloning a SharedStore gives another handle to the same store.
+///
+/// If a thread panicked while changing the store, the lock is recovered and the
+/// store is used as the panicking thread left it. The recovery is logged as an
+/// error, once per panic.
+#[derive(Clone, Debug)]
+pub struct SharedStore {
+    store: std::sync::Arc<std::sync::RwLock<crate::store::Store>>,
+}
+
+impl SharedStore {
+    pub fn new(sensitivity: crate::sensitivity::Sensitivity, config: crate::hashconfig::HashConfig) -> Self {
+        SharedStore::from(crate::store::Store::new(sensitivity, config))
+    }
+
+    pub fn from(store: crate::store::Store) -> Self {
+        SharedStore {
+            store: std::sync::Arc::new(std::sync::RwLock::new(store)),
+        }
+    }
+
+    /// read locks the store for reading, e.g. for several queries in a row.
+    pub fn read(&self) -> std::sync::RwLockReadGuard<'_, crate::store::Store> {
+        self.store.read().unwrap_or_else(|poisoned| {
+            self.recover();
+            poisoned.into_inner()
+        })
+    }
+
+    /// write locks the store for changes.
+    pub fn write(&self) -> std::sync::RwLockWriteGuard<'_, crate::store::Store> {
+        self.store.write().unwrap_or_else(|poisoned| {
+            self.recover();
+            poisoned.into_inner()
+        })
+    }
+
+    // recover logs that a thread panicked while it held the lock and clears the
+    // poison, so later locks do not log it again
+    fn recover(&self) {
+        log::error!("A thread panicked while changing the store, it may be inconsistent.");
+        self.store.clear_poison();
+    }
+
+    pub fn has(&self, id: &str) -> bool {
+        self.read().has(id)
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        self.read().ids()
+    }
+
+    pub fn size(&self) -> usize {
+        self.read().size()
+    }
+
+    /// see Store::add
+    pub fn add(&self, id: &str, hash: &crate::hash::Hash) -> crate::Result<()> {
+        self.write().add(id, hash)
+    }
+
+    /// see Store::delete
+    pub fn delete(&self, id: &str) -> crate::Result<()> {
+        self.write().delete(id)
+    }
+
+    /// see Store::exchange
+    pub fn exchange(&self, oldid: &str, newid: &str) -> crate::Result<bool> {
+        self.write().exchange(oldid, newid)
+    }
+
+    /// see Store::merge
+    pub fn merge(&self, other: &crate::store::Store, policy: crate::store::MergePolicy) -> crate::Result<usize> {
+        self.write().merge(other, policy)
+    }
+
+    /// see Store::compact
+    pub fn compact(&self) {
+        self.write().compact()
+    }
+
+    /// see Store::query
+    pub fn query(&self, hash: &crate::hash::Hash) -> crate::matches::Matches {
+        self.read().query(hash)
+    }
+
+    /// see Store::query_with
+    pub fn query_with(&self, hash: &crate::hash::Hash,
+                      options: &crate::queryoptions::QueryOptions) -> crate::matches::Matches {
+        self.read().query_with(hash, options)
+    }
+
+    /// see Store::query_by_id
+    pub fn query_by_id(&self, id: &str,
+                       options: &crate::queryoptions::QueryOptions) -> Option<crate::matches::Matches> {
+        self.read().query_by_id(id, options)
+    }
+
+    /// see Store::neighbours
+    pub fn neighbours(&self, id: &str) -> Option<crate::matches::Matches> {
+        self.read().neighbours(id)
+    }
+
+    /// see Store::dump_binary
+    pub fn dump_binary(&self, storefile: &str) -> crate::Result<()> {
+        self.write().dump_binary(storefile)
+    }
+
+    /// see Store::slurp_binary
+    pub fn slurp_binary(&self, storefile: &str) -> crate::Result<()> {
+        self.write().slurp_binary(storefile)
+    }
+}
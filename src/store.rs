@@ -1,15 +1,40 @@
 //use array2d::{Array2D, Error};
-use std::io::{Read, Write};
-use log;
+use std::io::Read;
 
 static WEIGHTS: [[f64;6];3] = [[5.00_f64, 0.83, 1.01, 0.52, 0.47, 0.30], 
                 [19.21, 1.26, 0.44, 0.53, 0.28, 0.14],
                 [34.37, 0.36, 0.45, 0.14, 0.18, 0.27]];
-pub const IMAGESCALE: u32 = 128;
-pub const INDICESMAX: u32 = 98400;
-pub static TOPCOEFS: i32 = 40;
-static WEIGHTSUMS: [f64;6] = [58.58 as f64, 2.45, 1.9, 1.19, 0.93, 0.71];
+static WEIGHTSUMS: [f64;6] = [58.58_f64, 2.45, 1.9, 1.19, 0.93, 0.71];
 pub static CTRL_C_PRESSED: bool = false;
+/// STORE_MAGIC are the first bytes of every store file written by dump_binary.
+pub const STORE_MAGIC: [u8; 8] = *b"DUPLOSTR";
+/// STORE_FORMAT_VERSION is the version of the store file format written by dump_binary.
+/// Version 1 is the headerless format of earlier releases, which is still read and
+/// migrated on the next dump. It does not record the hash config, the limits of
+/// the secondary metrics, the perceptual hashes, the region index, the orientation,
+/// file format and scaling function coefficient of the images nor the scoring
+/// profile, and its width to height ratios were rounded down to integers. These
+/// are unknown (NAN) after the migration until the images are added again.
+pub const STORE_FORMAT_VERSION: u32 = 2;
+// magic, version and payload length
+const STORE_HEADER_LEN: usize = 8 + 4 + 8;
+/// PARALLEL_QUERY_MIN_CANDIDATES is the store size from which a query scans the index
+/// lists with one thread per CPU, unless QueryOptions::num_threads says otherwise.
+pub const PARALLEL_QUERY_MIN_CANDIDATES: usize = 100_000;
+
+/// MergePolicy decides what Store::merge does with an ID that is in both stores.
+///
+/// KeepExisting: keep the image of the store that is merged into.
+/// Replace:      replace it with the image of the other store.
+/// Fail:         do not merge anything and return an error.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MergePolicy {
+    #[default]
+    KeepExisting,
+    Replace,
+    Fail,
+}
 
 /// Store is a data structure that holds references to images. It holds visual
 /// hashes and references to the images but the images themselves are not held
@@ -27,32 +52,61 @@ pub static CTRL_C_PRESSED: bool = false;
 /// of slices which contains image indices (into the "candidates" slice).
 /// Use the following formula to access an index slice:
 ///
-///	store.indices[sign*ImageScale*ImageScale*haar.ColourChannels + coefIdx*haar.ColourChannels + channel]
+///   store.indices[sign*ImageScale*ImageScale*haar.ColourChannels + coefIdx*haar.ColourChannels + channel]
 ///
 /// where the variables are as follows:
 ///
-///	sign: Either 0 (positive) or 1 (negative)
-///	coefIdx: The index of the coefficient (from 0 to (ImageScale*ImageScale)-1)
-///	channel: The colour channel (from 0 to haar.ColourChannels-1)
+///   sign: Either 0 (positive) or 1 (negative)
+///   coefIdx: The index of the coefficient (from 0 to (ImageScale*ImageScale)-1)
+///   channel: The colour channel (from 0 to haar.ColourChannels-1)
 ///     
-/// sentivity (Hamming distance threshold) for the perceptual hashes. 
-/// Larger values will allow more images to be seen as *similar*
+/// sensitivity are the thresholds of the score and the secondary metrics a match
+/// has to pass. See Sensitivity::from for the calibrated levels from 0 to 100.
 /// 
 /// modified tells Whether this store was modified since it was loaded/created.
 ///
+/// deleted marks the candidate slots of deleted images (tombstones). Deleted slots
+/// have an empty ID and are removed by compact().
+///
+/// Use SharedStore to share a store between threads.
+///
+/// journal receives every change once the store was loaded with slurp_binary or
+/// saved with dump_binary, so changes since the last snapshot survive a crash.
+/// sync_policy decides when the changes are flushed to disk.
+///
+/// config are the parameters all hashes in the store were made with. Hashes made
+/// with other parameters are rejected.
+///
+/// regions holds the tiles of the images added with add_regions, for finding
+/// images by a crop of them (see query_region).
+///
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct Store {
-	//sync.RWMutex,
-
 	pub candidates: Vec<crate::candidate::Candidate>,
 
 	pub ids: std::collections::BTreeMap<String, usize>,
 
 	pub indices: Vec<Vec<u32>>,
 
-    sensitivity: f64,
+    sensitivity: crate::sensitivity::Sensitivity,
 
     pub modified: bool,
+
+    journal: Option<crate::journal::Journal>,
+
+    sync_policy: crate::journal::SyncPolicy,
+
+    deleted: Vec<bool>,
+
+    config: crate::hashconfig::HashConfig,
+
+    regions: crate::region::RegionIndex,
+
+    dhash_index: Option<crate::dhashindex::DHashIndex>,
+
+    lsh: Option<crate::lsh::LshIndex>,
+
+    scoring: crate::scoring::ScoringProfile,
 }
 
 impl Default for Store {
@@ -61,17 +115,37 @@ impl Default for Store {
             candidates: Vec::new(),
             ids: std::collections::BTreeMap::new(),
             indices: Vec::new(),
-            sensitivity: -60.0,
+            sensitivity: crate::sensitivity::Sensitivity::new(),
             modified: false,
+            journal: None,
+            sync_policy: crate::journal::SyncPolicy::EveryRecord,
+            deleted: Vec::new(),
+            config: crate::hashconfig::HashConfig::new(),
+            regions: crate::region::RegionIndex::new(),
+            dhash_index: None,
+            lsh: None,
+            scoring: crate::scoring::ScoringProfile::new(),
        }
     }
 }
 
 impl Store {
-    pub fn new(sensitivity: f64) -> Self {
+    pub fn new(sensitivity: crate::sensitivity::Sensitivity, config: crate::hashconfig::HashConfig) -> Self {
+        Store::from(sensitivity, config, crate::lsh::CandidateGeneration::IndexLists)
+    }
+
+    /// from creates a store that finds the candidates of its queries as selected by
+    /// generation. The index lists are kept either way, they are part of the store
+    /// format. An LSH index is not stored but rebuilt when a store is read into this one.
+    pub fn from(sensitivity: crate::sensitivity::Sensitivity, config: crate::hashconfig::HashConfig,
+                generation: crate::lsh::CandidateGeneration) -> Self {
         let mut v = Store {..Default::default()};
+        if let crate::lsh::CandidateGeneration::Lsh(lsh) = generation {
+            v.lsh = Some(crate::lsh::LshIndex::new(lsh));
+        }
         v.sensitivity = sensitivity;
-        for _ in 0..INDICESMAX {
+        v.config = config;
+        for _ in 0..config.num_indices() {
             // prefill the outer Vec so we can directly access the inner Vec
             v.indices.push(Vec::new()); 
         } 
@@ -87,34 +161,88 @@ impl Store {
 
     /// Add adds an image (via its hash) to the store. The provided ID is the value
     /// that will be returned as the result of a similarity query. If an ID is
-    /// already in the store, it is not added again.
-    pub fn add(&mut self, id: &str, hash: &crate::hash::Hash) {
+    /// already in the store, it is not added again. Hashes made with another config
+    /// than the store's are rejected.
+    pub fn add(&mut self, id: &str, hash: &crate::hash::Hash) -> crate::Result<()> {
+        self.config.check(&hash.config)?;
+        if hash.perceptual.len() != self.config.perceptual.len() {
+            return Err(crate::Error::Conflict(format!(
+                "hash has {} perceptual hashes instead of {}", hash.perceptual.len(), self.config.perceptual.len())));
+        }
         if self.ids.contains_key(id) {
-            return;
+            return Ok(());
         }
-        let index = self.candidates.len();
-        self.candidates.push(crate::candidate::Candidate::from(id, hash));
-        self.ids.insert(id.to_string(), index);
-        if hash.matrix.coefs.len() < 2 {
-            return;
+        let candidate = crate::candidate::Candidate::from(id, hash);
+        let locations = candidate.signature.clone();
+        let record = crate::journal::Record::Add(candidate, locations);
+        self.log(&record)?;
+        self.apply(record);
+        Ok(())
+    }
+
+    /// add_regions adds the tiles of an image (see region::create_region_hashes) to
+    /// the region index, so query_region finds the image by a crop of it. The image
+    /// must be in the store, its tiles are deleted together with it.
+    pub fn add_regions(&mut self, id: &str,
+                       regions: &[(crate::region::BoundingBox, crate::hash::Hash)]) -> crate::Result<()> {
+        if !self.ids.contains_key(id) {
+            return Err(crate::Error::Conflict(format!("{} is not in the store", id)));
         }
-        for coefindex in 1..hash.matrix.coefs.len() {
-            let coef = &hash.matrix.coefs[coefindex];
-            for colorindex in 0..coef.c.len() {
-                let colorcoef = coef.c[colorindex];
-                if colorcoef.abs() < hash.thresholds.c[colorindex] {
-                    continue;
-                }
-                let mut sign = 0;
-                if colorcoef < 0.0 {
-                    sign = 1;
-                }
-                let location = sign * IMAGESCALE *IMAGESCALE * crate::haar::COLOURCHANNELS 
-                                    + coefindex as u32 * crate::haar::COLOURCHANNELS + colorindex as u32;
-                self.indices[location as usize].push(index as u32);
+        for (_, hash) in regions {
+            self.config.check(&hash.config)?;
+            if hash.perceptual.len() != self.config.perceptual.len() {
+                return Err(crate::Error::Conflict(format!(
+                    "hash has {} perceptual hashes instead of {}", hash.perceptual.len(), self.config.perceptual.len())));
             }
         }
-        self.modified = true;
+        for (region, hash) in regions {
+            let candidate = crate::candidate::Candidate::from(id, hash);
+            let locations = candidate.signature.clone();
+            let record = crate::journal::Record::AddRegion(candidate, *region, locations);
+            self.log(&record)?;
+            self.apply(record);
+        }
+        Ok(())
+    }
+
+    /// enable_dhash_index builds the dHash index of the store, which makes
+    /// query_dhash sub-linear, and keeps it up to date from now on. It is not
+    /// stored with the store but rebuilt when a store is read into this one.
+    pub fn enable_dhash_index(&mut self) {
+        self.dhash_index = Some(crate::dhashindex::DHashIndex::from(&self.candidates, &self.deleted));
+    }
+
+    /// set_scoring selects how queries score the images (see scoring::ScoringProfile).
+    /// The profile is saved with the store, region queries and StoreView always use
+    /// the classic scoring.
+    pub fn set_scoring(&mut self, scoring: crate::scoring::ScoringProfile) {
+        if self.scoring != scoring {
+            self.scoring = scoring;
+            self.modified = true;
+        }
+    }
+
+    /// scoring returns how queries score the images.
+    pub fn scoring(&self) -> crate::scoring::ScoringProfile {
+        self.scoring
+    }
+
+    /// candidate_generation returns how the store finds the candidates of a query.
+    pub fn candidate_generation(&self) -> crate::lsh::CandidateGeneration {
+        match &self.lsh {
+            Some(lsh) => crate::lsh::CandidateGeneration::Lsh(lsh.config()),
+            None => crate::lsh::CandidateGeneration::IndexLists,
+        }
+    }
+
+    /// has_dhash_index tells whether the store keeps a dHash index.
+    pub fn has_dhash_index(&self) -> bool {
+        self.dhash_index.is_some()
+    }
+
+    /// regions returns the region index of the store.
+    pub fn regions(&self) -> &crate::region::RegionIndex {
+        &self.regions
     }
 
     pub fn ids(&self) -> Vec<String> {
@@ -126,140 +254,480 @@ impl Store {
     }
 
     /// Delete removes an image from the store so it will not be returned during a
-    /// query anymore. Note that the candidate slot still remains occupied but its
-    /// index will be removed from all index lists. This also means that Size() will
-    /// not decrease. This is an expensive operation. If the provided ID could not be
-    /// found, nothing happens.
-    pub fn delete(&mut self, id: &str) {
+    /// query anymore. The candidate slot is only marked as deleted (a tombstone) and
+    /// stays in the index lists until compact() is called, so this is cheap. If the
+    /// provided ID could not be found, nothing happens.
+    pub fn delete(&mut self, id: &str) -> crate::Result<()> {
         if !self.ids.contains_key(id) {
-            return;
-        }
-        // Get the index.
-        let index = self.ids[id];
-        self.modified = true;
-        // clear the entry in the candidates list without deleting it
-        self.candidates[index] = crate::candidate::Candidate::new();
-        self.ids.remove(id);
-        // Remove from all index lists.
-        for i in 0..self.indices.len() {
-            for j in (0..self.indices[i].len()).rev() {
-                if self.indices[i][j] == index as u32 {
-                    self.indices[i].remove(j);
-                }
-            }
+            return Ok(());
         }
+        let record = crate::journal::Record::Delete(id.to_string());
+        self.log(&record)?;
+        self.apply(record);
+        Ok(())
     }
 
     /// Exchange exchanges the ID of an image for a new one. If the old ID could not
     /// be found, nothing happens. If the new ID already existed prior to the
     /// exchange, the function returns immediately.
     /// 
-    pub fn exchange(&mut self, oldid: &str, newid: &str) -> bool {
+    pub fn exchange(&mut self, oldid: &str, newid: &str) -> crate::Result<bool> {
         if !self.ids.contains_key(oldid) {
-            return false;
+            return Ok(false);
         }
         if self.ids.contains_key(newid) {
-            return false;
+            return Ok(false);
         }
-        let index = self.ids[oldid];
-        // update the ids
-        self.ids.remove(oldid);
-        self.ids.insert(newid.to_string(), index);
+        let record = crate::journal::Record::Exchange(oldid.to_string(), newid.to_string());
+        self.log(&record)?;
+        self.apply(record);
+        Ok(true)
+    }
 
-        // Update the candidate.
-        self.candidates[index].id = newid.to_string();
+    /// merge adds all images of the other store to this one, so stores that were
+    /// built independently (e.g. one per disk) can be searched as one. The
+    /// candidates of the other store get new indices behind the existing ones. IDs
+    /// that are in both stores are resolved by the policy. Both stores must use the
+    /// same sensitivity and hash config. Returns the number of images taken from the other store.
+    pub fn merge(&mut self, other: &Store, policy: MergePolicy) -> crate::Result<usize> {
+        if self.sensitivity != other.sensitivity {
+            return Err(crate::Error::Conflict(format!(
+                "cannot merge a store with sensitivity {:?} into one with sensitivity {:?}",
+                other.sensitivity, self.sensitivity)));
+        }
+        self.config.check(&other.config)?;
+        if policy == MergePolicy::Fail {
+            if let Some(id) = other.ids.keys().find(|id| self.ids.contains_key(*id)) {
+                return Err(crate::Error::Conflict(format!("{} is in both stores", id)));
+            }
+        }
+        let mut merged = 0;
+        // add in the order of the other store, so the index lists stay sorted
+        for (index, candidate) in other.candidates.iter().enumerate() {
+            if other.is_deleted(index) {
+                continue;
+            }
+            if self.ids.contains_key(&candidate.id) {
+                if policy == MergePolicy::KeepExisting {
+                    continue;
+                }
+                let record = crate::journal::Record::Delete(candidate.id.clone());
+                self.log(&record)?;
+                self.apply(record);
+            }
+            let record = crate::journal::Record::Add(candidate.clone(), candidate.signature.clone());
+            self.log(&record)?;
+            self.apply(record);
+            for (tile, region) in other.regions.tiles_of(&candidate.id) {
+                let record = crate::journal::Record::AddRegion(tile.clone(), region, tile.signature.clone());
+                self.log(&record)?;
+                self.apply(record);
+            }
+            merged += 1;
+        }
+        Ok(merged)
+    }
+
+    /// set_sync_policy decides when the changes in the journal are flushed to disk
+    /// (see journal::SyncPolicy). Changes that are not yet flushed are flushed now.
+    pub fn set_sync_policy(&mut self, policy: crate::journal::SyncPolicy) -> crate::Result<()> {
+        self.sync_policy = policy;
+        if let Some(journal) = self.journal.as_mut() {
+            journal.policy = policy;
+        }
+        self.flush()
+    }
+
+    pub fn sync_policy(&self) -> crate::journal::SyncPolicy {
+        self.sync_policy
+    }
+
+    /// flush waits until all changes in the journal are on disk.
+    pub fn flush(&mut self) -> crate::Result<()> {
+        match self.journal.as_mut() {
+            Some(journal) => journal.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// log writes the change to the journal, if there is one, before it is applied.
+    fn log(&mut self, record: &crate::journal::Record) -> crate::Result<()> {
+        match self.journal.as_mut() {
+            Some(journal) => journal.append(record),
+            None => Ok(()),
+        }
+    }
+
+    /// apply performs a change on the data structure.
+    fn apply(&mut self, record: crate::journal::Record) {
+        match record {
+            crate::journal::Record::Add(mut candidate, locations) => {
+                if self.ids.contains_key(&candidate.id) {
+                    return;
+                }
+                candidate.signature = locations.clone();
+                candidate.signature.sort_unstable();
+                let index = self.candidates.len();
+                self.ids.insert(candidate.id.clone(), index);
+                if let Some(dhash_index) = self.dhash_index.as_mut() {
+                    dhash_index.add(index as u32, &candidate.dhash);
+                }
+                if let Some(lsh) = self.lsh.as_mut() {
+                    lsh.add(index as u32, &candidate.signature, &candidate.dhash);
+                }
+                self.candidates.push(candidate);
+                self.deleted.push(false);
+                for location in locations {
+                    self.indices[location as usize].push(index as u32);
+                }
+            }
+            crate::journal::Record::Delete(id) => {
+                let index = match self.ids.remove(&id) {
+                    Some(index) => index,
+                    None => return,
+                };
+                // clear the entry in the candidates list without deleting it,
+                // queries skip it until compact() removes it from the index lists
+                let candidate = std::mem::take(&mut self.candidates[index]);
+                self.deleted[index] = true;
+                if let Some(lsh) = self.lsh.as_mut() {
+                    lsh.delete(index as u32, &candidate.signature, &self.deleted);
+                }
+                self.regions.delete(&id);
+            }
+            crate::journal::Record::Exchange(oldid, newid) => {
+                if self.ids.contains_key(&newid) {
+                    return;
+                }
+                let index = match self.ids.remove(&oldid) {
+                    Some(index) => index,
+                    None => return,
+                };
+                self.ids.insert(newid.clone(), index);
+                self.regions.exchange(&oldid, &newid);
+                self.candidates[index].id = newid;
+            }
+            crate::journal::Record::AddRegion(candidate, region, locations) => {
+                if !self.ids.contains_key(&candidate.id) {
+                    return;
+                }
+                self.regions.add(candidate, region, locations, self.config.num_indices());
+            }
+        }
         self.modified = true;
-        true
     }
 
     /// Query performs a similarity search on the given image hashes and returns
     /// all potential matches. The returned slice will sort it so the match with the best score is its
     /// first element.
     pub fn query(&self, hash: &crate::hash::Hash) -> crate::matches::Matches {
-        let mut ms = crate::matches::Matches::new();
-        if self.candidates.len() == 0 {
-            return ms;
+        self.query_with(hash, &crate::queryoptions::QueryOptions::new())
+    }
+
+    /// query_with performs a similarity search like query but applies the given
+    /// options while the matches are collected. A hash made with another config
+    /// than the store's has no matches.
+    pub fn query_with(&self, hash: &crate::hash::Hash,
+                      options: &crate::queryoptions::QueryOptions) -> crate::matches::Matches {
+        if let Err(error) = self.config.check(&hash.config) {
+            log::error!("Cannot query the store: {}", error);
+            return crate::matches::Matches::new();
         }
+        self.query_locations(&hash_locations(hash), hash, options)
+    }
+
+    /// query_dihedral performs a similarity search with all hashes made by
+    /// hash::create_dihedral_hashes, so rotated and mirrored copies of the image are
+    /// found as well. Every ID is returned once, with the transform of the query
+    /// image that matched best in Match::transform.
+    pub fn query_dihedral(&self, hashes: &[(crate::dihedral::Dihedral, crate::hash::Hash)],
+                          options: &crate::queryoptions::QueryOptions) -> crate::matches::Matches {
+        let results = hashes.iter().flat_map(|(transform, hash)| {
+            self.query_with(hash, options).m.into_iter().map(|mut m| {
+                m.transform = *transform;
+                m
+            })
+        });
+        crate::matches::best_per_id(results, options.max_results)
+    }
 
-        let mut scores: Vec<f64> = Vec::new();
-        for _ in 0..self.candidates.len() {
-            scores.push(f64::NAN);
+    /// query_region performs a similarity search for images that contain the image
+    /// of the given hash, e.g. because it was cropped from them. It compares the hash
+    /// with the tiles of the region index. Every ID is returned once, with the
+    /// bounding box of its best tile in Match::region.
+    pub fn query_region(&self, hash: &crate::hash::Hash,
+                        options: &crate::queryoptions::QueryOptions) -> crate::matches::Matches {
+        if self.regions.candidates.is_empty() {
+            return crate::matches::Matches::new();
+        }
+        if let Err(error) = self.config.check(&hash.config) {
+            log::error!("Cannot query the store: {}", error);
+            return crate::matches::Matches::new();
         }
-        //let mut nummatches: usize;
+        let tiles = &self.regions;
+        let scores = score_buckets(&hash_locations(hash), self.config.scale, 0..tiles.candidates.len(), |location| {
+            tiles.indices[location as usize].iter().copied()
+        });
+        let skip = |index: usize| {
+            let id = &tiles.candidates[index].id;
+            id.is_empty() || options.exclude_id.as_ref() == Some(id)
+        };
+        let mut region_options = options.clone();
+        region_options.max_results = None;
+        let matches = crate::queryoptions::select_matches(&scores, &self.sensitivity, &region_options, skip, |index, score| {
+            let candidate = &tiles.candidates[index];
+            let mut m = create_match(&candidate.id, candidate.ratio, &candidate.dhash,
+                                     candidate.histogram, score, hash);
+            m.perceptual_distances = self.config.perceptual.distances(&candidate.perceptual, &hash.perceptual);
+            m.region = Some(tiles.regions[index]);
+            m
+        });
+        crate::matches::best_per_id(matches.m, options.max_results)
+    }
+
+    /// query_contains performs a similarity search for images that appear inside the
+    /// query image, e.g. in a larger screenshot. It queries with the hashes of the
+    /// tiles of the query image made by region::create_region_hashes. Every ID is
+    /// returned once, with the bounding box of the best tile of the query image in
+    /// Match::region.
+    pub fn query_contains(&self, regions: &[(crate::region::BoundingBox, crate::hash::Hash)],
+                          options: &crate::queryoptions::QueryOptions) -> crate::matches::Matches {
+        let results = regions.iter().flat_map(|(region, hash)| {
+            self.query_with(hash, options).m.into_iter().map(|mut m| {
+                m.region = Some(*region);
+                m
+            })
+        });
+        crate::matches::best_per_id(results, options.max_results)
+    }
 
-        // Examine hash buckets.
-        for coefindex in 0..hash.matrix.coefs.len() {
-            let coef = &hash.matrix.coefs[coefindex];
-            if coefindex == 0 {
-                continue; // igore scaling function coefficient for now
+    /// query_dhash returns the images whose dHash is within the given Hamming
+    /// distance of the dHash of the hash, without looking at the Haar coefficients.
+    /// This finds near-identical images (re-encodes and resizes) much faster than
+    /// query. The score of a match is its dHash distance, so the closest image comes
+    /// first. With a dHash index (see enable_dhash_index), only the candidates that
+    /// share a part of the dHash are compared, otherwise all of them.
+    pub fn query_dhash(&self, hash: &crate::hash::Hash, max_distance: i64) -> crate::matches::Matches {
+        if let Err(error) = self.config.check(&hash.config) {
+            log::error!("Cannot query the store: {}", error);
+            return crate::matches::Matches::new();
+        }
+        let indices: Vec<usize> = match self.dhash_index.as_ref().and_then(|index| index.lookup(&hash.dhash, max_distance)) {
+            Some(indices) => indices.into_iter().map(|index| index as usize).collect(),
+            None => (0..self.candidates.len()).collect(),
+        };
+        let mut matches = Vec::new();
+        for index in indices {
+            if self.deleted[index] {
+                continue;
             }
-            // Calculate the weight bin outside the main loop.
-            let y = coefindex / hash.matrix.width as usize;
-            let x = coefindex % hash.matrix.width as usize;
-            let mut bin = y;
-            if x > y {
-                bin = x;
+            let candidate = &self.candidates[index];
+            let mut m = create_match(&candidate.id, candidate.ratio, &candidate.dhash,
+                                     candidate.histogram, 0.0, hash);
+            if m.dhash_distance > max_distance {
+                continue;
             }
-            if bin > 5 {
-                bin = 5;
+            m.score = m.dhash_distance as f64;
+            m.perceptual_distances = self.config.perceptual.distances(&candidate.perceptual, &hash.perceptual);
+            matches.push(m);
+        }
+        // stable, so images with the same distance stay in the order they were added
+        matches.sort_by_key(|m| m.dhash_distance);
+        crate::matches::Matches::from(matches)
+    }
+
+    /// query_by_id performs a similarity search with the image that is stored under
+    /// the given ID, using its retained signature. The image file is not needed.
+    /// The image itself is part of the result unless options exclude it. Returns
+    /// None if the ID is not in the store.
+    pub fn query_by_id(&self, id: &str,
+                       options: &crate::queryoptions::QueryOptions) -> Option<crate::matches::Matches> {
+        let candidate = &self.candidates[*self.ids.get(id)?];
+        let mut hash = candidate.hash();
+        hash.config = self.config;
+        Some(self.query_locations(&candidate.signature, &hash, options))
+    }
+
+    /// neighbours returns all images that are similar to the image stored under the
+    /// given ID, without the image itself. Returns None if the ID is not in the store.
+    pub fn neighbours(&self, id: &str) -> Option<crate::matches::Matches> {
+        let mut options = crate::queryoptions::QueryOptions::new();
+        options.exclude_id = Some(id.to_string());
+        self.query_by_id(id, &options)
+    }
+
+    /// rebuild_lsh builds the LSH index of the store again, if it has one.
+    fn rebuild_lsh(&mut self) {
+        if let Some(lsh) = self.lsh.as_ref() {
+            self.lsh = Some(crate::lsh::LshIndex::from(lsh.config(), &self.candidates, &self.deleted));
+        }
+    }
+
+    /// query_locations performs a similarity search for an image that belongs to the
+    /// given index lists. The other metrics of the matches are compared with hash.
+    fn query_locations(&self, locations: &[u32], hash: &crate::hash::Hash,
+                       options: &crate::queryoptions::QueryOptions) -> crate::matches::Matches {
+        if self.candidates.is_empty() {
+            return crate::matches::Matches::new();
+        }
+        if let Some(lsh) = self.lsh.as_ref() {
+            return self.query_lsh(lsh, locations, hash, options);
+        }
+        let num_threads = match options.num_threads {
+            Some(num_threads) => num_threads,
+            None if self.candidates.len() >= PARALLEL_QUERY_MIN_CANDIDATES => {
+                std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
             }
-            for colorindex in 0..coef.c.len() {
-                let colorcoef = coef.c[colorindex];
-                if colorcoef.abs() < hash.thresholds.c[colorindex] {
-                    // Coef is too small. Ignore.
-				    continue;
-                }
-                // At this point, we have a coefficient which we want to look up
-			    // in the index buckets.
-                let mut sign = 0;
-                if colorcoef < 0.0 {
-                    sign = 1;
-                }
-                let location = sign * IMAGESCALE *IMAGESCALE * crate::haar::COLOURCHANNELS 
-                                    + coefindex as u32 * crate::haar::COLOURCHANNELS + colorindex as u32;
-                let arr = &self.indices[location as usize];
-                for i in 0..arr.len() {
-                    let sindex = arr[i];
-                    if scores[sindex as usize].is_nan() {
-                        // calculated initial score
-                        let mut score: f64  = 0.0;
-                        for colorid in 0..coef.c.len() {
-                            score += WEIGHTS[colorid][0];
-                        }
-                        scores[sindex as usize] = score;
-                    }
-                    // At this point, we have an entry in matches. Simply subtract the
-                    // corresponding weight.
-                    scores[sindex as usize] -= WEIGHTSUMS[bin];
-                }
+            None => 1,
+        };
+        let mut scores = self.score(locations, num_threads);
+        if !self.scoring.is_classic() {
+            for (candidate, score) in self.candidates.iter().zip(scores.iter_mut()) {
+                *score = self.scoring.adjust(*score, &candidate.scale_coeff, &candidate.histo_max, hash);
             }
         }
-        // Create matches. If the dhash_distance is lower than the sensitivity threshold it is a *valid* match.
-        for index in 0..scores.len() {
-            if !scores[index].is_nan() {
-                let mut m = crate::matches::Match::new();
-                m.id = self.candidates[index].id.clone();
-                m.score = scores[index];
-                m.ratio_diff = self.candidates[index].ratio.log(10.0).abs() - hash.ratio.log(10.0);
-                m.dhash_distance = crate::hamming::hamming_distance(
-                                    self.candidates[index].dhash[0], hash.dhash[0])
-                                    + crate::hamming::hamming_distance(
-                                        self.candidates[index].dhash[1], hash.dhash[1]);
-                m.histogram_distance = crate::hamming::hamming_distance(
-                                            self.candidates[index].histogram, hash.histogram);
-                if m.score < self.sensitivity {
-                    ms.m.push(m);                
+        let excluded = options.exclude_id.as_ref().and_then(|id| self.ids.get(id).copied());
+        let skip = |index: usize| self.deleted[index] || Some(index) == excluded;
+        crate::queryoptions::select_matches(&scores, &self.sensitivity, options, skip, |index, score| {
+            let candidate = &self.candidates[index];
+            let mut m = create_match(&candidate.id, candidate.ratio, &candidate.dhash,
+                                     candidate.histogram, score, hash);
+            m.perceptual_distances = self.config.perceptual.distances(&candidate.perceptual, &hash.perceptual);
+            m
+        })
+    }
+
+    /// query_lsh performs a similarity search like query_locations, but only scores
+    /// the candidates the LSH index finds for the query.
+    fn query_lsh(&self, lsh: &crate::lsh::LshIndex, locations: &[u32], hash: &crate::hash::Hash,
+                 options: &crate::queryoptions::QueryOptions) -> crate::matches::Matches {
+        let mut signature = locations.to_vec();
+        signature.sort_unstable();
+        let found = lsh.lookup(&signature, &hash.dhash, &self.deleted);
+        let scale = self.config.scale;
+        let scores: Vec<f64> = found
+            .iter()
+            .map(|index| {
+                let candidate = &self.candidates[*index as usize];
+                let mut score = f64::NAN;
+                for &location in locations {
+                    if candidate.signature.binary_search(&location).is_ok() {
+                        score = if score.is_nan() { initial_score() } else { score };
+                        score -= location_weight(location, scale);
+                    }
                 }
-            }
+                self.scoring.adjust(score, &candidate.scale_coeff, &candidate.histo_max, hash)
+            })
+            .collect();
+        let excluded = options.exclude_id.as_ref().and_then(|id| self.ids.get(id).copied());
+        let skip = |position: usize| {
+            let index = found[position] as usize;
+            self.deleted[index] || Some(index) == excluded
+        };
+        crate::queryoptions::select_matches(&scores, &self.sensitivity, options, skip, |position, score| {
+            let candidate = &self.candidates[found[position] as usize];
+            let mut m = create_match(&candidate.id, candidate.ratio, &candidate.dhash,
+                                     candidate.histogram, score, hash);
+            m.perceptual_distances = self.config.perceptual.distances(&candidate.perceptual, &hash.perceptual);
+            m
+        })
+    }
+
+    /// score calculates the scores of all candidates. The candidates are split into
+    /// ranges that are scanned by separate threads. As the index lists are sorted,
+    /// each thread only looks at its own part of every list.
+    fn score(&self, locations: &[u32], num_threads: usize) -> Vec<f64> {
+        let size = self.candidates.len();
+        let num_threads = num_threads.clamp(1, size.max(1));
+        let scale = self.config.scale;
+        let bucket = |location: u32, start: usize, end: usize| {
+            let list = &self.indices[location as usize];
+            let first = list.partition_point(|index| (*index as usize) < start);
+            let last = list.partition_point(|index| (*index as usize) < end);
+            list[first..last].iter().copied()
+        };
+        if num_threads == 1 {
+            return score_buckets(locations, scale, 0..size, |location| bucket(location, 0, size));
         }
-        // sort the vector so the first match is the one with the lowest value --> the best match
-        // The number of matches should be small, so the bubble sort is about the fastest algorithm.
-        ms.sort();
-        ms
+        let chunk = size.div_ceil(num_threads);
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..size)
+                .step_by(chunk)
+                .map(|start| {
+                    let end = (start + chunk).min(size);
+                    scope.spawn(move || score_buckets(locations, scale, start..end, |location| bucket(location, start, end)))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                .collect()
+        })
+    }
+
+    pub fn sensitivity(&self) -> crate::sensitivity::Sensitivity {
+        self.sensitivity
+    }
+
+    /// config returns the parameters the hashes of the store are made with.
+    pub fn config(&self) -> crate::hashconfig::HashConfig {
+        self.config
     }
 
+    /// find_duplicate_groups compares every image in the store with all others and
+    /// merges similar images transitively into groups, so the result does not depend
+    /// on the order the images were added in. A pair is similar if a query with one
+    /// of the images would return the other with the given options. max_results
+    /// limits the number of groups.
+    pub fn find_duplicate_groups(&self, options: &crate::queryoptions::QueryOptions)
+                                 -> Vec<crate::duplicates::DuplicateGroup> {
+        crate::duplicates::find_duplicate_groups(self, options)
+    }
+
+    /// size returns the number of images in the store, without deleted ones.
     pub fn size(&self) -> usize {
-        self.candidates.len()
+        self.ids.len()
+    }
+
+    /// is_deleted tells whether the candidate slot with the given index belongs to a
+    /// deleted image.
+    pub fn is_deleted(&self, index: usize) -> bool {
+        self.deleted.get(index).copied().unwrap_or(true)
+    }
+
+    /// compact removes the slots of deleted images from the candidates and the index
+    /// lists and renumbers the remaining candidates. It takes time proportional to
+    /// the size of the index, so call it once after many deletes.
+    pub fn compact(&mut self) {
+        self.regions.compact();
+        if !self.deleted.contains(&true) {
+            return;
+        }
+        let mut renumber = vec![u32::MAX; self.candidates.len()];
+        let mut candidates = Vec::with_capacity(self.ids.len());
+        for (index, candidate) in std::mem::take(&mut self.candidates).into_iter().enumerate() {
+            if !self.deleted[index] {
+                renumber[index] = candidates.len() as u32;
+                candidates.push(candidate);
+            }
+        }
+        for list in self.indices.iter_mut() {
+            // keeps the lists sorted as the order of the candidates does not change
+            list.retain(|index| renumber[*index as usize] != u32::MAX);
+            for index in list.iter_mut() {
+                *index = renumber[*index as usize];
+            }
+        }
+        for index in self.ids.values_mut() {
+            *index = renumber[*index] as usize;
+        }
+        self.deleted = vec![false; candidates.len()];
+        self.candidates = candidates;
+        if self.dhash_index.is_some() {
+            self.enable_dhash_index();
+        }
+        self.rebuild_lsh();
+        self.modified = true;
     }
 
     pub fn modified(&self) -> bool {
@@ -276,74 +744,324 @@ impl Store {
         crate::marshal::store_hash_string_usize(&self.ids, to);
         let s = self.indices.len();
         crate::marshal::store_usize(s, to);
-        crate::marshal::store_f64(self.sensitivity, to);
+        crate::marshal::store_f64(self.sensitivity.score_threshold, to);
         for elem in &self.indices {
             crate::marshal::store_vec_u32(elem, to);
         }
         crate::marshal::store_bool(self.modified, to);
+        self.config.encode(to);
+        crate::marshal::store_i64(self.sensitivity.max_dhash_distance, to);
+        crate::marshal::store_i64(self.sensitivity.max_histogram_distance, to);
+        for elem in &self.candidates {
+            crate::marshal::store_vec_u64(&elem.perceptual, to);
+        }
+        self.regions.encode(to);
+        for elem in &self.candidates {
+            crate::marshal::store_u32(elem.orientation as u32, to);
+        }
+        for elem in &self.candidates {
+            crate::marshal::store_u32(crate::formats::Format::code(elem.format), to);
+        }
+        for elem in &self.candidates {
+            crate::marshal::store_vec_f64(&elem.scale_coeff.c, to);
+        }
+        self.scoring.encode(to);
     }
 
     /// decode data structure from binary stream
-    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) {
-        let s = crate::marshal::restore_usize(from);
+    /// The store is only replaced if the whole stream could be decoded and is consistent
+    /// and its hashes were made with the same config as the ones of this store.
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        self.decode_format(from, STORE_FORMAT_VERSION)
+    }
+
+    /// decode_format decodes a stream written by the given format version. Streams
+    /// of version 1 were written with the default hash config and without the data
+    /// added in version 2, they keep the scoring profile of this store.
+    fn decode_format(&mut self, from: &mut std::io::Cursor<Vec<u8>>, version: u32) -> crate::Result<()> {
+        let mut v = Store {..Default::default()};
+        let s = crate::marshal::restore_usize(from)?;
         for _i in 0..s {
             let mut elem = crate::candidate::Candidate::new();
-            elem.decode(from);
-            self.candidates.push(elem);
+            elem.decode(from)?;
+            v.candidates.push(elem);
         }
-        self.ids.extend(crate::marshal::restore_hash_string_usize(from));
-        self.sensitivity = crate::marshal::restore_f64(from);
-        let s = crate::marshal::restore_usize(from);
+        v.ids = crate::marshal::restore_hash_string_usize(from)?;
+        let s = crate::marshal::restore_usize(from)?;
+        v.sensitivity = crate::sensitivity::Sensitivity::from_score_threshold(crate::marshal::restore_f64(from)?);
         for _i in 0..s {
-            let mut elem = Vec::new();
-            elem.extend(crate::marshal::restore_vec_u32(from));
-            self.indices.push(elem);
+            v.indices.push(crate::marshal::restore_vec_u32(from)?);
         }
-        self.modified = crate::marshal::restore_bool(from);
-        self.modified = false;
+        crate::marshal::restore_bool(from)?;
+        v.scoring = self.scoring;
+        if version < 2 {
+            // version 1 rounded the ratio down to an integer (0 for portrait images),
+            // it cannot be recomputed without the image
+            for elem in v.candidates.iter_mut() {
+                elem.ratio = f64::NAN;
+                elem.scale_coeff.c.clear();
+            }
+        }
+        if version >= 2 {
+            v.config.decode(from)?;
+            v.sensitivity.max_dhash_distance = crate::marshal::restore_i64(from)?;
+            v.sensitivity.max_histogram_distance = crate::marshal::restore_i64(from)?;
+            for elem in v.candidates.iter_mut() {
+                elem.perceptual = crate::marshal::restore_vec_u64(from)?;
+            }
+            v.regions.decode(from)?;
+            for elem in v.candidates.iter_mut() {
+                elem.orientation = crate::marshal::restore_u32(from)? as u8;
+            }
+            for elem in v.candidates.iter_mut() {
+                elem.format = crate::formats::Format::from_code(crate::marshal::restore_u32(from)?);
+            }
+            for elem in v.candidates.iter_mut() {
+                elem.scale_coeff.c = crate::marshal::restore_vec_f64(from)?;
+            }
+            v.scoring.decode(from)?;
+        }
+        self.config.check(&v.config)?;
+        v.validate()?;
+        // every slot without an ID is a tombstone
+        v.deleted = v.candidates.iter().map(|candidate| candidate.id.is_empty()).collect();
+        // the signatures are not stored, every candidate is in the index lists it belongs to
+        for (location, list) in v.indices.iter().enumerate() {
+            for &index in list {
+                if !v.deleted[index as usize] {
+                    v.candidates[index as usize].signature.push(location as u32);
+                }
+            }
+        }
+        if self.dhash_index.is_some() {
+            v.enable_dhash_index();
+        }
+        v.lsh = self.lsh.take();
+        v.rebuild_lsh();
+        v.sync_policy = self.sync_policy;
+        *self = v;
+        Ok(())
+    }
+
+    /// validate checks that all ids and index lists point to existing candidates.
+    fn validate(&self) -> crate::Result<()> {
+        // baseline stores have 98400 lists, a few more than HashConfig::num_indices needs
+        let num_indices = self.config.num_indices() as usize;
+        if self.indices.len() < num_indices {
+            return Err(crate::Error::Decode(format!(
+                "store has {} index lists instead of at least {}", self.indices.len(), num_indices)));
+        }
+        for (id, index) in self.ids.iter() {
+            if *index >= self.candidates.len() || self.candidates[*index].id != *id {
+                return Err(crate::Error::Decode(format!(
+                    "id {} points to an invalid candidate {}", id, index)));
+            }
+            if self.candidates[*index].perceptual.len() != self.config.perceptual.len() {
+                return Err(crate::Error::Decode(format!(
+                    "candidate {} has no perceptual hashes", id)));
+            }
+        }
+        if !self.regions.indices.is_empty() && self.regions.indices.len() != num_indices {
+            return Err(crate::Error::Decode(format!(
+                "region index has {} lists instead of {}", self.regions.indices.len(), num_indices)));
+        }
+        for tile in self.regions.candidates.iter() {
+            if !tile.id.is_empty() && !self.ids.contains_key(&tile.id) {
+                return Err(crate::Error::Decode(format!("region of unknown image {}", tile.id)));
+            }
+            if !tile.id.is_empty() && tile.perceptual.len() != self.config.perceptual.len() {
+                return Err(crate::Error::Decode(format!(
+                    "region of {} has no perceptual hashes", tile.id)));
+            }
+        }
+        for list in self.indices.iter() {
+            if list.last().is_some_and(|index| *index as usize >= self.candidates.len()) {
+                return Err(crate::Error::Decode(format!(
+                    "index list points to an invalid candidate {}", list[list.len() - 1])));
+            }
+            // queries rely on sorted index lists
+            if list.windows(2).any(|pair| pair[0] >= pair[1]) {
+                return Err(crate::Error::Decode("index list is not sorted".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// encode the data structure to a self-describing binary stream:
+    /// magic, format version, payload length, payload (see encode) and a CRC-32
+    /// of everything before it.
+    pub fn encode_versioned(&self, to: &mut Vec<u8>) {
+        let start = to.len();
+        let mut payload = Vec::new();
+        self.encode(&mut payload);
+        to.extend_from_slice(&STORE_MAGIC);
+        crate::marshal::store_u32(STORE_FORMAT_VERSION, to);
+        crate::marshal::store_usize(payload.len(), to);
+        to.extend_from_slice(&payload);
+        let checksum = crate::marshal::checksum(&to[start..]);
+        crate::marshal::store_u32(checksum, to);
+    }
+
+    /// decode a binary stream written by encode_versioned. The checksum is verified
+    /// before any data is decoded. Streams without the magic header are read as
+    /// format version 1 and the store is marked as modified so the next dump
+    /// migrates it to the current format.
+    pub fn decode_versioned(&mut self, bytes: Vec<u8>) -> crate::Result<()> {
+        if !bytes.starts_with(&STORE_MAGIC) {
+            log::warn!("Store has no format header, reading it as format version 1. \
+                        The width to height ratios of its images are unknown until they are added again.");
+            self.decode_format(&mut std::io::Cursor::new(bytes), 1)?;
+            self.modified = true;
+            return Ok(());
+        }
+        if bytes.len() < STORE_HEADER_LEN + 4 {
+            return Err(crate::Error::Decode("store header is truncated".to_string()));
+        }
+        let mut header = std::io::Cursor::new(bytes[8..STORE_HEADER_LEN].to_vec());
+        let version = crate::marshal::restore_u32(&mut header)?;
+        if version != STORE_FORMAT_VERSION {
+            return Err(crate::Error::Version(version));
+        }
+        let length = crate::marshal::restore_usize(&mut header)?;
+        if bytes.len() - STORE_HEADER_LEN - 4 != length {
+            return Err(crate::Error::Decode(format!(
+                "store payload has {} bytes instead of {}", bytes.len() - STORE_HEADER_LEN - 4, length)));
+        }
+        let end = STORE_HEADER_LEN + length;
+        let mut trailer = std::io::Cursor::new(bytes[end..].to_vec());
+        let expected = crate::marshal::restore_u32(&mut trailer)?;
+        let found = crate::marshal::checksum(&bytes[..end]);
+        if expected != found {
+            return Err(crate::Error::Checksum { expected, found });
+        }
+        let mut payload = std::io::Cursor::new(bytes[STORE_HEADER_LEN..end].to_vec());
+        self.decode_format(&mut payload, version)
     }
 
     /// Write binary stream to file
-    pub fn dump_binary(&mut self, storefile: &str) {
+    /// The file is replaced atomically and a new, empty journal is started next to it.
+    pub fn dump_binary(&mut self, storefile: &str) -> crate::Result<()> {
         let mut buffer = Vec::new();
-        self.encode(&mut buffer);
+        self.encode_versioned(&mut buffer);
         let path = std::path::Path::new(&storefile);
-        let display = path.display();
-        // Open a file in write-only mode, returns `io::Result<File>`
-        let mut write_file = match std::fs::File::create(&path) {
-            Err(why) => panic!("couldn't create {}: {}", display, why),
-            Ok(write_file) => write_file,
-        };
-        let ret = write_file.write(&mut buffer);
-        if ret.is_err() {
-            log::error!("failed to write binary data to file {}!", &storefile)
-        }
-        let ret = write_file.flush();
-        if ret.is_err() {
-            log::error!(
-                "failed to empty the write buffer for binary data to file {}!",
-                &storefile
-            )
-        }
+        crate::journal::write_atomic(path, &buffer)?;
+        let journalpath = crate::journal::Journal::path_for(storefile);
+        let snapshot = crate::marshal::checksum(&buffer);
+        self.journal = Some(crate::journal::Journal::create(&journalpath, snapshot, self.sync_policy)?);
+        self.modified = false;
+        Ok(())
     }
 
     /// read binary stream from file
-    pub fn slurp_binary(&mut self, storefile: &str) {
+    /// The changes in the journal of the file are replayed and further changes are
+    /// appended to it.
+    pub fn slurp_binary(&mut self, storefile: &str) -> crate::Result<()> {
         let path = std::path::Path::new(&storefile);
-        let display = path.display();
-        let mut input_file = match std::fs::File::open(&path) {
-            Err(why) => panic!("couldn't open {}: {}", display, why),
-            Ok(read_file) => read_file,
-        };
+        let mut input_file = std::fs::File::open(path)?;
         let mut buf = Vec::new();
-        let ret = input_file.read_to_end(&mut buf);
-        if ret.is_err() {
-            log::error!("Failed to read test file back in.");
-            return;
+        input_file.read_to_end(&mut buf)?;
+        let snapshot = crate::marshal::checksum(&buf);
+        self.decode_versioned(buf)?;
+        let journalpath = crate::journal::Journal::path_for(storefile);
+        let (journal, records) = crate::journal::Journal::open(&journalpath, snapshot, self.sync_policy)?;
+        for record in records {
+            self.apply(record);
+        }
+        self.journal = Some(journal);
+        Ok(())
+    }
+}
+
+/// hash_locations returns the index lists an image with the given hash belongs to.
+pub(crate) fn hash_locations(hash: &crate::hash::Hash) -> Vec<u32> {
+    let mut locations = Vec::new();
+    let scale = hash.config.scale;
+    for coefindex in 1..hash.matrix.coefs.len() {
+        let coef = &hash.matrix.coefs[coefindex];
+        for colorindex in 0..coef.c.len() {
+            let colorcoef = coef.c[colorindex];
+            if colorcoef.abs() < hash.thresholds.c[colorindex] {
+                continue;
+            }
+            let mut sign = 0;
+            if colorcoef < 0.0 {
+                sign = 1;
+            }
+            let location = sign * scale * scale * crate::haar::COLOURCHANNELS 
+                                + coefindex as u32 * crate::haar::COLOURCHANNELS + colorindex as u32;
+            locations.push(location);
         }
-        let mut read_file = std::io::Cursor::new(buf);
-        self.decode(&mut read_file);
     }
+    locations
+}
 
+/// score_buckets calculates the score of every candidate in the given range that
+/// shares at least one of the given index lists (see hash_locations) of a hash
+/// with the given scale. bucket
+/// returns the candidate indices of the index list at the given location.
+/// Candidates without a common index list get NAN. The first score belongs to the
+/// first candidate of the range.
+pub(crate) fn score_buckets<F, I>(locations: &[u32], scale: u32, candidates: std::ops::Range<usize>,
+                                  bucket: F) -> Vec<f64>
+where
+    F: Fn(u32) -> I,
+    I: Iterator<Item = u32>,
+{
+    let mut scores = vec![f64::NAN; candidates.len()];
+    // Examine hash buckets.
+    for &location in locations {
+        // Calculate the weight bin outside the main loop.
+        let weight = location_weight(location, scale);
+        for sindex in bucket(location) {
+            let sindex = sindex as usize;
+            if !candidates.contains(&sindex) {
+                continue;
+            }
+            let sindex = sindex - candidates.start;
+            if scores[sindex].is_nan() {
+                scores[sindex] = initial_score();
+            }
+            // At this point, we have an entry in matches. Simply subtract the
+            // corresponding weight.
+            scores[sindex] -= weight;
+        }
+    }
+    scores
 }
 
+/// initial_score is the score of a candidate before the weights of the index lists
+/// it shares with the query are subtracted.
+pub(crate) fn initial_score() -> f64 {
+    WEIGHTS.iter().take(crate::haar::COLOURCHANNELS as usize).map(|weights| weights[0]).sum()
+}
+
+/// scale_coefficient_score returns the score of the difference of the scaling
+/// function coefficients of two images with scale x scale coefficients. The
+/// coefficient is the average colour times scale, as the transform is orthonormal.
+pub(crate) fn scale_coefficient_score(query: &crate::haar::Coef, candidate: &crate::haar::Coef, scale: u32) -> f64 {
+    WEIGHTS
+        .iter()
+        .zip(query.c.iter().zip(candidate.c.iter()))
+        .map(|(weights, (q, t))| weights[0] * (q - t).abs() / scale as f64)
+        .sum()
+}
+
+/// location_weight returns the weight that is subtracted from the score for a
+/// shared index list at the given location of a hash with scale x scale coefficients.
+pub(crate) fn location_weight(location: u32, scale: u32) -> f64 {
+    let coefindex = (location % (scale * scale * crate::haar::COLOURCHANNELS))
+                    / crate::haar::COLOURCHANNELS;
+    let y = coefindex / scale;
+    let x = coefindex % scale;
+    WEIGHTSUMS[x.max(y).min(5) as usize]
+}
+
+/// create_match compares the metrics of a candidate with the hash of the query.
+pub(crate) fn create_match(id: &str, ratio: f64, dhash: &[u64], histogram: u64,
+                           score: f64, hash: &crate::hash::Hash) -> crate::matches::Match {
+    let ratio_diff = (ratio.log(10.0) - hash.ratio.log(10.0)).abs();
+    let dhash_distance = crate::hamming::hamming_distance(dhash[0], hash.dhash[0])
+                        + crate::hamming::hamming_distance(dhash[1], hash.dhash[1]);
+    let histogram_distance = crate::hamming::hamming_distance(histogram, hash.histogram);
+    crate::matches::Match::from(id, score, ratio_diff, dhash_distance, histogram_distance)
+}
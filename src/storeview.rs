@@ -0,0 +1,342 @@
+//! Package storeview provides a read-only view of a Store that is memory-mapped
+//! from a flat snapshot file. Opening a view only checks the header and the
+//! offset table; candidates and index lists are read from the mapped file when
+//! a query touches them. Several processes querying the same snapshot share the
+//! page cache.
+//!
+//! The snapshot layout (all numbers big-endian):
+//!
+//!   header:     magic, version, candidate count, index list count,
+//!               sensitivity, total index entries, id count, id blob length,
+//!               hash config (scale, top coefficients, resize filter, perceptual
+//!               hashes, orientation switch), padding,
+//!               dHash and histogram distance limits of the sensitivity
+//!   offsets:    (index list count + 1) u64, start of each index list in the entries
+//!   entries:    u32 candidate indices of all index lists, one after another
+//!   candidates: fixed-size records of CANDIDATE_RECORD_LEN bytes
+//!   ids:        u32 candidate indices sorted by their ID, for lookups by ID
+//!   id blob:    the UTF-8 IDs the candidate records point into
+
+/// VIEW_MAGIC are the first bytes of every snapshot written by StoreView::write.
+pub const VIEW_MAGIC: [u8; 8] = *b"DUPLOVEW";
+/// VIEW_FORMAT_VERSION is the version of the snapshot layout.
+pub const VIEW_FORMAT_VERSION: u32 = 1;
+const VIEW_HEADER_LEN: usize = 104;
+// id offset, id length, reserved, ratio, dhash, histogram, histo_max and padding
+const CANDIDATE_RECORD_LEN: usize = 8 + 4 + 4 + 8 + 16 + 8 + 12 + 4;
+
+/// StoreView answers queries directly from a memory-mapped snapshot.
+#[derive(Debug)]
+pub struct StoreView {
+    map: memmap2::Mmap,
+    num_candidates: usize,
+    num_indices: usize,
+    num_ids: usize,
+    sensitivity: crate::sensitivity::Sensitivity,
+    config: crate::hashconfig::HashConfig,
+    offsets_start: usize,
+    entries_start: usize,
+    candidates_start: usize,
+    ids_start: usize,
+    blob_start: usize,
+}
+
+fn u32_at(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], pos: usize) -> u64 {
+    u64::from_be_bytes(bytes[pos..pos + 8].try_into().unwrap())
+}
+
+fn f64_at(bytes: &[u8], pos: usize) -> f64 {
+    f64::from_be_bytes(bytes[pos..pos + 8].try_into().unwrap())
+}
+
+impl StoreView {
+    /// write stores the store as a flat snapshot that can be opened as a StoreView.
+    /// The file is replaced atomically.
+    pub fn write(store: &crate::store::Store, viewfile: &str) -> crate::Result<()> {
+        // deleted images are left out of the index lists
+        let indices: Vec<Vec<u32>> = store
+            .indices
+            .iter()
+            .map(|list| list.iter().copied().filter(|index| !store.is_deleted(*index as usize)).collect())
+            .collect();
+        let entries: usize = indices.iter().map(|list| list.len()).sum();
+        let mut blob = Vec::new();
+        let mut records = Vec::with_capacity(store.candidates.len() * CANDIDATE_RECORD_LEN);
+        for candidate in store.candidates.iter() {
+            let mut dhash = [0_u64; 2];
+            for (to, from) in dhash.iter_mut().zip(candidate.dhash.iter()) {
+                *to = *from;
+            }
+            let mut histo_max = [0.0_f32; 3];
+            for (to, from) in histo_max.iter_mut().zip(candidate.histo_max.iter()) {
+                *to = *from;
+            }
+            crate::marshal::store_u64(blob.len() as u64, &mut records);
+            crate::marshal::store_u32(candidate.id.len() as u32, &mut records);
+            crate::marshal::store_u32(0, &mut records);
+            crate::marshal::store_f64(candidate.ratio, &mut records);
+            crate::marshal::store_u64(dhash[0], &mut records);
+            crate::marshal::store_u64(dhash[1], &mut records);
+            crate::marshal::store_u64(candidate.histogram, &mut records);
+            for value in histo_max {
+                crate::marshal::store_f32(value, &mut records);
+            }
+            crate::marshal::store_u32(0, &mut records);
+            blob.extend_from_slice(candidate.id.as_bytes());
+        }
+
+        let mut to = Vec::new();
+        to.extend_from_slice(&VIEW_MAGIC);
+        crate::marshal::store_u32(VIEW_FORMAT_VERSION, &mut to);
+        crate::marshal::store_u32(0, &mut to);
+        crate::marshal::store_u64(store.candidates.len() as u64, &mut to);
+        crate::marshal::store_u64(indices.len() as u64, &mut to);
+        crate::marshal::store_f64(store.sensitivity().score_threshold, &mut to);
+        crate::marshal::store_u64(entries as u64, &mut to);
+        crate::marshal::store_u64(store.ids.len() as u64, &mut to);
+        crate::marshal::store_u64(blob.len() as u64, &mut to);
+        store.config().encode(&mut to);
+        to.resize(88, 0);
+        crate::marshal::store_i64(store.sensitivity().max_dhash_distance, &mut to);
+        crate::marshal::store_i64(store.sensitivity().max_histogram_distance, &mut to);
+        let mut offset = 0_u64;
+        for list in indices.iter() {
+            crate::marshal::store_u64(offset, &mut to);
+            offset += list.len() as u64;
+        }
+        crate::marshal::store_u64(offset, &mut to);
+        for list in indices.iter() {
+            for index in list {
+                crate::marshal::store_u32(*index, &mut to);
+            }
+        }
+        to.extend_from_slice(&records);
+        // the BTreeMap is already sorted by ID
+        for index in store.ids.values() {
+            crate::marshal::store_u32(*index as u32, &mut to);
+        }
+        to.extend_from_slice(&blob);
+        crate::journal::write_atomic(std::path::Path::new(viewfile), &to)
+    }
+
+    /// open maps the snapshot into memory. Only the header and the offset table are
+    /// checked, nothing else is read until a query needs it.
+    pub fn open(viewfile: &str) -> crate::Result<Self> {
+        let file = std::fs::File::open(viewfile)?;
+        // SAFETY: the snapshot is only ever replaced by renaming a new file over it,
+        // so the mapped file is never modified while it is mapped.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        if map.len() < VIEW_HEADER_LEN || !map.starts_with(&VIEW_MAGIC) {
+            return Err(crate::Error::Decode(format!("{} is not a store view", viewfile)));
+        }
+        let version = u32_at(&map, 8);
+        if version != VIEW_FORMAT_VERSION {
+            return Err(crate::Error::Version(version));
+        }
+        let mut config = crate::hashconfig::HashConfig::new();
+        config.decode(&mut std::io::Cursor::new(map[64..88].to_vec()))?;
+        let truncated = || crate::Error::Decode(format!("store view {} is truncated", viewfile));
+        let num_candidates = u64_at(&map, 16) as usize;
+        let num_indices = u64_at(&map, 24) as usize;
+        let mut sensitivity = crate::sensitivity::Sensitivity::from_score_threshold(f64_at(&map, 32));
+        sensitivity.max_dhash_distance = u64_at(&map, 88) as i64;
+        sensitivity.max_histogram_distance = u64_at(&map, 96) as i64;
+        let num_entries = u64_at(&map, 40) as usize;
+        let num_ids = u64_at(&map, 48) as usize;
+        let blob_len = u64_at(&map, 56) as usize;
+        if num_indices < config.num_indices() as usize || num_ids > num_candidates {
+            return Err(crate::Error::Decode(format!("store view {} has an invalid header", viewfile)));
+        }
+        let offsets_start = VIEW_HEADER_LEN;
+        let entries_start = num_indices
+            .checked_add(1)
+            .and_then(|n| n.checked_mul(8))
+            .and_then(|n| n.checked_add(offsets_start))
+            .ok_or_else(truncated)?;
+        let candidates_start = num_entries
+            .checked_mul(4)
+            .and_then(|n| n.checked_add(entries_start))
+            .ok_or_else(truncated)?;
+        let ids_start = num_candidates
+            .checked_mul(CANDIDATE_RECORD_LEN)
+            .and_then(|n| n.checked_add(candidates_start))
+            .ok_or_else(truncated)?;
+        let blob_start = num_ids
+            .checked_mul(4)
+            .and_then(|n| n.checked_add(ids_start))
+            .ok_or_else(truncated)?;
+        if blob_start.checked_add(blob_len) != Some(map.len()) {
+            return Err(truncated());
+        }
+        let mut previous = 0;
+        for location in 0..=num_indices {
+            let offset = u64_at(&map, offsets_start + location * 8) as usize;
+            if offset < previous || offset > num_entries {
+                return Err(crate::Error::Decode(format!(
+                    "store view {} has an invalid offset table", viewfile)));
+            }
+            previous = offset;
+        }
+        Ok(StoreView {
+            map,
+            num_candidates,
+            num_indices,
+            num_ids,
+            sensitivity,
+            config,
+            offsets_start,
+            entries_start,
+            candidates_start,
+            ids_start,
+            blob_start,
+        })
+    }
+
+    /// size returns the number of images in the view, without deleted ones.
+    pub fn size(&self) -> usize {
+        self.num_ids
+    }
+
+    pub fn sensitivity(&self) -> crate::sensitivity::Sensitivity {
+        self.sensitivity
+    }
+
+    /// config returns the parameters the hashes of the view are made with.
+    pub fn config(&self) -> crate::hashconfig::HashConfig {
+        self.config
+    }
+
+    /// bucket returns the candidate indices of the index list at the given location.
+    fn bucket(&self, location: u32) -> impl Iterator<Item = u32> + '_ {
+        let location = location as usize;
+        let (start, end) = if location < self.num_indices {
+            (u64_at(&self.map, self.offsets_start + location * 8) as usize,
+             u64_at(&self.map, self.offsets_start + (location + 1) * 8) as usize)
+        } else {
+            (0, 0)
+        };
+        (start..end).map(move |entry| u32_at(&self.map, self.entries_start + entry * 4))
+    }
+
+    fn record(&self, index: usize) -> usize {
+        self.candidates_start + index * CANDIDATE_RECORD_LEN
+    }
+
+    /// id returns the ID of the candidate with the given index. Deleted candidates
+    /// have an empty ID.
+    pub fn id(&self, index: usize) -> &str {
+        if index >= self.num_candidates {
+            return "";
+        }
+        let record = self.record(index);
+        let offset = u64_at(&self.map, record) as usize;
+        let length = u32_at(&self.map, record + 8) as usize;
+        let bytes = offset
+            .checked_add(length)
+            .and_then(|end| self.map.get(self.blob_start + offset..self.blob_start + end));
+        match bytes.map(std::str::from_utf8) {
+            Some(Ok(id)) => id,
+            _ => {
+                log::error!("Store view has an invalid ID for candidate {}.", index);
+                ""
+            }
+        }
+    }
+
+    /// index returns the candidate index of the image with the given ID.
+    pub fn index(&self, id: &str) -> Option<usize> {
+        let (mut low, mut high) = (0, self.num_ids);
+        while low < high {
+            let middle = (low + high) / 2;
+            let index = u32_at(&self.map, self.ids_start + middle * 4) as usize;
+            if index >= self.num_candidates {
+                return None;
+            }
+            match self.id(index).cmp(id) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Some(index),
+            }
+        }
+        None
+    }
+
+    pub fn has(&self, id: &str) -> bool {
+        self.index(id).is_some()
+    }
+
+    /// ids returns the IDs of all images in the view in sorted order.
+    pub fn ids(&self) -> Vec<String> {
+        let mut v = Vec::new();
+        for position in 0..self.num_ids {
+            let index = u32_at(&self.map, self.ids_start + position * 4) as usize;
+            if index < self.num_candidates {
+                v.push(self.id(index).to_string());
+            }
+        }
+        v
+    }
+
+    /// Query performs a similarity search on the given image hashes exactly like
+    /// Store::query, reading only the index lists and candidates it needs.
+    pub fn query(&self, hash: &crate::hash::Hash) -> crate::matches::Matches {
+        self.query_with(hash, &crate::queryoptions::QueryOptions::new())
+    }
+
+    /// query_with performs a similarity search exactly like Store::query_with. The
+    /// view has no perceptual hashes, so matches cannot be verified with them.
+    pub fn query_with(&self, hash: &crate::hash::Hash,
+                      options: &crate::queryoptions::QueryOptions) -> crate::matches::Matches {
+        if self.num_candidates == 0 {
+            return crate::matches::Matches::new();
+        }
+        if let Err(error) = self.config.check(&hash.config) {
+            log::error!("Cannot query the store view: {}", error);
+            return crate::matches::Matches::new();
+        }
+        let locations = crate::store::hash_locations(hash);
+        let scores = crate::store::score_buckets(&locations, self.config.scale, 0..self.num_candidates, |location| {
+            self.bucket(location)
+        });
+        let excluded = options.exclude_id.as_ref().and_then(|id| self.index(id));
+        let skip = |index: usize| Some(index) == excluded;
+        crate::queryoptions::select_matches(&scores, &self.sensitivity, options, skip, |index, score| {
+            let record = self.record(index);
+            let ratio = f64_at(&self.map, record + 16);
+            let dhash = [u64_at(&self.map, record + 24), u64_at(&self.map, record + 32)];
+            let histogram = u64_at(&self.map, record + 40);
+            crate::store::create_match(self.id(index), ratio, &dhash, histogram, score, hash)
+        })
+    }
+
+    /// query_dihedral performs a similarity search with the rotated and mirrored
+    /// hashes exactly like Store::query_dihedral.
+    pub fn query_dihedral(&self, hashes: &[(crate::dihedral::Dihedral, crate::hash::Hash)],
+                          options: &crate::queryoptions::QueryOptions) -> crate::matches::Matches {
+        let results = hashes.iter().flat_map(|(transform, hash)| {
+            self.query_with(hash, options).m.into_iter().map(|mut m| {
+                m.transform = *transform;
+                m
+            })
+        });
+        crate::matches::best_per_id(results, options.max_results)
+    }
+
+    /// query_contains performs a similarity search with the tiles of the query image
+    /// exactly like Store::query_contains. The view has no region index, so it
+    /// cannot answer Store::query_region.
+    pub fn query_contains(&self, regions: &[(crate::region::BoundingBox, crate::hash::Hash)],
+                          options: &crate::queryoptions::QueryOptions) -> crate::matches::Matches {
+        let results = regions.iter().flat_map(|(region, hash)| {
+            self.query_with(hash, options).m.into_iter().map(|mut m| {
+                m.region = Some(*region);
+                m
+            })
+        });
+        crate::matches::best_per_id(results, options.max_results)
+    }
+}
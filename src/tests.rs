@@ -76,145 +76,61 @@ const IMGC: &str = "/9j/4AAQSkZJRgABAQAAAQABAAD//gA7Q1JFQVRPUjogZ2QtanBlZyB2MS4w
     cyuUs7a2lP8UaBSfoQKwl1uS5jKzMWQD7maR7+GSH5Dux0Dc7aSi+o210IXuZfMb97J1PeiqZmO\
     TzRWpmZCVajP86KKtkojbic4p7k7c5NFFIZGCeuTnNTMxwpyc+tFFAC5PrRRRQM/9k=";
 
+// Decodes one of the base64 encoded test JPEGs above.
+fn load_image(data: &str) -> image::DynamicImage {
+    use base64::{engine::general_purpose, Engine as _};
+
+    let bytes = general_purpose::STANDARD.decode(data.as_bytes()).unwrap();
+    image::load_from_memory_with_format(&bytes, image::ImageFormat::Jpeg).unwrap()
+}
+
+// Hashes one of the base64 encoded test JPEGs above.
+fn load_hash(data: &str) -> crate::hash::Hash {
+    let (hash, _small) = crate::hash::create_hash(&load_image(data).into(), crate::hashconfig::HashConfig::new()).unwrap();
+    hash
+}
+
 #[test]
 fn test_quick_select() {
-    let mut coefs = Vec::new();
-    coefs.push(crate::haar::Coef::from(1.0, -5.0, 0.0));
-    coefs.push(crate::haar::Coef::from(2.0, 2.0, 0.0));
-    coefs.push(crate::haar::Coef::from(3.0, -7.5, 0.0));
-    coefs.push(crate::haar::Coef::from(4.0, 1.0, 0.0));
-    coefs.push(crate::haar::Coef::from(5.0, 0.0, 0.0));
-    coefs.push(crate::haar::Coef::from(6.0, 6.0, 0.0));
-    coefs.push(crate::haar::Coef::from(7.0, -3.0, 0.0));
-    coefs.push(crate::haar::Coef::from(8.0, -9.0, 0.0));
-    coefs.push(crate::haar::Coef::from(9.0, 4.7, 0.0));
-    coefs.push(crate::haar::Coef::from(10.0, 4.7, 0.0));
-    coefs.push(crate::haar::Coef::from(11.0, 8.0, 0.0));
-    coefs.push(crate::haar::Coef::from(12.0, -2.2, 0.0));
-    let thresholds = crate::hash::coef_thresholds(&mut coefs, 4);
+    let coefs = vec![
+        crate::haar::Coef::from(1.0, -5.0, 0.0),
+        crate::haar::Coef::from(2.0, 2.0, 0.0),
+        crate::haar::Coef::from(3.0, -7.5, 0.0),
+        crate::haar::Coef::from(4.0, 1.0, 0.0),
+        crate::haar::Coef::from(5.0, 0.0, 0.0),
+        crate::haar::Coef::from(6.0, 6.0, 0.0),
+        crate::haar::Coef::from(7.0, -3.0, 0.0),
+        crate::haar::Coef::from(8.0, -9.0, 0.0),
+        crate::haar::Coef::from(9.0, 4.7, 0.0),
+        crate::haar::Coef::from(10.0, 4.7, 0.0),
+        crate::haar::Coef::from(11.0, 8.0, 0.0),
+        crate::haar::Coef::from(12.0, -2.2, 0.0),
+    ];
+    let thresholds = crate::hash::coef_thresholds(&coefs, 4);
     assert!((thresholds.c[0] - 9.0).abs() > 0.02 || (thresholds.c[1] - 6.0).abs() > 0.02);
 }
 
 #[test]
 fn test_query() {
-    use base64::{engine::general_purpose, Engine as _};
-    use image;
-
-    let bytes_a = general_purpose::STANDARD.decode(IMGA.as_bytes());
-    if bytes_a.is_err() {
-        assert!(false);
-    }
-    let img_a;
-    match image::load_from_memory_with_format(&bytes_a.unwrap(), image::ImageFormat::Jpeg) {
-        Ok(img) => {
-            img_a = img;
-        }
-        Err(_) => {
-            println!("Image A could not be created!");
-            std::process::exit(1);
-        }
-    }
-    let bytes_b = general_purpose::STANDARD.decode(IMGB.as_bytes());
-    if bytes_b.is_err() {
-        assert!(false);
-    }
-    let img_b;
-    match image::load_from_memory_with_format(&bytes_b.unwrap(), image::ImageFormat::Jpeg) {
-        Ok(img) => {
-            img_b = img;
-        }
-        Err(_) => {
-            println!("Image B could not be created!");
-            std::process::exit(1);
-        }
-    }
-    let bytes_c = general_purpose::STANDARD.decode(IMGC.as_bytes());
-    if bytes_c.is_err() {
-        assert!(false);
-    }
-    let img_c;
-    match image::load_from_memory_with_format(&bytes_c.unwrap(), image::ImageFormat::Jpeg) {
-        Ok(img) => {
-            img_c = img;
-        }
-        Err(_) => {
-            println!("Image C could not be created!");
-            std::process::exit(1);
-        }
-    }
-
-    let mut store = crate::store::Store::new(100.0);
-    let (hash_a, _small_a) = crate::hash::create_hash(&img_a.into());
-    let (hash_b, _small_b) = crate::hash::create_hash(&img_b.into());
-    store.add("imgA", &hash_a);
-    store.add("imgB", &hash_b);
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    store.add("imgA", &load_hash(IMGA)).unwrap();
+    store.add("imgB", &load_hash(IMGB)).unwrap();
 
     // Query the store.
-    let (queryhash, _small_c) = crate::hash::create_hash(&img_c.into());
+    let queryhash = load_hash(IMGC);
     let matches = store.query(&queryhash);
-    assert!(matches.m.len() > 0);
+    assert!(!matches.m.is_empty());
     assert!(matches.m[0].id == "imgA");
 }
 
 #[test]
 fn test_delete() {
-    use base64::{engine::general_purpose, Engine as _};
-    use image;
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    let queryhash = load_hash(IMGC);
+    store.add("imgA", &load_hash(IMGA)).unwrap();
+    store.add("imgB", &load_hash(IMGB)).unwrap();
 
-    let bytes_a = general_purpose::STANDARD.decode(IMGA.as_bytes());
-    if bytes_a.is_err() {
-        assert!(false);
-    }
-    let img_a;
-    match image::load_from_memory_with_format(&bytes_a.unwrap(), image::ImageFormat::Jpeg) {
-        Ok(img) => {
-            img_a = img;
-        }
-        Err(_) => {
-            println!("Image A could not be created!");
-            std::process::exit(1);
-        }
-    }
-    let bytes_b = general_purpose::STANDARD.decode(IMGB.as_bytes());
-    if bytes_b.is_err() {
-        assert!(false);
-    }
-    let img_b;
-    match image::load_from_memory_with_format(&bytes_b.unwrap(), image::ImageFormat::Jpeg) {
-        Ok(img) => {
-            img_b = img;
-        }
-        Err(_) => {
-            println!("Image B could not be created!");
-            std::process::exit(1);
-        }
-    }
-    let bytes_c = general_purpose::STANDARD.decode(IMGC.as_bytes());
-    if bytes_c.is_err() {
-        assert!(false);
-    }
-    let bytes_c = general_purpose::STANDARD.decode(IMGC.as_bytes());
-    if bytes_c.is_err() {
-        assert!(false);
-    }
-    let img_c;
-    match image::load_from_memory_with_format(&bytes_c.unwrap(), image::ImageFormat::Jpeg) {
-        Ok(img) => {
-            img_c = img;
-        }
-        Err(_) => {
-            println!("Image C could not be created!");
-            std::process::exit(1);
-        }
-    }
-    let mut store = crate::store::Store::new(100.0);
-    let (hash_a, _small_a) = crate::hash::create_hash(&img_a.into());
-    let (hash_b, _small_b) = crate::hash::create_hash(&img_b.into());
-    let (queryhash, _small_c) = crate::hash::create_hash(&img_c.into());
-    store.add("imgA", &hash_a);
-    store.add("imgB", &hash_b);
-
-    store.delete("imgA");
+    store.delete("imgA").unwrap();
 
     let matches = store.query(&queryhash);
     assert!(matches.m.len() == 1);
@@ -223,62 +139,10 @@ fn test_delete() {
 
 #[test]
 fn test_ids() {
-    use base64::{engine::general_purpose, Engine as _};
-    use image;
-
-    let bytes_a = general_purpose::STANDARD.decode(IMGA.as_bytes());
-    if bytes_a.is_err() {
-        assert!(false);
-    }
-    let img_a;
-    match image::load_from_memory_with_format(&bytes_a.unwrap(), image::ImageFormat::Jpeg) {
-        Ok(img) => {
-            img_a = img;
-        }
-        Err(_) => {
-            println!("Image A could not be created!");
-            std::process::exit(1);
-        }
-    }
-    let bytes_b = general_purpose::STANDARD.decode(IMGB.as_bytes());
-    if bytes_b.is_err() {
-        assert!(false);
-    }
-    let img_b;
-    match image::load_from_memory_with_format(&bytes_b.unwrap(), image::ImageFormat::Jpeg) {
-        Ok(img) => {
-            img_b = img;
-        }
-        Err(_) => {
-            println!("Image B could not be created!");
-            std::process::exit(1);
-        }
-    }
-    let bytes_c = general_purpose::STANDARD.decode(IMGC.as_bytes());
-    if bytes_c.is_err() {
-        assert!(false);
-    }
-    let bytes_c = general_purpose::STANDARD.decode(IMGC.as_bytes());
-    if bytes_c.is_err() {
-        assert!(false);
-    }
-    let img_c;
-    match image::load_from_memory_with_format(&bytes_c.unwrap(), image::ImageFormat::Jpeg) {
-        Ok(img) => {
-            img_c = img;
-        }
-        Err(_) => {
-            println!("Image C could not be created!");
-            std::process::exit(1);
-        }
-    }
-    let mut store = crate::store::Store::new(100.0);
-    let (hash_a, _small_a) = crate::hash::create_hash(&img_a.into());
-    let (hash_b, _small_b) = crate::hash::create_hash(&img_b.into());
-    let (hash_c, _small_c) = crate::hash::create_hash(&img_c.into());
-    store.add("imgA", &hash_a);
-    store.add("imgB", &hash_b);
-    store.add("imgC", &hash_c);
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    store.add("imgA", &load_hash(IMGA)).unwrap();
+    store.add("imgB", &load_hash(IMGB)).unwrap();
+    store.add("imgC", &load_hash(IMGC)).unwrap();
 
     let ids = store.ids();
     assert!(ids.len() == 3);
@@ -286,7 +150,7 @@ fn test_ids() {
     assert!(ids[1] == "imgB");
     assert!(ids[2] == "imgC");
 
-    store.delete("imgA");
+    store.delete("imgA").unwrap();
 
     let ids = store.ids();
     assert!(ids.len() == 2);
@@ -296,74 +160,1203 @@ fn test_ids() {
 
 #[test]
 fn test_exchange() {
-    use base64::{engine::general_purpose, Engine as _};
-    use image;
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    store.add("imgA", &load_hash(IMGA)).unwrap();
+    store.add("imgB", &load_hash(IMGB)).unwrap();
+    store.add("imgC", &load_hash(IMGC)).unwrap();
+    // Test failure to find original ID.
+    assert!(!store.exchange("does not exist", "is irrelevant").unwrap());
+    assert!(store.ids.len() == 3);
+    // Test failure to rename into existing ID.
+    assert!(!store.exchange("imgA", "imgB").unwrap());
+
+    // Now rename and check result.
+    assert!(store.exchange("imgA", "imgD").unwrap());
+    assert!(store.ids.len() == 3);
+    assert!(!store.ids.contains_key("imgA"));
+    assert!(store.ids.contains_key("imgD"));
+    assert!(store.candidates[0].id == "imgD");
+    assert!(store.ids.contains_key("imgB"));
+    assert!(store.ids.contains_key("imgC"));
+}
+
+#[test]
+fn test_truncated_stream() {
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    store.add("imgA", &load_hash(IMGA)).unwrap();
+    let mut bytes = Vec::new();
+    store.encode(&mut bytes);
+    bytes.truncate(bytes.len() / 2);
+
+    // A damaged stream is reported instead of panicking.
+    let mut restored = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    let mut cursor = std::io::Cursor::new(bytes);
+    assert!(matches!(restored.decode(&mut cursor), Err(crate::Error::Decode(_))));
 
-    let bytes_a = general_purpose::STANDARD.decode(IMGA.as_bytes());
-    if bytes_a.is_err() {
-        assert!(false);
+    // So is an image that cannot be hashed.
+    let empty = image::RgbaImage::new(0, 0);
+    assert!(crate::hash::create_hash(&empty, crate::hashconfig::HashConfig::new()).is_err());
+}
+
+#[test]
+fn test_store_format() {
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    store.add("imgA", &load_hash(IMGA)).unwrap();
+    store.add("bäume/imgB", &load_hash(IMGB)).unwrap();
+
+    // Round trip through the versioned format.
+    let mut bytes = Vec::new();
+    store.encode_versioned(&mut bytes);
+    assert!(bytes.starts_with(&crate::store::STORE_MAGIC));
+    let mut restored = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(0.0), crate::hashconfig::HashConfig::new());
+    restored.decode_versioned(bytes.clone()).unwrap();
+    assert!(restored.ids() == store.ids());
+    assert!(restored.indices == store.indices);
+    assert!(!restored.modified());
+
+    // A flipped bit is detected before decoding.
+    let mut corrupt = bytes.clone();
+    let middle = corrupt.len() / 2;
+    corrupt[middle] ^= 0x01;
+    let ret = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(0.0), crate::hashconfig::HashConfig::new()).decode_versioned(corrupt);
+    assert!(matches!(ret, Err(crate::Error::Checksum { .. })));
+
+    // Unknown versions are refused.
+    let mut future = bytes;
+    future[11] = 99;
+    let ret = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(0.0), crate::hashconfig::HashConfig::new()).decode_versioned(future);
+    assert!(matches!(ret, Err(crate::Error::Version(_))));
+
+    // Headerless streams of format version 1 are migrated.
+    let mut legacy = Vec::new();
+    store.encode(&mut legacy);
+    let mut migrated = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(0.0), crate::hashconfig::HashConfig::new());
+    migrated.decode_versioned(legacy).unwrap();
+    assert!(migrated.ids() == store.ids());
+    assert!(migrated.modified());
+    // Their integer ratios are unknown and not matched by ratio limits.
+    assert!(migrated.candidates.iter().all(|candidate| candidate.ratio.is_nan()));
+    // Nor do they have a scaling function coefficient.
+    assert!(migrated.candidates.iter().all(|candidate| candidate.scale_coeff.c.is_empty()));
+    let options = crate::queryoptions::QueryOptions {
+        max_ratio_diff: Some(1.0),
+        ..Default::default()
+    };
+    assert!(migrated.query_with(&load_hash(IMGA), &options).m.is_empty());
+}
+
+#[test]
+fn test_journal() {
+    let dir = std::env::temp_dir().join(format!("duplo-rs-journal-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let storefile = dir.join("test.store").to_string_lossy().to_string();
+
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    store.add("imgA", &load_hash(IMGA)).unwrap();
+    store.dump_binary(&storefile).unwrap();
+
+    // Changes after the snapshot only go to the journal, flushed in batches.
+    store.set_sync_policy(crate::journal::SyncPolicy::Every(3)).unwrap();
+    store.add("imgB", &load_hash(IMGB)).unwrap();
+    store.add("imgC", &load_hash(IMGC)).unwrap();
+    store.delete("imgA").unwrap();
+    store.set_sync_policy(crate::journal::SyncPolicy::OnFlush).unwrap();
+    assert!(store.exchange("imgB", "imgD").unwrap());
+    store.flush().unwrap();
+
+    // A record torn by a crash is dropped, everything before it is replayed.
+    let journalpath = crate::journal::Journal::path_for(&storefile);
+    let mut journal = std::fs::OpenOptions::new().append(true).open(&journalpath).unwrap();
+    std::io::Write::write_all(&mut journal, &[1, 0, 0, 0]).unwrap();
+    let mut restored = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    restored.slurp_binary(&storefile).unwrap();
+    assert!(restored.ids() == store.ids());
+    assert!(restored.indices == store.indices);
+
+    // A complete record with a payload that is too short is corruption, not a tear.
+    let mut payload = Vec::new();
+    crate::candidate::Candidate::from("imgE", &load_hash(IMGA)).encode(&mut payload);
+    crate::marshal::store_vec_u32(&[1, 2, 3], &mut payload);
+    let mut record = vec![1];
+    crate::marshal::store_usize(payload.len(), &mut record);
+    record.extend_from_slice(&payload);
+    let checksum = crate::marshal::checksum(&record);
+    crate::marshal::store_u32(checksum, &mut record);
+    assert!(matches!(crate::journal::Record::decode(&record), Err(crate::Error::Decode(_))));
+    let mut journal = std::fs::OpenOptions::new().append(true).open(&journalpath).unwrap();
+    std::io::Write::write_all(&mut journal, &record).unwrap();
+    let mut corrupt = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    assert!(matches!(corrupt.slurp_binary(&storefile), Err(crate::Error::Decode(_))));
+
+    // After a new snapshot the old journal is not replayed again.
+    restored.add("imgA", &load_hash(IMGA)).unwrap();
+    restored.dump_binary(&storefile).unwrap();
+    let mut reloaded = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    reloaded.slurp_binary(&storefile).unwrap();
+    assert!(reloaded.ids() == vec!["imgA", "imgC", "imgD"]);
+    assert!(!reloaded.modified());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_store_view() {
+    let dir = std::env::temp_dir().join(format!("duplo-rs-view-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let viewfile = dir.join("test.view").to_string_lossy().to_string();
+
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    store.add("imgA", &load_hash(IMGA)).unwrap();
+    store.add("imgB", &load_hash(IMGB)).unwrap();
+    store.add("imgD", &load_hash(IMGB)).unwrap();
+    store.delete("imgD").unwrap();
+    crate::storeview::StoreView::write(&store, &viewfile).unwrap();
+
+    // The view answers queries exactly like the store it was written from.
+    let view = crate::storeview::StoreView::open(&viewfile).unwrap();
+    let queryhash = load_hash(IMGC);
+    assert!(view.query(&queryhash) == store.query(&queryhash));
+    assert!(view.ids() == store.ids());
+    assert!(view.index("imgB") == Some(1));
+    assert!(!view.has("imgD"));
+
+    // A truncated snapshot is refused.
+    let bytes = std::fs::read(&viewfile).unwrap();
+    std::fs::write(&viewfile, &bytes[..bytes.len() - 1]).unwrap();
+    assert!(crate::storeview::StoreView::open(&viewfile).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_query_with() {
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    store.add("imgA", &load_hash(IMGA)).unwrap();
+    store.add("imgB", &load_hash(IMGB)).unwrap();
+    store.add("imgC", &load_hash(IMGC)).unwrap();
+    let queryhash = load_hash(IMGC);
+    let all = store.query(&queryhash);
+    assert!(all.m.len() == 3);
+
+    // The query image itself is left out.
+    let mut options = crate::queryoptions::QueryOptions::new();
+    options.exclude_id = Some("imgC".to_string());
+    let matches = store.query_with(&queryhash, &options);
+    assert!(matches.m.len() == 2);
+    assert!(matches.m.iter().all(|m| m.id != "imgC"));
+
+    // Only the best match is returned.
+    options.max_results = Some(1);
+    let matches = store.query_with(&queryhash, &options);
+    assert!(matches.m.len() == 1);
+    assert!(matches.m[0] == all.m[1]);
+
+    // Secondary metrics and the score threshold filter the matches.
+    let mut options = crate::queryoptions::QueryOptions::new();
+    options.max_dhash_distance = Some(0);
+    options.max_ratio_diff = Some(0.0);
+    let matches = store.query_with(&queryhash, &options);
+    assert!(matches.m.len() == 1 && matches.m[0].id == "imgC");
+    let mut options = crate::queryoptions::QueryOptions::new();
+    options.score_threshold = Some(all.m[0].score);
+    assert!(store.query_with(&queryhash, &options).m.is_empty());
+}
+
+#[test]
+fn test_parallel_query() {
+    let store = crate::sharedstore::SharedStore::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    let hashes = [load_hash(IMGA), load_hash(IMGB), load_hash(IMGC)];
+
+    // One writer adds while several readers query.
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            for (number, hash) in hashes.iter().cycle().take(30).enumerate() {
+                store.add(&format!("img{}", number), hash).unwrap();
+            }
+        });
+        for _ in 0..3 {
+            scope.spawn(|| {
+                for _ in 0..10 {
+                    assert!(store.query(&hashes[2]).len() <= store.size());
+                }
+            });
+        }
+    });
+    assert!(store.size() == 30);
+
+    // Splitting the scan across threads does not change the result.
+    let sequential = {
+        let mut options = crate::queryoptions::QueryOptions::new();
+        options.num_threads = Some(1);
+        store.query_with(&hashes[2], &options)
+    };
+    for num_threads in [2, 4, 7, 64] {
+        let mut options = crate::queryoptions::QueryOptions::new();
+        options.num_threads = Some(num_threads);
+        assert!(store.query_with(&hashes[2], &options) == sequential);
     }
-    let img_a;
-    match image::load_from_memory_with_format(&bytes_a.unwrap(), image::ImageFormat::Jpeg) {
-        Ok(img) => {
-            img_a = img;
+}
+
+#[test]
+fn test_duplicate_groups() {
+    let hashes = [("imgA", load_hash(IMGA)), ("imgB", load_hash(IMGB)),
+                  ("imgC", load_hash(IMGC)), ("imgD", load_hash(IMGB))];
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    for (id, hash) in hashes.iter() {
+        store.add(id, hash).unwrap();
+    }
+
+    // Everything is similar with this sensitivity, so there is one group with all pairs.
+    let options = crate::queryoptions::QueryOptions::new();
+    let groups = store.find_duplicate_groups(&options);
+    assert!(groups.len() == 1);
+    assert!(groups[0].ids == vec!["imgA", "imgB", "imgC", "imgD"]);
+    assert!(groups[0].pairs.len() == 6);
+
+    // Pairs have the score a query would give them.
+    let matches = store.query(&hashes[1].1);
+    let pair = groups[0].pairs.iter().find(|pair| pair.id == "imgB" && pair.m.id == "imgD").unwrap();
+    let m = matches.m.iter().find(|m| m.id == "imgD").unwrap();
+    assert!((pair.m.score - m.score).abs() < 1e-9);
+
+    // Only the identical images are left with a strict threshold.
+    let mut options = crate::queryoptions::QueryOptions::new();
+    options.score_threshold = Some(m.score + 1e-6);
+    let groups = store.find_duplicate_groups(&options);
+    assert!(groups.len() == 1);
+    assert!(groups[0].ids == vec!["imgB", "imgD"]);
+
+    // The result does not depend on the order the images were added in.
+    let mut reversed = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    for (id, hash) in hashes.iter().rev() {
+        reversed.add(id, hash).unwrap();
+    }
+    let groups2 = reversed.find_duplicate_groups(&options);
+    assert!(groups2.len() == 1 && groups2[0].ids == groups[0].ids);
+    assert!(groups2[0].pairs[0].id == "imgB" && groups2[0].pairs[0].m.id == "imgD");
+}
+
+#[test]
+fn test_compact() {
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    let queryhash = load_hash(IMGC);
+    store.add("imgA", &load_hash(IMGA)).unwrap();
+    store.add("imgB", &load_hash(IMGB)).unwrap();
+    store.add("imgC", &load_hash(IMGC)).unwrap();
+    let before = store.query(&queryhash);
+
+    // Deleting only marks the candidate, the index lists keep it until compaction.
+    store.delete("imgB").unwrap();
+    assert!(store.size() == 2);
+    assert!(store.is_deleted(1));
+    assert!(store.indices.iter().any(|list| list.contains(&1)));
+    let matches = store.query(&queryhash);
+    assert!(matches.m.iter().all(|m| m.id != "imgB"));
+    assert!(matches.len() == 2);
+
+    store.compact();
+    assert!(store.size() == 2 && store.candidates.len() == 2);
+    assert!(store.ids.get("imgC") == Some(&1));
+    assert!(store.indices.iter().all(|list| list.iter().all(|index| *index < 2)));
+    assert!(store.indices.iter().all(|list| list.windows(2).all(|pair| pair[0] < pair[1])));
+    let compacted = store.query(&queryhash);
+    let expected: Vec<_> = before.m.iter().filter(|m| m.id != "imgB").collect();
+    assert!(compacted.len() == expected.len());
+    for (a, b) in compacted.m.iter().zip(expected) {
+        assert!(a.id == b.id && (a.score - b.score).abs() < 1e-9);
+    }
+
+    // Tombstones survive a round trip through the binary format.
+    store.delete("imgA").unwrap();
+    let mut buffer = Vec::new();
+    store.encode(&mut buffer);
+    let mut restored = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    restored.decode(&mut std::io::Cursor::new(buffer)).unwrap();
+    assert!(restored.size() == 1 && restored.is_deleted(0));
+    assert!(restored.query(&queryhash).m.iter().all(|m| m.id == "imgC"));
+}
+
+#[test]
+fn test_merge() {
+    let mut first = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    first.add("imgA", &load_hash(IMGA)).unwrap();
+    first.add("imgB", &load_hash(IMGB)).unwrap();
+    let mut second = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    second.add("imgX", &load_hash(IMGA)).unwrap();
+    second.add("imgB", &load_hash(IMGC)).unwrap();
+    second.add("imgC", &load_hash(IMGC)).unwrap();
+    second.delete("imgX").unwrap();
+
+    // A collision fails without changing anything.
+    let mut merged = first.clone();
+    assert!(merged.merge(&second, crate::store::MergePolicy::Fail).is_err());
+    assert!(merged == first);
+    assert!(merged.merge(&crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(50.0), crate::hashconfig::HashConfig::new()), crate::store::MergePolicy::KeepExisting).is_err());
+
+    // The merged store answers queries like a store with all images added to it.
+    assert!(merged.merge(&second, crate::store::MergePolicy::KeepExisting).unwrap() == 1);
+    let mut expected = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    expected.add("imgA", &load_hash(IMGA)).unwrap();
+    expected.add("imgB", &load_hash(IMGB)).unwrap();
+    expected.add("imgC", &load_hash(IMGC)).unwrap();
+    let queryhash = load_hash(IMGC);
+    let (a, b) = (merged.query(&queryhash), expected.query(&queryhash));
+    assert!(merged.size() == 3 && a.len() == b.len());
+    for (a, b) in a.m.iter().zip(b.m.iter()) {
+        assert!(a.id == b.id && (a.score - b.score).abs() < 1e-9);
+    }
+
+    // Replace takes the image of the other store.
+    let mut replaced = first.clone();
+    assert!(replaced.merge(&second, crate::store::MergePolicy::Replace).unwrap() == 2);
+    assert!(replaced.size() == 3);
+    let matches = replaced.query(&queryhash);
+    assert!(matches.m[0].id == "imgB" || matches.m[0].id == "imgC");
+    assert!(matches.m[0].score == matches.m[1].score);
+}
+
+#[test]
+fn test_query_by_id() {
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    let hashes = [("imgA", load_hash(IMGA)), ("imgB", load_hash(IMGB)), ("imgC", load_hash(IMGC))];
+    for (id, hash) in hashes.iter() {
+        store.add(id, hash).unwrap();
+    }
+    assert!(store.query_by_id("imgX", &crate::queryoptions::QueryOptions::new()).is_none());
+
+    // A query by ID gives the same result as a query with the hash of the image.
+    let expected = store.query(&hashes[2].1);
+    let check = |store: &crate::store::Store| {
+        let matches = store.query_by_id("imgC", &crate::queryoptions::QueryOptions::new()).unwrap();
+        assert!(matches.len() == expected.len());
+        for (a, b) in matches.m.iter().zip(expected.m.iter()) {
+            assert!(a.id == b.id && (a.score - b.score).abs() < 1e-9);
+            assert!(a.dhash_distance == b.dhash_distance && a.histogram_distance == b.histogram_distance);
         }
-        Err(_) => {
-            println!("Image A could not be created!");
-            std::process::exit(1);
+        let neighbours = store.neighbours("imgC").unwrap();
+        assert!(neighbours.len() == expected.len() - 1);
+        assert!(neighbours.m.iter().all(|m| m.id != "imgC"));
+    };
+    check(&store);
+
+    // The signature is restored when the store is read back.
+    let mut buffer = Vec::new();
+    store.encode(&mut buffer);
+    let mut restored = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    restored.decode(&mut std::io::Cursor::new(buffer)).unwrap();
+    assert!(restored.candidates[2].signature == store.candidates[2].signature);
+    check(&restored);
+}
+
+#[test]
+fn test_signature() {
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    store.add("imgA", &load_hash(IMGA)).unwrap();
+    store.add("imgB", &load_hash(IMGB)).unwrap();
+    let hash = load_hash(IMGC);
+
+    // A hash restored from its signature gives the same query results.
+    let signature = hash.to_signature();
+    let restored = crate::hash::Hash::from_signature(&signature).unwrap();
+    assert!(restored.to_signature() == signature);
+    let (a, b) = (store.query(&hash), store.query(&restored));
+    assert!(a == b);
+
+    assert!(crate::hash::Hash::from_signature("not a signature!").is_err());
+    assert!(crate::hash::Hash::from_signature(&signature[..signature.len() / 2]).is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    store.add("imgA", &load_hash(IMGA)).unwrap();
+    let hash = load_hash(IMGC);
+    let matches = store.query(&hash);
+
+    let json = serde_json::to_string(&matches).unwrap();
+    assert!(serde_json::from_str::<crate::matches::Matches>(&json).unwrap() == matches);
+    let json = serde_json::to_string(&hash).unwrap();
+    assert!(serde_json::from_str::<crate::hash::Hash>(&json).unwrap() == hash);
+    let candidate = &store.candidates[0];
+    let json = serde_json::to_string(candidate).unwrap();
+    assert!(serde_json::from_str::<crate::candidate::Candidate>(&json).unwrap() == *candidate);
+}
+
+#[test]
+fn test_hash_cache() {
+    use base64::{engine::general_purpose, Engine as _};
+
+    let dir = std::env::temp_dir().join(format!("duplo-rs-hashcache-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let imagefile = dir.join("image.jpg");
+    let cachefile = dir.join("test.hashcache").to_string_lossy().to_string();
+    std::fs::write(&imagefile, general_purpose::STANDARD.decode(IMGA.as_bytes()).unwrap()).unwrap();
+
+    // A new file is hashed once, afterwards the cached hash is used.
+    let mut cache = crate::hashcache::HashCache::load(&cachefile).unwrap();
+    assert!(cache.is_empty() && cache.changed(&imagefile).unwrap());
+    let hash = cache.process_image(&imagefile, crate::hashconfig::HashConfig::new()).unwrap();
+    assert!(cache.len() == 1 && !cache.changed(&imagefile).unwrap());
+    let cached = cache.get(&imagefile).unwrap().unwrap();
+    assert!(cached.to_signature() == hash.to_signature());
+
+    // The cache survives a restart.
+    cache.save(&cachefile).unwrap();
+    let mut cache = crate::hashcache::HashCache::load(&cachefile).unwrap();
+    assert!(!cache.changed(&imagefile).unwrap());
+
+    // A modified file is hashed again.
+    std::fs::write(&imagefile, general_purpose::STANDARD.decode(IMGB.as_bytes()).unwrap()).unwrap();
+    assert!(cache.changed(&imagefile).unwrap());
+    assert!(cache.get(&imagefile).unwrap().is_none());
+    let hash = cache.process_image(&imagefile, crate::hashconfig::HashConfig::new()).unwrap();
+    let mut expected = load_hash(IMGB);
+    expected.format = Some(crate::formats::Format::Jpeg);
+    assert!(hash.to_signature() == expected.to_signature());
+
+    // A second walk takes unchanged files from the cache without decoding them:
+    // the damaged file still has its old size and modification time.
+    let walked = crate::files::hash_dir_images(&dir.to_string_lossy(), crate::hashconfig::HashConfig::new(),
+                                               Some(&mut cache)).unwrap();
+    assert!(walked.len() == 1 && walked[0].1.as_ref().unwrap().to_signature() == hash.to_signature());
+    let modified = std::fs::metadata(&imagefile).unwrap().modified().unwrap();
+    let mut bytes = std::fs::read(&imagefile).unwrap();
+    bytes[crate::formats::SNIFF_LEN..].fill(0);
+    std::fs::write(&imagefile, &bytes).unwrap();
+    std::fs::File::options().write(true).open(&imagefile).unwrap().set_modified(modified).unwrap();
+    let walked = crate::files::hash_dir_images(&dir.to_string_lossy(), crate::hashconfig::HashConfig::new(),
+                                               Some(&mut cache)).unwrap();
+    assert!(walked.len() == 1 && walked[0].1.as_ref().unwrap().to_signature() == hash.to_signature());
+    let walked = crate::files::hash_dir_images(&dir.to_string_lossy(), crate::hashconfig::HashConfig::new(),
+                                               None).unwrap();
+    assert!(walked.len() == 1 && walked[0].1.is_err());
+
+    // Files that are gone are pruned.
+    std::fs::remove_file(&imagefile).unwrap();
+    cache.prune();
+    assert!(cache.is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_ingest_images() {
+    use base64::{engine::general_purpose, Engine as _};
+
+    let dir = std::env::temp_dir().join(format!("duplo-rs-ingest-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut paths = Vec::new();
+    for (name, data) in [("a.jpg", IMGA), ("b.jpg", IMGB), ("c.jpg", IMGC), ("d.jpg", IMGB)] {
+        let path = dir.join(name);
+        std::fs::write(&path, general_purpose::STANDARD.decode(data.as_bytes()).unwrap()).unwrap();
+        paths.push(path);
+    }
+    let broken = dir.join("broken.jpg");
+    std::fs::write(&broken, b"not an image").unwrap();
+    paths.insert(2, broken.clone());
+    let id = |path: &std::path::Path| path.to_string_lossy().to_string();
+
+    // Only the identical copy of b.jpg is similar enough, it is not added.
+    let mut options = crate::ingest::IngestOptions::new();
+    options.num_threads = Some(3);
+    options.queue_size = Some(1);
+    let mut expected = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    expected.add("b", &load_hash(IMGB)).unwrap();
+    options.query.score_threshold = Some(expected.query(&load_hash(IMGB)).m[0].score + 1e-6);
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    let mut cache = crate::hashcache::HashCache::new();
+    let mut seen = Vec::new();
+    let report = crate::ingest::ingest_images(&paths, &mut store, Some(&mut cache), &options,
+                                              |_, filepath, _, matches| {
+        seen.push((filepath.to_string(), matches.m[0].id.clone()));
+        Ok(false)
+    }).unwrap();
+    assert!(report.added == 3 && report.skipped == 1);
+    assert!(report.failed.len() == 1 && report.failed[0].0 == broken);
+    assert!(seen == vec![(id(&paths[4]), id(&paths[1]))]);
+    // The images are added in the order of the paths.
+    let order: Vec<_> = store.candidates.iter().map(|candidate| candidate.id.clone()).collect();
+    assert!(order == vec![id(&paths[0]), id(&paths[1]), id(&paths[3])]);
+    assert!(cache.len() == 4);
+
+    // A second run skips the images in the store and uses the cache for the rest.
+    let report = crate::ingest::ingest_images(&paths, &mut store, Some(&mut cache), &options,
+                                              |_, _, _, _| Ok(true)).unwrap();
+    assert!(report.added == 1 && report.skipped == 3 && report.failed.len() == 1);
+    assert!(store.size() == 4);
+
+    // A changed image that cannot be hashed keeps its old entry until it can.
+    std::fs::write(&paths[0], b"not an image").unwrap();
+    let report = crate::ingest::ingest_images(&paths, &mut store, Some(&mut cache), &options,
+                                              |_, _, _, _| Ok(true)).unwrap();
+    assert!(report.added == 0 && report.failed.len() == 2 && report.failed[0].0 == paths[0]);
+    assert!(store.has(&id(&paths[0])) && store.size() == 4);
+    std::fs::write(&paths[0], general_purpose::STANDARD.decode(IMGC.as_bytes()).unwrap()).unwrap();
+    let report = crate::ingest::ingest_images(&paths, &mut store, Some(&mut cache), &options,
+                                              |_, _, _, _| Ok(true)).unwrap();
+    assert!(report.added == 1 && report.failed.len() == 1);
+    assert!(store.has(&id(&paths[0])) && store.size() == 4);
+
+    // A slow image does not let the hashes of the images after it pile up.
+    let slow: Vec<std::path::PathBuf> = (0..20).map(|n| dir.join(format!("{}.jpg", n))).collect();
+    options.num_threads = Some(4);
+    options.queue_size = Some(2);
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), crate::hashconfig::HashConfig::new());
+    let report = crate::ingest::ingest_with(&slow, &mut store, None, &options, |path, _| {
+        if path == slow[0] {
+            std::thread::sleep(std::time::Duration::from_millis(200));
         }
+        Ok(load_hash(IMGA))
+    }, |_, _, _, _| Ok(true)).unwrap();
+    assert!(report.added == 20);
+    assert!(report.max_pending >= 1 && report.max_pending <= 2);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_hash_config() {
+    use crate::hashconfig::{HashConfig, ResizeFilter};
+    use crate::perceptualhash::{Algorithm, Algorithms};
+
+    let mut small = HashConfig::from(32, 20, ResizeFilter::Triangle);
+    small.perceptual = Algorithms::from(&[Algorithm::PHash]);
+    small.apply_orientation = false;
+    assert!(HashConfig::from(100, 40, ResizeFilter::Lanczos3).validate().is_err());
+    assert!(crate::hash::create_hash(&load_image(IMGA).into(), HashConfig::from(8, 64, ResizeFilter::Nearest)).is_err());
+    let hash = |data: &str| crate::hash::create_hash(&load_image(data).into(), small).unwrap().0;
+
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), small);
+    assert!(store.indices.len() == small.num_indices() as usize);
+    store.add("imgA", &hash(IMGA)).unwrap();
+    store.add("imgB", &hash(IMGB)).unwrap();
+    let matches = store.query(&hash(IMGA));
+    assert!(matches.m[0].id == "imgA");
+
+    // Hashes made with another config are neither added nor compared.
+    let ret = store.add("imgC", &load_hash(IMGC));
+    assert!(matches!(ret, Err(crate::Error::Conflict(_))));
+    assert!(store.query(&load_hash(IMGA)).is_empty());
+
+    // The config is part of the store file and signatures.
+    let mut bytes = Vec::new();
+    store.encode_versioned(&mut bytes);
+    let mut restored = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), small);
+    restored.decode_versioned(bytes.clone()).unwrap();
+    assert!(restored.config() == small);
+    assert!(restored.query(&hash(IMGA)).m[0].id == "imgA");
+    let ret = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), HashConfig::new()).decode_versioned(bytes);
+    assert!(matches!(ret, Err(crate::Error::Conflict(_))));
+    let signature = hash(IMGB).to_signature();
+    assert!(crate::hash::Hash::from_signature(&signature).unwrap().config == small);
+    let viewfile = std::env::temp_dir().join(format!("duplo-rs-config-{}.view", std::process::id()));
+    crate::storeview::StoreView::write(&store, &viewfile.to_string_lossy()).unwrap();
+    let view = crate::storeview::StoreView::open(&viewfile.to_string_lossy()).unwrap();
+    assert!(view.config() == small);
+    assert!(view.query(&hash(IMGA)).m[0].id == "imgA");
+    std::fs::remove_file(&viewfile).unwrap();
+    let dbpath = std::env::temp_dir().join(format!("duplo-rs-config-{}.sqlite3", std::process::id()));
+    let mut connection = crate::videostore::connect(&dbpath.to_string_lossy()).unwrap();
+    crate::videostore::VideoStore::new(&mut connection, crate::sensitivity::Sensitivity::new(), ".", 1, 10, 6, 0, small).unwrap();
+    let (perceptual, apply_orientation): (u32, bool) = connection.query_row(
+        "SELECT hash_perceptual, hash_apply_orientation FROM videostore_parameters WHERE config_id = 1", [],
+        |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+    assert!(perceptual == small.perceptual.bits() && !apply_orientation);
+    drop(connection);
+    std::fs::remove_file(&dbpath).unwrap();
+}
+
+#[test]
+fn test_sensitivity() {
+    use crate::sensitivity::Sensitivity;
+
+    let (loose, strict) = (Sensitivity::from(0.0), Sensitivity::from(100.0));
+    assert!(loose == Sensitivity::new());
+    assert!(loose.score_threshold == -60.0 && strict.score_threshold == -160.0);
+    assert!(Sensitivity::from(50.0).max_dhash_distance < loose.max_dhash_distance);
+    assert!(Sensitivity::from(50.0).max_histogram_distance > strict.max_histogram_distance);
+    assert!(Sensitivity::from(250.0) == strict);
+
+    // The secondary thresholds are part of the store file.
+    let sensitivity = Sensitivity::from(35.0);
+    let store = crate::store::Store::new(sensitivity, crate::hashconfig::HashConfig::new());
+    let mut bytes = Vec::new();
+    store.encode_versioned(&mut bytes);
+    let mut restored = crate::store::Store::new(Sensitivity::new(), crate::hashconfig::HashConfig::new());
+    restored.decode_versioned(bytes).unwrap();
+    assert!(restored.sensitivity() == sensitivity);
+
+    // Negative score thresholds survive the parameters table of the video store.
+    let dir = std::env::temp_dir().join(format!("duplo-rs-sensitivity-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dbpath = dir.join("videos.sqlite3").to_string_lossy().to_string();
+    let mut connection = crate::videostore::connect(&dbpath).unwrap();
+    crate::videostore::VideoStore::new(&mut connection, sensitivity, ".", 1, 10, 6, 0,
+                                       crate::hashconfig::HashConfig::new()).unwrap();
+    drop(connection);
+    let connection = crate::videostore::connect(&dbpath).unwrap();
+    let (score_threshold, max_dhash_distance): (f64, i64) = connection.query_row(
+        "SELECT sensitivity, max_dhash_distance FROM videostore_parameters WHERE config_id = 1", [],
+        |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+    assert!(score_threshold == sensitivity.score_threshold);
+    assert!(max_dhash_distance == sensitivity.max_dhash_distance);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_perceptual_hash() {
+    use crate::perceptualhash::{Algorithm, Algorithms};
+
+    let image: image::RgbaImage = load_image(IMGA).into();
+    for algorithm in crate::perceptualhash::ALGORITHMS {
+        let hasher = algorithm.hasher();
+        assert!(hasher.algorithm() == algorithm);
+        let hash = hasher.hash(&image, crate::hashconfig::ResizeFilter::Lanczos3);
+        assert!(hasher.distance(hash, hasher.hash(&image, crate::hashconfig::ResizeFilter::Lanczos3)) == 0);
     }
-    let bytes_b = general_purpose::STANDARD.decode(IMGB.as_bytes());
-    if bytes_b.is_err() {
-        assert!(false);
+    // The DC coefficient takes no bit of the pHash: inverting the brightness flips
+    // all bits but the one of the median.
+    let gray: image::RgbaImage = image::DynamicImage::ImageLuma8(image::imageops::grayscale(&image)).into();
+    let mut inverted = gray.clone();
+    image::imageops::invert(&mut inverted);
+    let hasher = Algorithm::PHash.hasher();
+    let (hash, opposite) = (hasher.hash(&gray, crate::hashconfig::ResizeFilter::Nearest),
+                            hasher.hash(&inverted, crate::hashconfig::ResizeFilter::Nearest));
+    assert!(hash & opposite == 0 && (hash | opposite).count_ones() == 62);
+    // The thumbnails are made with the filter of the config.
+    let hasher = Algorithm::AverageHash.hasher();
+    let filters = [crate::hashconfig::ResizeFilter::Nearest, crate::hashconfig::ResizeFilter::Lanczos3];
+    let checkered = image::RgbaImage::from_fn(64, 64, |x, y| {
+        let value = if (x / 3 + y / 5) % 2 == 0 { 255 } else { 0 };
+        image::Rgba([value, value, value, 255])
+    });
+    assert!(hasher.hash(&checkered, filters[0]) != hasher.hash(&checkered, filters[1]));
+
+    let mut config = crate::hashconfig::HashConfig::new();
+    config.perceptual = Algorithms::from(&[Algorithm::PHash, Algorithm::BlockMeanHash]);
+    let hash = |data: &str| crate::hash::create_hash(&load_image(data).into(), config).unwrap().0;
+    assert!(hash(IMGA).perceptual.len() == 2);
+    assert!(crate::hash::Hash::from_signature(&hash(IMGA).to_signature()).unwrap().perceptual == hash(IMGA).perceptual);
+
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), config);
+    assert!(store.add("plain", &load_hash(IMGA)).is_err());
+    store.add("imgA", &hash(IMGA)).unwrap();
+    store.add("imgB", &hash(IMGB)).unwrap();
+    store.add("imgC", &hash(IMGC)).unwrap();
+
+    // Every match carries the distances of the perceptual hashes of the store.
+    let matches = store.query(&hash(IMGA));
+    assert!(matches.m.len() == 3);
+    assert!(matches.m[0].perceptual_distances == vec![(Algorithm::PHash, 0), (Algorithm::BlockMeanHash, 0)]);
+
+    // Haar matches can be verified with a second metric.
+    let mut options = crate::queryoptions::QueryOptions::new();
+    options.max_perceptual_distances = vec![(Algorithm::PHash, 0)];
+    let verified = store.query_with(&hash(IMGA), &options);
+    assert!(verified.m.iter().all(|m| m.perceptual_distances[0].1 == 0));
+    assert!(verified.m[0].id == "imgA");
+    options.max_perceptual_distances = vec![(Algorithm::AverageHash, 64)];
+    assert!(store.query_with(&hash(IMGA), &options).is_empty());
+
+    // The perceptual hashes are kept in the store file.
+    let mut bytes = Vec::new();
+    store.encode_versioned(&mut bytes);
+    let mut restored = crate::store::Store::new(crate::sensitivity::Sensitivity::new(), config);
+    restored.decode_versioned(bytes).unwrap();
+    assert!(restored.candidates == store.candidates);
+}
+
+#[test]
+fn test_query_dihedral() {
+    use crate::dihedral::Dihedral;
+
+    let config = crate::hashconfig::HashConfig::new();
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from(0.0), config);
+    store.add("imgA", &load_hash(IMGA)).unwrap();
+    store.add("imgB", &load_hash(IMGB)).unwrap();
+    store.add("imgC", &load_hash(IMGC)).unwrap();
+    let exact = store.query(&load_hash(IMGA));
+
+    let image: image::RgbaImage = load_image(IMGA).into();
+    let options = crate::queryoptions::QueryOptions::new();
+    for (transform, inverse) in [(Dihedral::Rotate90, Dihedral::Rotate270),
+                                 (Dihedral::FlipHorizontal, Dihedral::FlipHorizontal),
+                                 (Dihedral::Transpose, Dihedral::Transpose)] {
+        let hashes = crate::hash::create_dihedral_hashes(&transform.apply(&image), config).unwrap();
+        assert!(hashes.len() == 8);
+        let matches = store.query_dihedral(&hashes, &options);
+        assert!(matches.m[0].id == "imgA");
+        assert!(matches.m[0].transform == inverse);
+        assert!((matches.m[0].score - exact.m[0].score).abs() < 1.0);
+        assert!(matches.m.iter().filter(|m| m.id == "imgA").count() == 1);
     }
-    let img_b;
-    match image::load_from_memory_with_format(&bytes_b.unwrap(), image::ImageFormat::Jpeg) {
-        Ok(img) => {
-            img_b = img;
+}
+
+#[test]
+fn test_query_region() {
+    let config = crate::hashconfig::HashConfig::new();
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), config);
+    let image: image::RgbaImage = load_image(IMGA).into();
+    assert!(crate::region::tiles().len() == 29);
+    store.add("imgA", &load_hash(IMGA)).unwrap();
+    store.add("imgB", &load_hash(IMGB)).unwrap();
+    store.add_regions("imgA", &crate::region::create_region_hashes(&image, config).unwrap()).unwrap();
+    assert!(store.add_regions("missing", &[]).is_err());
+    assert!(store.regions().len() == 29);
+    assert!(store.regions().candidates.iter().all(|tile| tile.scale_coeff.c.is_empty()));
+    assert!(matches!(crate::region::create_region_hashes(&image::RgbaImage::new(0, 0), config),
+                     Err(crate::Error::Image(_))));
+    assert!(crate::region::BoundingBox::from(0.5, 0.5, 0.5, 0.5).pixels(0, 0) == (0, 0, 0, 0));
+
+    // A crop of imgA is found in the region index, with the tile it came from.
+    let options = crate::queryoptions::QueryOptions::new();
+    let tile = crate::region::BoundingBox::from(0.3, 0.3, 0.6, 0.6);
+    let (crop, _) = crate::hash::create_hash(&tile.crop(&image), config).unwrap();
+    let matches = store.query_region(&crop, &options);
+    assert!(matches.len() == 1);
+    assert!(matches.m[0].id == "imgA");
+    assert!(matches.m[0].region == Some(tile));
+
+    // imgA is found inside a larger image, where it covers the tile at 0.4, 0.4.
+    let mut canvas = image::RgbaImage::from_pixel(image.width() * 5 / 2, image.height() * 5 / 2,
+                                                  image::Rgba([255, 255, 255, 255]));
+    image::imageops::overlay(&mut canvas, &image, image.width() as i64, image.height() as i64);
+    let matches = store.query_contains(&crate::region::create_region_hashes(&canvas, config).unwrap(), &options);
+    assert!(matches.m[0].id == "imgA");
+    assert!(matches.m[0].region == Some(crate::region::BoundingBox::from(0.4, 0.4, 0.4, 0.4)));
+
+    // The tiles survive a round trip and are deleted with their image.
+    let mut bytes = Vec::new();
+    store.encode_versioned(&mut bytes);
+    let mut restored = crate::store::Store::new(crate::sensitivity::Sensitivity::new(), config);
+    restored.decode_versioned(bytes).unwrap();
+    assert!(restored.regions() == store.regions());
+    assert!(restored.query_region(&crop, &options) == store.query_region(&crop, &options));
+    assert!(restored.exchange("imgA", "imgE").unwrap());
+    assert!(restored.regions().tiles_of("imgA").is_empty());
+    assert!(restored.regions().tiles_of("imgE").len() == 29);
+    restored.delete("imgE").unwrap();
+    assert!(restored.query_region(&crop, &options).is_empty());
+    assert!(restored.regions().is_empty() && restored.regions().tiles_of("imgE").is_empty());
+    restored.add_regions("imgB", &crate::region::create_region_hashes(&image, config).unwrap()).unwrap();
+    restored.compact();
+    assert!(restored.regions().len() == 29);
+    assert!(restored.regions().tiles_of("imgB").iter().all(|(tile, _)| tile.id == "imgB"));
+}
+
+#[test]
+fn test_exif_orientation() {
+    let dir = std::env::temp_dir().join(format!("duplo-rs-orientation-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let imagefile = dir.join("rotated.png");
+
+    // A PNG turned by 90 degrees counterclockwise, with an EXIF orientation of 6
+    // telling viewers to turn it back clockwise.
+    let image: image::RgbaImage = load_image(IMGA).into();
+    let rotated = image::imageops::rotate270(&image);
+    let exif = vec![0x4d, 0x4d, 0, 42, 0, 0, 0, 8, 0, 1, 0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0, 0, 0, 0, 0];
+    let mut bytes = Vec::new();
+    let mut encoder = image::codecs::png::PngEncoder::new(&mut bytes);
+    image::ImageEncoder::set_exif_metadata(&mut encoder, exif).unwrap();
+    image::ImageEncoder::write_image(encoder, &rotated, rotated.width(), rotated.height(),
+                                     image::ExtendedColorType::Rgba8).unwrap();
+    std::fs::write(&imagefile, bytes).unwrap();
+
+    // The upright image gives the hash of the original, the orientation is kept.
+    let config = crate::hashconfig::HashConfig::new();
+    let hash = crate::files::process_image(&imagefile, config).unwrap();
+    assert!(hash.orientation == 6);
+    assert!(hash.matrix == load_hash(IMGA).matrix);
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::new(), config);
+    store.add("rotated", &hash).unwrap();
+    assert!(store.candidates[0].orientation == 6);
+    let restored = crate::hash::Hash::from_signature(&hash.to_signature()).unwrap();
+    assert!(restored.orientation == 6);
+
+    // The normalisation can be switched off, the switch is part of the config.
+    let mut unrotated = crate::hashconfig::HashConfig::new();
+    unrotated.apply_orientation = false;
+    let hash = crate::files::process_image(&imagefile, unrotated).unwrap();
+    assert!(hash.orientation == 6);
+    assert!(hash.matrix != load_hash(IMGA).matrix);
+    assert!(store.add("unrotated", &hash).is_err());
+    let mut bytes = Vec::new();
+    unrotated.encode(&mut bytes);
+    let mut decoded = crate::hashconfig::HashConfig::new();
+    decoded.decode(&mut std::io::Cursor::new(bytes)).unwrap();
+    assert!(decoded == unrotated);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_animation_store() {
+    let dir = std::env::temp_dir().join(format!("duplo-rs-animation-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let frame = |data: &str| -> image::RgbaImage {
+        let image: image::RgbaImage = load_image(data).into();
+        image::imageops::resize(&image, 50, 50, image::imageops::FilterType::Triangle)
+    };
+    let write_gif = |name: &str, frames: &[&str]| {
+        let path = dir.join(name);
+        let mut encoder = image::codecs::gif::GifEncoder::new(std::fs::File::create(&path).unwrap());
+        for data in frames {
+            let delay = image::Delay::from_numer_denom_ms(100, 1);
+            encoder.encode_frame(image::Frame::from_parts(frame(data), 0, 0, delay)).unwrap();
         }
-        Err(_) => {
-            println!("Image B could not be created!");
-            std::process::exit(1);
+        path
+    };
+    let original = write_gif("original.gif", &[IMGA, IMGB, IMGC]);
+    let longer = write_gif("longer.gif", &[IMGC, IMGA, IMGB, IMGC]);
+    let shuffled = write_gif("shuffled.gif", &[IMGA, IMGC, IMGB]);
+
+    // Every frame is hashed, the timecodes are in milliseconds.
+    let config = crate::hashconfig::HashConfig::new();
+    let animation = crate::files::process_animation(&original, config).unwrap();
+    assert!(animation.screenshots.len() == 3);
+    assert!(animation.screenshots[2].timecode == 200 && animation.runtime == 300);
+
+    let mut store = crate::animation::AnimationStore::new(crate::sensitivity::Sensitivity::from(50.0), config);
+    store.add(&animation).unwrap();
+    store.add(&crate::files::process_animation(&shuffled, config).unwrap()).unwrap();
+    assert!(store.size() == 2);
+
+    // The frames in the middle of the longer animation match all of the original,
+    // the shuffled one only shares single frames.
+    let matches = store.query(&crate::files::process_animation(&longer, config).unwrap());
+    assert!(matches.len() == 1);
+    assert!(matches.m[0].id == crate::files::osstring_to_string(original.as_os_str()));
+    assert!(matches.m[0].screenshot_id == 1 && matches.m[0].video_id == 1);
+
+    // An animation does not match itself.
+    assert!(store.query(&animation).m.iter().all(|m| m.id != animation.id));
+    store.delete(&animation.id).unwrap();
+    assert!(!store.has(&animation.id));
+
+    // An animation with a frame that cannot be added leaves no frames behind.
+    let mut broken = animation.clone();
+    broken.id = "broken".to_string();
+    broken.screenshots[2].hash.perceptual = vec![0];
+    assert!(store.add(&broken).is_err());
+    assert!(!store.has("broken") && store.size() == 1);
+    assert!(store.query(&animation).m.iter().all(|m| m.id != "broken"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[cfg(all(feature = "qoi", feature = "raw"))]
+fn test_image_formats() {
+    use base64::{engine::general_purpose, Engine as _};
+    let dir = std::env::temp_dir().join(format!("duplo-rs-formats-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let extensions = crate::formats::supported_extensions();
+    assert!(extensions.contains(&"jpg") && extensions.contains(&"qoi") && extensions.contains(&"nef"));
+
+    // A minimal little-endian RAW file: IFD0 only points to the embedded JPEG preview.
+    let jpeg = general_purpose::STANDARD.decode(IMGA).unwrap();
+    let mut raw = vec![0x49, 0x49, 42, 0, 8, 0, 0, 0, 2, 0];
+    raw.extend([0x01, 0x02, 4, 0, 1, 0, 0, 0, 38, 0, 0, 0]);
+    raw.extend([0x02, 0x02, 4, 0, 1, 0, 0, 0]);
+    raw.extend((jpeg.len() as u32).to_le_bytes());
+    raw.extend([0, 0, 0, 0]);
+    raw.extend(&jpeg);
+    let rawfile = dir.join("photo.NEF");
+    std::fs::write(&rawfile, &raw).unwrap();
+    assert!(crate::formats::Format::from_path(&rawfile) == Some(crate::formats::Format::Raw));
+    let config = crate::hashconfig::HashConfig::new();
+    let hash = crate::files::process_image(&rawfile, config).unwrap();
+    assert!(hash.matrix == load_hash(IMGA).matrix);
+
+    // A QOI file is read like any other image.
+    let qoifile = dir.join("image.qoi");
+    let image: image::RgbaImage = load_image(IMGA).into();
+    image.save(&qoifile).unwrap();
+    let hash = crate::files::process_image(&qoifile, config).unwrap();
+    assert!(hash.matrix == load_hash(IMGA).matrix);
+    let found = crate::files::walk_dir_images(dir.to_str().unwrap()).unwrap();
+    assert!(found.len() == 2);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_file_type_detection() {
+    use base64::{engine::general_purpose, Engine as _};
+    let dir = std::env::temp_dir().join(format!("duplo-rs-sniffing-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // A PNG named .jpg, a JPEG without extension and a text file named .jpg.
+    let image: image::RgbaImage = load_image(IMGA).into();
+    image.save_with_format(dir.join("misnamed.jpg"), image::ImageFormat::Png).unwrap();
+    std::fs::write(dir.join("download"), general_purpose::STANDARD.decode(IMGA).unwrap()).unwrap();
+    std::fs::write(dir.join("notes.jpg"), "not an image").unwrap();
+    // An MP4 named .mov and a WebM without extension.
+    std::fs::write(dir.join("clip.mov"), b"\0\0\0\x18ftypisom\0\0\x02\0isomiso2").unwrap();
+    std::fs::write(dir.join("movie"), b"\x1a\x45\xdf\xa3\x9f\x42\x86\x81\x01\x42\x82\x84webm").unwrap();
+
+    let mut images = crate::files::walk_dir_images(dir.to_str().unwrap()).unwrap();
+    images.sort();
+    let names: Vec<String> = images.iter().map(|p| crate::files::osstring_to_string(p.file_name().unwrap())).collect();
+    assert!(names == ["download", "misnamed.jpg"]);
+    let mut videos = crate::files::walk_tree_videos(dir.to_str().unwrap()).unwrap();
+    videos.sort();
+    let formats: Vec<_> = videos.iter().map(|p| crate::formats::VideoFormat::detect(p)).collect();
+    assert!(formats == [Some(crate::formats::VideoFormat::Mp4), Some(crate::formats::VideoFormat::WebM)]);
+    // AVIF images are ISO base media files too, but no videos.
+    let avif = b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf";
+    assert!(crate::formats::Format::sniff(avif) == Some(crate::formats::Format::Avif));
+    assert!(crate::formats::VideoFormat::sniff(avif).is_none());
+    let sequence = b"\0\0\0\x1cftypavis\0\0\0\0avismsf1miaf";
+    assert!(crate::formats::Format::sniff(sequence) == Some(crate::formats::Format::Avif));
+    assert!(crate::formats::VideoFormat::sniff(sequence).is_none());
+    assert!(!crate::formats::Format::Avif.is_supported());
+
+    // The misnamed file is decoded as what it is and its format is kept.
+    let config = crate::hashconfig::HashConfig::new();
+    let hash = crate::files::process_image(&dir.join("misnamed.jpg"), config).unwrap();
+    assert!(hash.format == Some(crate::formats::Format::Png));
+    assert!(hash.matrix == load_hash(IMGA).matrix);
+    let restored = crate::hash::Hash::from_signature(&hash.to_signature()).unwrap();
+    assert!(restored.format == Some(crate::formats::Format::Png));
+    let mut store = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), config);
+    store.add("misnamed", &hash).unwrap();
+    let mut bytes = Vec::new();
+    store.encode_versioned(&mut bytes);
+    let mut restored = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), config);
+    restored.decode_versioned(bytes).unwrap();
+    assert!(restored.candidates[0].format == Some(crate::formats::Format::Png));
+
+    // Video candidates keep their format, the ones of earlier releases have none.
+    let mut video = crate::videocandidate::VideoCandidate::from("movie", 1);
+    video.format = crate::formats::VideoFormat::detect(&dir.join("movie"));
+    let mut blob = Vec::new();
+    video.encode(&mut blob);
+    let mut decoded = crate::videocandidate::VideoCandidate::new();
+    decoded.decode(&mut std::io::Cursor::new(blob.clone())).unwrap();
+    assert!(decoded == video);
+    let legacy = blob[8..blob.len() - 4].to_vec();
+    let mut decoded = crate::videocandidate::VideoCandidate::new();
+    decoded.decode(&mut std::io::Cursor::new(legacy.clone())).unwrap();
+    assert!(decoded.format.is_none());
+    assert!(decoded.id == video.id);
+
+    // Several records in one stream, new and old, are read one after another.
+    let mut stream = Vec::new();
+    stream.extend_from_slice(&legacy);
+    stream.extend_from_slice(&blob);
+    stream.extend_from_slice(&legacy);
+    let mut from = std::io::Cursor::new(stream);
+    for format in [None, video.format, None] {
+        let mut decoded = crate::videocandidate::VideoCandidate::new();
+        decoded.decode(&mut from).unwrap();
+        assert!(decoded.id == video.id && decoded.format == format);
+    }
+    let mut cache = crate::videostore::CandidateCache::new(10);
+    cache.add(video.clone());
+    cache.add(crate::videocandidate::VideoCandidate::from("other", 2));
+    let mut bytes = Vec::new();
+    cache.encode(&mut bytes);
+    let mut restored = crate::videostore::CandidateCache::new(10);
+    restored.decode(&mut std::io::Cursor::new(bytes)).unwrap();
+    assert!(restored.map == cache.map);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_query_dhash() {
+    let config = crate::hashconfig::HashConfig::new();
+    let mut plain = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), config);
+    let mut indexed = crate::store::Store::new(crate::sensitivity::Sensitivity::from_score_threshold(100.0), config);
+    indexed.add("imgB", &load_hash(IMGB)).unwrap();
+    indexed.enable_dhash_index();
+    assert!(indexed.has_dhash_index() && !plain.has_dhash_index());
+    plain.add("imgB", &load_hash(IMGB)).unwrap();
+
+    // Variants of imgA with more and more bits of the dHash flipped.
+    let base = load_hash(IMGA);
+    let mut state = 0x2545f4914f6cdd1d_u64;
+    for n in 0..300 {
+        let mut hash = base.clone();
+        for _ in 0..n % 40 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            hash.dhash[(state % 2) as usize] ^= 1 << ((state >> 8) % 64);
         }
+        plain.add(&format!("imgA-{}", n), &hash).unwrap();
+        indexed.add(&format!("imgA-{}", n), &hash).unwrap();
     }
-    let bytes_c = general_purpose::STANDARD.decode(IMGC.as_bytes());
-    if bytes_c.is_err() {
-        assert!(false);
+    plain.delete("imgA-0").unwrap();
+    indexed.delete("imgA-0").unwrap();
+
+    // The index gives the same matches as comparing all candidates.
+    for max_distance in [0, 3, 8, 16, 23, 40] {
+        let expected = plain.query_dhash(&base, max_distance);
+        let found = indexed.query_dhash(&base, max_distance);
+        assert!(found == expected);
+        assert!(found.m.iter().all(|m| m.dhash_distance <= max_distance && m.score == m.dhash_distance as f64));
+        assert!(found.m.windows(2).all(|pair| pair[0].dhash_distance <= pair[1].dhash_distance));
     }
-    let bytes_c = general_purpose::STANDARD.decode(IMGC.as_bytes());
-    if bytes_c.is_err() {
-        assert!(false);
+    let found = indexed.query_dhash(&base, 0);
+    assert!(!found.is_empty() && found.m.iter().all(|m| m.id != "imgA-0"));
+    assert!(indexed.query_dhash(&load_hash(IMGB), 0).m[0].id == "imgB");
+
+    // The index survives compaction and a round trip through the store format.
+    indexed.compact();
+    assert!(indexed.query_dhash(&base, 16) == plain.query_dhash(&base, 16));
+    let mut bytes = Vec::new();
+    indexed.encode_versioned(&mut bytes);
+    indexed.decode_versioned(bytes).unwrap();
+    assert!(indexed.has_dhash_index());
+    assert!(indexed.query_dhash(&base, 16) == plain.query_dhash(&base, 16));
+}
+
+#[test]
+fn test_lsh_candidate_generation() {
+    let config = crate::hashconfig::HashConfig::new();
+    let sensitivity = crate::sensitivity::Sensitivity::from_score_threshold(100.0);
+    let generation = crate::lsh::CandidateGeneration::Lsh(crate::lsh::LshConfig::new());
+    let mut exact = crate::store::Store::new(sensitivity, config);
+    let mut lsh = crate::store::Store::from(sensitivity, config, generation);
+    assert!(exact.candidate_generation() == crate::lsh::CandidateGeneration::IndexLists);
+    assert!(lsh.candidate_generation() == generation);
+    let small = {
+        let image: image::RgbaImage = load_image(IMGA).into();
+        let small = image::imageops::resize(&image, image.width() / 2, image.height() / 2,
+                                            image::imageops::FilterType::Triangle);
+        crate::hash::create_hash(&small, config).unwrap().0
+    };
+    for store in [&mut exact, &mut lsh] {
+        store.add("imgA", &load_hash(IMGA)).unwrap();
+        store.add("imgB", &load_hash(IMGB)).unwrap();
+        store.add("imgC", &load_hash(IMGC)).unwrap();
+        store.add("imgA-small", &small).unwrap();
     }
-    let img_c;
-    match image::load_from_memory_with_format(&bytes_c.unwrap(), image::ImageFormat::Jpeg) {
-        Ok(img) => {
-            img_c = img;
-        }
-        Err(_) => {
-            println!("Image C could not be created!");
-            std::process::exit(1);
+
+    // The candidates found by the LSH index are scored like all images are scored
+    // by the index lists.
+    let found = lsh.query(&load_hash(IMGA));
+    let expected = exact.query(&load_hash(IMGA));
+    assert!(found == expected && found.m[0].id == "imgA");
+    assert!(found.m.iter().any(|m| m.id == "imgA-small"));
+    assert!(lsh.neighbours("imgA-small").unwrap() == exact.neighbours("imgA-small").unwrap());
+
+    // The number of scored candidates is bounded.
+    let bounded = crate::lsh::CandidateGeneration::Lsh(crate::lsh::LshConfig::from(20, 3, 1));
+    let mut store = crate::store::Store::from(sensitivity, config, bounded);
+    store.add("imgA", &load_hash(IMGA)).unwrap();
+    store.add("imgA-small", &small).unwrap();
+    store.add("imgB", &load_hash(IMGB)).unwrap();
+    assert!(store.query(&load_hash(IMGA)).len() == 1);
+
+    // An image added after more than max_candidates images that collide with the
+    // query in many bands is still found: capped buckets take a sample of their
+    // candidates instead of the oldest ones, and the image collides in every band.
+    let mut index = crate::lsh::LshIndex::new(crate::lsh::LshConfig::from(20, 3, 4));
+    let signature: Vec<u32> = (0..40).map(|n| n * 7).collect();
+    for collider in 0..12 {
+        let mut similar = signature.clone();
+        for (n, location) in similar.iter_mut().skip(collider % 8).step_by(8).enumerate() {
+            *location = 1000 + 10 * collider as u32 + n as u32;
         }
+        similar.sort_unstable();
+        index.add(collider as u32, &similar, &[collider as u64, 0]);
     }
-    let mut store = crate::store::Store::new(100.0);
-    let (hash_a, _small_a) = crate::hash::create_hash(&img_a.into());
-    let (hash_b, _small_b) = crate::hash::create_hash(&img_b.into());
-    let (hash_c, _small_c) = crate::hash::create_hash(&img_c.into());
-    store.add("imgA", &hash_a);
-    store.add("imgB", &hash_b);
-    store.add("imgC", &hash_c);
-    // Test failure to find original ID.
-    assert!(!store.exchange("does not exist", "is irrelevant"));
-    assert!(store.ids.len() == 3);
-    // Test failure to rename into existing ID.
-    assert!(!store.exchange("imgA", "imgB"));
+    let dhash = [0xfedc_ba98_7654_3210, 0x0123_4567_89ab_cdef];
+    index.add(12, &signature, &dhash);
+    let mut deleted = vec![false; 13];
+    let found = index.lookup(&signature, &dhash, &deleted);
+    assert!(found.len() == 4 && found.contains(&12));
 
-    // Now rename and check result.
-    assert!(store.exchange("imgA", "imgD"));
-    assert!(store.ids.len() == 3);
-    assert!(!store.ids.contains_key("imgA"));
-    assert!(store.ids.contains_key("imgD"));
-    assert!(store.candidates[0].id == "imgD");
-    assert!(store.ids.contains_key("imgB"));
-    assert!(store.ids.contains_key("imgC"));
+    // Deleted candidates leave their place in the samples to others.
+    let mut index = crate::lsh::LshIndex::new(crate::lsh::LshConfig::from(20, 3, 1));
+    index.add(0, &signature, &dhash);
+    index.add(1, &signature, &dhash);
+    let first = index.lookup(&signature, &dhash, &deleted[..2]);
+    assert!(first.len() == 1);
+    deleted[first[0] as usize] = true;
+    index.delete(first[0], &signature, &deleted[..2]);
+    assert!(index.lookup(&signature, &dhash, &deleted[..2]) == vec![1 - first[0]]);
+
+    // The index follows deletes, compaction and reading a store.
+    lsh.delete("imgA").unwrap();
+    assert!(lsh.query(&load_hash(IMGA)).m.iter().all(|m| m.id != "imgA"));
+    lsh.compact();
+    assert!(lsh.query(&load_hash(IMGA)).m.iter().any(|m| m.id == "imgA-small"));
+    let mut bytes = Vec::new();
+    lsh.encode_versioned(&mut bytes);
+    let mut restored = crate::store::Store::from(sensitivity, config, generation);
+    restored.decode_versioned(bytes).unwrap();
+    assert!(restored.candidate_generation() == generation);
+    assert!(restored.query(&load_hash(IMGA)) == lsh.query(&load_hash(IMGA)));
+}
+
+#[test]
+fn test_scoring_profile() {
+    let config = crate::hashconfig::HashConfig::new();
+    let sensitivity = crate::sensitivity::Sensitivity::from_score_threshold(100.0);
+    // The same image with a different overall colour.
+    let tinted = {
+        let mut image: image::RgbaImage = load_image(IMGA).into();
+        for pixel in image.pixels_mut() {
+            pixel[0] = pixel[0] / 2 + 120;
+        }
+        crate::hash::create_hash(&image, config).unwrap().0
+    };
+    let mut store = crate::store::Store::new(sensitivity, config);
+    assert!(store.scoring() == crate::scoring::ScoringProfile::new() && store.scoring().is_classic());
+    store.add("imgA", &load_hash(IMGA)).unwrap();
+    store.add("tinted", &tinted).unwrap();
+    let score = |store: &crate::store::Store, id: &str| {
+        store.query(&load_hash(IMGA)).m.iter().find(|m| m.id == id).unwrap().score
+    };
+    let classic = (score(&store, "imgA"), score(&store, "tinted"));
+
+    // The paper scores the difference of the average colours instead of their weights.
+    store.set_scoring(crate::scoring::ScoringProfile::paper());
+    let paper = (score(&store, "imgA"), score(&store, "tinted"));
+    assert!((paper.0 - (classic.0 - crate::store::initial_score())).abs() < 1e-9);
+    assert!(paper.1 - paper.0 > classic.1 - classic.0 + 1.0);
+    let neighbours = store.neighbours("imgA").unwrap();
+    assert!((neighbours.m[0].score - paper.1).abs() < 1e-9);
+    let groups = store.find_duplicate_groups(&crate::queryoptions::QueryOptions::new());
+    assert!((groups[0].pairs[0].m.score - paper.1).abs() < 1e-9);
+
+    // The histogram penalty only adds to the score of differing images.
+    store.set_scoring(crate::scoring::ScoringProfile::from(false, 100.0));
+    let penalized = (score(&store, "imgA"), score(&store, "tinted"));
+    assert!(penalized.0 == classic.0 && penalized.1 > classic.1);
+
+    // The profile and the scaling function coefficients are saved with the store.
+    store.set_scoring(crate::scoring::ScoringProfile::paper());
+    let mut bytes = Vec::new();
+    store.encode_versioned(&mut bytes);
+    let mut restored = crate::store::Store::new(sensitivity, config);
+    restored.decode_versioned(bytes).unwrap();
+    assert!(restored.scoring() == crate::scoring::ScoringProfile::paper());
+    assert!(restored.query(&load_hash(IMGA)) == store.query(&load_hash(IMGA)));
+
+    // A black image has a scaling function coefficient of zero, it is scored by its
+    // colour like any other: a black query prefers it to a white one.
+    let flat = |value: u8| {
+        let image = image::RgbaImage::from_pixel(64, 64, image::Rgba([value, value, value, 255]));
+        crate::hash::create_hash(&image, config).unwrap().0
+    };
+    let mut store = crate::store::Store::new(sensitivity, config);
+    store.add("white", &flat(255)).unwrap();
+    store.add("black", &flat(0)).unwrap();
+    let score = |store: &crate::store::Store, id: &str| {
+        store.query(&flat(0)).m.iter().find(|m| m.id == id).unwrap().score
+    };
+    let classic = (score(&store, "black"), score(&store, "white"));
+    assert!(classic.0 == classic.1);
+    store.set_scoring(crate::scoring::ScoringProfile::paper());
+    let paper = (score(&store, "black"), score(&store, "white"));
+    assert!((paper.0 - (classic.0 - crate::store::initial_score())).abs() < 1e-9);
+    assert!(paper.0 < paper.1);
+    assert!(store.query(&flat(0)).m[0].id == "black");
 }
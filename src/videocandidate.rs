@@ -1,5 +1,13 @@
 
+/// VIDEO_CANDIDATE_VERSION is the version of the records written by
+/// VideoCandidate::encode. Records start with RECORD_MARKER | version, records of
+/// earlier releases have no version and start with the length of the ID, which
+/// never has the highest bit set.
+pub const VIDEO_CANDIDATE_VERSION: u64 = 2;
+const RECORD_MARKER: u64 = 1 << 63;
+
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Screenshot {
     pub id: String,
     pub video_id: u32, 
@@ -10,15 +18,16 @@ pub struct Screenshot {
 
 impl Screenshot {
     pub fn new() -> Self {
-        let v: Screenshot = Screenshot{..Default::default()};
-        v
+        Screenshot {
+            ..Default::default()
+        }
     }
     pub fn from(id: &str, video_id: usize, screenshot_id: usize, timecode: u32, hash: &crate::hash::Hash) -> Self {
         let mut v: Screenshot = Screenshot{..Default::default()};
         v.id = id.to_string();
         v.video_id = video_id as u32;
         v.screenshot_id = screenshot_id as u32;
-        v.timecode = timecode as u32;
+        v.timecode = timecode;
         v.hash = hash.clone();
         v
     }
@@ -31,12 +40,13 @@ impl Screenshot {
         self.hash.encode(to);
     }
 
-    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) {
-        self.id = crate::marshal::restore_string(from);
-        self.video_id = crate::marshal::restore_u32(from);
-        self.screenshot_id = crate::marshal::restore_u32(from);
-        self.timecode = crate::marshal::restore_u32(from);
-        self.hash.decode(from);
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        self.id = crate::marshal::restore_string(from)?;
+        self.video_id = crate::marshal::restore_u32(from)?;
+        self.screenshot_id = crate::marshal::restore_u32(from)?;
+        self.timecode = crate::marshal::restore_u32(from)?;
+        self.hash.decode(from)?;
+        Ok(())
     }
 }
 
@@ -53,6 +63,7 @@ impl Default for Screenshot {
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VideoCandidate {
     pub id: String,
     pub index: u32,
@@ -61,12 +72,16 @@ pub struct VideoCandidate {
     pub height: u32,
     pub runtime: u32,
     pub framerate: f32,
+    // format is the container format of the video file, detected by
+    // files::process_video from its content.
+    pub format: Option<crate::formats::VideoFormat>,
 }
 
 impl VideoCandidate {
     pub fn new() -> Self {
-        let v: VideoCandidate = VideoCandidate{..Default::default()};
-        v
+        VideoCandidate {
+            ..Default::default()
+        }
     }
     pub fn from(id: &str, index: usize) -> Self {
         let mut v: VideoCandidate = VideoCandidate{..Default::default()};
@@ -76,6 +91,7 @@ impl VideoCandidate {
     }
 
     pub fn encode(&self, to: &mut Vec<u8>) {
+        crate::marshal::store_u64(RECORD_MARKER | VIDEO_CANDIDATE_VERSION, to);
         crate::marshal::store_string(&self.id, to);
         crate::marshal::store_u32(self.index, to);
         let s = self.screenshots.len();
@@ -87,21 +103,40 @@ impl VideoCandidate {
         crate::marshal::store_u32(self.height, to);
         crate::marshal::store_u32(self.runtime, to);
         crate::marshal::store_f32(self.framerate, to);
+        crate::marshal::store_u32(crate::formats::VideoFormat::code(self.format), to);
     }
 
-    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) {
-        self.id = crate::marshal::restore_string(from);
-        self.index = crate::marshal::restore_u32(from);
-        let s = crate::marshal::restore_usize(from);
+    /// decode a record written by encode or by earlier releases. Records of earlier
+    /// releases have no format.
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        let start = from.position();
+        let marker = crate::marshal::restore_u64(from)?;
+        let version = if marker & RECORD_MARKER != 0 {
+            marker & !RECORD_MARKER
+        } else {
+            // the marker was the length of the ID
+            from.set_position(start);
+            1
+        };
+        if version > VIDEO_CANDIDATE_VERSION {
+            return Err(crate::Error::Version(version as u32));
+        }
+        self.id = crate::marshal::restore_string(from)?;
+        self.index = crate::marshal::restore_u32(from)?;
+        let s = crate::marshal::restore_usize(from)?;
         for _i in 0..s {
             let mut elem = Screenshot::new();
-            elem.decode(from);
+            elem.decode(from)?;
             self.screenshots.push(elem);
         }
-        self.width = crate::marshal::restore_u32(from);
-        self.height = crate::marshal::restore_u32(from);
-        self.runtime = crate::marshal::restore_u32(from);
-        self.framerate = crate::marshal::restore_f32(from);
+        self.width = crate::marshal::restore_u32(from)?;
+        self.height = crate::marshal::restore_u32(from)?;
+        self.runtime = crate::marshal::restore_u32(from)?;
+        self.framerate = crate::marshal::restore_f32(from)?;
+        if version >= 2 {
+            self.format = crate::formats::VideoFormat::from_code(crate::marshal::restore_u32(from)?);
+        }
+        Ok(())
     }
 }
 
@@ -115,6 +150,7 @@ impl Default for VideoCandidate {
             height: 0,
             runtime: 0,
             framerate: 0.0,
+            format: None,
         }
     }
 }
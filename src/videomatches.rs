@@ -1,4 +1,5 @@
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VideoMatch {
     pub id: String,
     pub video_id: u32,
@@ -12,8 +13,9 @@ pub struct VideoMatch {
 
 impl VideoMatch {
     pub fn new() -> Self {
-        let v = VideoMatch{..Default::default()};
-        v
+        VideoMatch {
+            ..Default::default()
+        }
     }
 
     pub fn from(id: &str, score: f64, ratio_diff: f64, dhash_distance: i64, histogram_distance: i64) -> Self {
@@ -42,16 +44,16 @@ impl VideoMatch {
         crate::marshal::store_i64(self.histogram_distance, to);
     }
 
-    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) {
-        self.id = crate::marshal::restore_string(from);
-        self.video_id = crate::marshal::restore_u32(from);
-        self.screenshot_id = crate::marshal::restore_u32(from);
-        self.timecode = crate::marshal::restore_u32(from);
-        self.score = crate::marshal::restore_f64(from);
-        self.ratio_diff = crate::marshal::restore_f64(from);
-        self.dhash_distance = crate::marshal::restore_i64(from);
-        self.histogram_distance = crate::marshal::restore_i64(from);
-
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        self.id = crate::marshal::restore_string(from)?;
+        self.video_id = crate::marshal::restore_u32(from)?;
+        self.screenshot_id = crate::marshal::restore_u32(from)?;
+        self.timecode = crate::marshal::restore_u32(from)?;
+        self.score = crate::marshal::restore_f64(from)?;
+        self.ratio_diff = crate::marshal::restore_f64(from)?;
+        self.dhash_distance = crate::marshal::restore_i64(from)?;
+        self.histogram_distance = crate::marshal::restore_i64(from)?;
+        Ok(())
     }
 }
 
@@ -70,15 +72,17 @@ impl Default for VideoMatch {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VideoMatches {
     pub m: Vec<VideoMatch>,
 }
 
 impl VideoMatches {
     pub fn new() -> Self {
-        let v = VideoMatches {..Default::default()};
-        v
+        VideoMatches {
+            ..Default::default()
+        }
     }
 
     pub fn from(m: Vec<VideoMatch>) -> Self {
@@ -88,6 +92,8 @@ impl VideoMatches {
     }
 
     pub fn len(&self) -> usize {self.m.len()}
+
+    pub fn is_empty(&self) -> bool {self.m.is_empty()}
     
     pub fn swap(&mut self, pos1: usize, pos2: usize) {
         let tmp = self.m[pos1].clone();
@@ -102,12 +108,12 @@ impl VideoMatches {
         if testpos >= self.m.len() {
             return false;
         }
-        return self.m[testpos].score < self.m[comparepos].score;
+        self.m[testpos].score < self.m[comparepos].score
     }
 
     pub fn sort(&mut self) {
         // we use Bubble sort until someone wants to spend the time
-        if self.m.len() == 0 {
+        if self.m.is_empty() {
             return;
         }
         for i in 0..self.m.len() - 1 {
@@ -127,22 +133,15 @@ impl VideoMatches {
         }
     }
 
-    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) {
-        let s = crate::marshal::restore_usize(from);
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        let s = crate::marshal::restore_usize(from)?;
         for _i in 0..s {
             let mut elem = VideoMatch {
                 ..Default::default()
             };
-            elem.decode(from);
+            elem.decode(from)?;
             self.m.push(elem);
         }
-    }
-}
-
-impl Default for VideoMatches {
-    fn default() -> VideoMatches {
-        VideoMatches {
-            m: Vec::new(),
-        }
+        Ok(())
     }
 }
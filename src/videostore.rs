@@ -1,36 +1,22 @@
 //use array2d::{Array2D, Error};
-use log;
+use rusqlite::{params, Connection};
 use std::io::{Read, Write};
-use rusqlite::{Connection, Result, params};
 
 static WEIGHTS: [[f64; 6]; 3] = [
     [5.00_f64, 0.83, 1.01, 0.52, 0.47, 0.30],
     [19.21, 1.26, 0.44, 0.53, 0.28, 0.14],
     [34.37, 0.36, 0.45, 0.14, 0.18, 0.27],
 ];
-pub const IMAGESCALE: u32 = 128;
-pub const INDICESMAX: u32 = 98400;
-pub static TOPCOEFS: i32 = 40;
-static WEIGHTSUMS: [f64; 6] = [58.58 as f64, 2.45, 1.9, 1.19, 0.93, 0.71];
+static WEIGHTSUMS: [f64; 6] = [58.58_f64, 2.45, 1.9, 1.19, 0.93, 0.71];
 pub static CTRL_C_PRESSED: bool = false;
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
 pub struct CandidateCache {
     pub map: std::collections::BTreeMap<u32, crate::videocandidate::VideoCandidate>,
     pub fifo: std::collections::LinkedList<u32>,
     pub max_candidates: usize,
 }
 
-impl Default for CandidateCache {
-    fn default() -> CandidateCache {
-        CandidateCache {
-            map: std::collections::BTreeMap::new(),
-            fifo: std::collections::LinkedList::new(),
-            max_candidates: 0,
-        }
-    }
-}
-
 impl CandidateCache {
     pub fn new(max_candidates: usize) -> Self {
         let mut v = CandidateCache {
@@ -53,9 +39,8 @@ impl CandidateCache {
         }
         if self.fifo.len() == self.max_candidates {
             // drop the oldest entry
-            let drop_id = self.fifo.pop_front();
-            if drop_id.is_some() {
-                self.map.remove(&drop_id.unwrap());
+            if let Some(drop_id) = self.fifo.pop_front() {
+                self.map.remove(&drop_id);
             }
         }
         self.fifo.push_back(video.index);
@@ -76,22 +61,23 @@ impl CandidateCache {
     }
 
     // decode data structure from binary stream
-    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) {
-        let maplen = crate::marshal::restore_usize(from);
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        let maplen = crate::marshal::restore_usize(from)?;
         for _ in 0..maplen {
             let mut candidate = crate::videocandidate::VideoCandidate::new();
-            candidate.decode(from);
+            candidate.decode(from)?;
             self.map.insert(candidate.index, candidate);
         }
-        let fifolen = crate::marshal::restore_usize(from);
+        let fifolen = crate::marshal::restore_usize(from)?;
         for _ in 0..fifolen {
-            self.fifo.push_back(crate::marshal::restore_u32(from));
+            self.fifo.push_back(crate::marshal::restore_u32(from)?);
         }
-        self.max_candidates = crate::marshal::restore_usize(from);
+        self.max_candidates = crate::marshal::restore_usize(from)?;
+        Ok(())
     }
 }
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
 pub struct ScreenshotIndex {
     pub id: String,
     pub video_id: u32,
@@ -99,17 +85,6 @@ pub struct ScreenshotIndex {
     pub runtime: u32,
 }
 
-impl Default for ScreenshotIndex {
-    fn default() -> ScreenshotIndex {
-        ScreenshotIndex {
-            id: String::new(),
-            video_id: 0,
-            screenshot_id: 0,
-            runtime: 0,
-        }
-    }
-}
-
 impl ScreenshotIndex {
     pub fn new() -> Self {
         ScreenshotIndex {
@@ -137,31 +112,22 @@ impl ScreenshotIndex {
     }
 
     // decode data structure from binary stream
-    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) {
-        self.id = crate::marshal::restore_string(from);
-        self.video_id = crate::marshal::restore_u32(from);
-        self.screenshot_id = crate::marshal::restore_u32(from);
-        self.runtime = crate::marshal::restore_u32(from);
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        self.id = crate::marshal::restore_string(from)?;
+        self.video_id = crate::marshal::restore_u32(from)?;
+        self.screenshot_id = crate::marshal::restore_u32(from)?;
+        self.runtime = crate::marshal::restore_u32(from)?;
+        Ok(())
     }
 }
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
 pub struct Sequence {
     pub video_id: u32,      // index of this video
     pub last_timecode: u32, // time in seconds
     pub sequence: Vec<u32>,
 }
 
-impl Default for Sequence {
-    fn default() -> Sequence {
-        Sequence {
-            video_id: 0,
-            last_timecode: 0,
-            sequence: Vec::new(),
-        }
-    }
-}
-
 impl Sequence {
     pub fn new() -> Self {
         Sequence {
@@ -196,16 +162,16 @@ impl Sequence {
 /// of slices which contains image indices (into the "candidates" slice).
 /// Use the following formula to access an index slice:
 ///
-///	store.indices[sign*ImageScale*ImageScale*haar.ColourChannels + coefIdx*haar.ColourChannels + channel]
+///   store.indices[sign*ImageScale*ImageScale*haar.ColourChannels + coefIdx*haar.ColourChannels + channel]
 ///
 /// where the variables are as follows:
 ///
-///	sign: Either 0 (positive) or 1 (negative)
-///	coefIdx: The index of the coefficient (from 0 to (ImageScale*ImageScale)-1)
-///	channel: The colour channel (from 0 to haar.ColourChannels-1)
+///   sign: Either 0 (positive) or 1 (negative)
+///   coefIdx: The index of the coefficient (from 0 to (ImageScale*ImageScale)-1)
+///   channel: The colour channel (from 0 to haar.ColourChannels-1)
 ///
-/// sentivity (Hamming distance threshold) for the perceptual hashes.
-/// Larger values will allow more images to be seen as *similar*
+/// sensitivity are the thresholds a similar screenshot has to pass. See Sensitivity::from
+/// for the calibrated levels from 0 to 100.
 ///
 /// start_directory the directory the direcotory list or walk started. Also the directory possible matches will be presented in
 /// num_threads     number of threads scanning videos
@@ -217,6 +183,9 @@ impl Sequence {
 /// candidate_cache   hold N last used video data in RAM so we don't have to hit the database all the time.
 ///                   blocks N * <data_size> for the runtime of the program! Where <data_size> is 100 MB or more, depending on the runtime of the video.
 ///                   This will easlity eat 10 GB or your RAM for 50 < N < 150.
+///
+/// config            the parameters the screenshots are hashed with. Stored in the parameters table,
+///                   databases without one use the default config.
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct VideoStore {
     //sync.RWMutex,
@@ -230,7 +199,7 @@ pub struct VideoStore {
     //pub indices: Vec<Vec<ScreenshotIndex>>,
     pub num_indices: std::collections::BTreeMap<u32, usize>,
     pub num_index_values: u32,
-    sensitivity: f64,
+    sensitivity: crate::sensitivity::Sensitivity,
     pub start_directory: String,
     pub num_threads: u32,
 
@@ -238,6 +207,7 @@ pub struct VideoStore {
     pub num_seconds_between_screenshots: u32, 
     pub min_similar_screenshots_in_sequence: u32,
     candidate_cache: CandidateCache,
+    config: crate::hashconfig::HashConfig,
 }
 
 impl Default for VideoStore {
@@ -253,25 +223,30 @@ impl Default for VideoStore {
             num_index_values: 0,
             start_directory: ".".to_string(),
             num_threads: 1,
-            sensitivity: -100.0,
+            sensitivity: crate::sensitivity::Sensitivity::from(40.0),
             modified: false,
             num_seconds_between_screenshots: 10, 
             min_similar_screenshots_in_sequence: 6,
             candidate_cache: CandidateCache::new(100),
+            config: crate::hashconfig::HashConfig::new(),
         }
     }
 }
 
 impl VideoStore {
+    /// new reads the store from the database and records the given parameters in it.
+    /// A database with videos that were hashed with another config is rejected.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         connection: &mut rusqlite::Connection,
-        sensitivity: f64,
+        sensitivity: crate::sensitivity::Sensitivity,
         start_directory: &str,
         num_threads: u32,
         num_seconds_between_screenshots: u32,
         min_similar_screenshots_in_sequence: u32,
-        max_candidates_in_cache: usize, 
-    ) -> Self {
+        max_candidates_in_cache: usize,
+        config: crate::hashconfig::HashConfig,
+    ) -> crate::Result<Self> {
         let mut v = VideoStore {
             ..Default::default()
         };
@@ -281,284 +256,184 @@ impl VideoStore {
         v.num_seconds_between_screenshots = num_seconds_between_screenshots;
         v.min_similar_screenshots_in_sequence = min_similar_screenshots_in_sequence;
         v.candidate_cache.max_candidates = max_candidates_in_cache;
-        let query = "SELECT * FROM videostore_parameters WHERE config_id = 1";
-        match connection.prepare(query) {
-            Ok(mut statement) => {
-                match statement.query(params![]) {
-                    Ok(mut rows) => {
-                        loop { 
-                            match rows.next() {
-                                Ok(Some(row)) => {
-                                    let s_opt = row.get(1);
-                                    if s_opt.is_ok() {
-                                        v.sensitivity = s_opt.unwrap();
-                                    }
-                                    let s_opt = row.get(2);
-                                    if s_opt.is_ok() {
-                                        v.start_directory = s_opt.unwrap();
-                                    }
-                                    let s_opt = row.get(3);
-                                    if s_opt.is_ok() {
-                                        v.num_threads = s_opt.unwrap();
-                                    }
-                                    let s_opt = row.get(4);
-                                    if s_opt.is_ok() {
-                                        v.num_seconds_between_screenshots = s_opt.unwrap();
-                                    }
-                                    let s_opt = row.get(5);
-                                    if s_opt.is_ok() {
-                                        v.min_similar_screenshots_in_sequence = s_opt.unwrap();
-                                    }
-                                    let s_opt = row.get(6);
-                                    if s_opt.is_ok() {
-                                        v.candidate_cache.max_candidates = s_opt.unwrap();
-                                    }
-                                    break;
-                                },
-                                Ok(None) => {
-                                    log::warn!("No data read from parameters.");
-                                    break;
-                                },
-                                Err(error) => {
-                                    log::error!("Failed to read a row from parameters: {}", error);
-                                    break;
-                                }
-                            }
-                        }
-                    },
-                    Err(err) => {
-                        log::error!("could not read line from parameter database: {}", err);
+        let mut stored_config = None;
+        {
+            let query = "SELECT * FROM videostore_parameters WHERE config_id = 1";
+            let mut statement = connection.prepare(query)?;
+            let mut rows = statement.query(params![])?;
+            match rows.next()? {
+                Some(row) => {
+                    // earlier releases stored a truncated raw score threshold
+                    // without the limits of the secondary metrics
+                    if let Ok(s) = row.get(1) {
+                        v.sensitivity = crate::sensitivity::Sensitivity::from_score_threshold(s);
                     }
-                }
-                if v.sensitivity != sensitivity 
-                || v.num_seconds_between_screenshots != num_seconds_between_screenshots 
-                || v.min_similar_screenshots_in_sequence != min_similar_screenshots_in_sequence
-                || v.candidate_cache.max_candidates != max_candidates_in_cache {
-                    // change the parameters in the database
-                    let query_delete = "DELETE FROM videostore_parameters WHERE config_id = 1";
-                    match connection.execute(query_delete, params![]) {
-                        Ok(retval) => log::warn!("Deleted {} data from parameters.", retval),
-                        Err(error) => {
-                            log::error!("Failed to delete data from parameter database: {}", error);
-                            return v;
-                        }
+                    if let Ok(s) = row.get(2) {
+                        v.start_directory = s;
+                    }
+                    if let Ok(s) = row.get(3) {
+                        v.num_threads = s;
+                    }
+                    if let Ok(s) = row.get(4) {
+                        v.num_seconds_between_screenshots = s;
+                    }
+                    if let Ok(s) = row.get(5) {
+                        v.min_similar_screenshots_in_sequence = s;
+                    }
+                    if let Ok(s) = row.get(6) {
+                        v.candidate_cache.max_candidates = s;
                     }
-                    let sensitivity_u32 = v.sensitivity as u32;
-                    match connection.execute(
-                        "INSERT INTO videostore_parameters (config_id, sensitivity, start_directory, num_threads, num_seconds_between_screenshots, min_similar_screenshots_in_sequence, max_candidates_in_cache) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-                        params![&1, &sensitivity_u32, &v.start_directory, &v.num_threads, &v.num_seconds_between_screenshots, &v.min_similar_screenshots_in_sequence, &v.candidate_cache.max_candidates],
-                    ) {
-                        Ok(retval) => log::warn!("Inserted {} data into parameter.", retval),
-                        Err(error) => {
-                            log::error!("Failed to insert data into parameter database: {}", error);
-                            return v;
+                    // no config was recorded before the columns existed
+                    let scale: Option<u32> = row.get(7).unwrap_or(None);
+                    let top_coefs: Option<i32> = row.get(8).unwrap_or(None);
+                    let filter: Option<u32> = row.get(9).unwrap_or(None);
+                    let perceptual: Option<u32> = row.get(12).unwrap_or(None);
+                    let apply_orientation: Option<bool> = row.get(13).unwrap_or(None);
+                    v.config = match (scale, top_coefs, filter, perceptual, apply_orientation) {
+                        (Some(scale), Some(top_coefs), Some(filter), Some(perceptual), Some(apply_orientation)) => {
+                            let mut hashconfig = crate::hashconfig::HashConfig::from(
+                                scale, top_coefs, crate::hashconfig::ResizeFilter::from_code(filter)?);
+                            hashconfig.perceptual = crate::perceptualhash::Algorithms::from_bits(perceptual)?;
+                            hashconfig.apply_orientation = apply_orientation;
+                            hashconfig.validate()?;
+                            stored_config = Some(hashconfig);
+                            hashconfig
                         }
+                        _ => crate::hashconfig::HashConfig::new(),
+                    };
+                    if let Ok(Some(s)) = row.get(10) {
+                        v.sensitivity.max_dhash_distance = s;
+                    }
+                    if let Ok(Some(s)) = row.get(11) {
+                        v.sensitivity.max_histogram_distance = s;
                     }
-                    v.sensitivity = sensitivity;
-                    v.start_directory = start_directory.to_string();
-                    v.num_threads = num_threads;
-                    v.num_seconds_between_screenshots = num_seconds_between_screenshots;
-                    v.min_similar_screenshots_in_sequence = min_similar_screenshots_in_sequence;
-                    v.candidate_cache.max_candidates = max_candidates_in_cache;
                 }
-            },
-            Err(error) => {
-                log::error!("Could not prepare statement {}: {}", query, error);
-                return v;
+                None => log::warn!("No data read from parameters."),
             }
         }
-        let query_candidates = "SELECT candidate_id, filename, video_id FROM videostore_candidates";
-        match connection.prepare(query_candidates) {
-            Ok(mut statement) => {
-                match statement.query(params![]) {
-                    Ok(mut rows) => {
-                        loop {
-                            match rows.next() {
-                                Ok(Some(row)) => {
-                                    let mut candidate_id: usize;
-                                    let id;
-                                    let video_id;
-                                    match row.get(0) {
-                                        Ok(s) => candidate_id = s,
-                                        Err(error) => {
-                                            log::error!("Failed to read candiate_id for video: {}", error);
-                                            continue;
-                                        }
-                                    }
-                                    match row.get(1) {
-                                        Ok(s) => id = s,
-                                        Err(error) => {
-                                            log::error!("Failed to read id for video: {}", error);
-                                            continue;
-                                        }
-                                    }
-                                    match row.get(2) {
-                                        Ok(s) => video_id = s,
-                                        Err(error) => {
-                                            log::error!("Failed to read video_id for video: {}", error);
-                                            continue;
-                                        }
-                                    }
-                                    candidate_id -= 1;
-                                    v.ids.insert(id, candidate_id as usize);
-                                    v.video_ids.insert(video_id, candidate_id as usize);
-                                    v.num_candidates += 1;
-                                },
-                                Ok(None) => {
-                                    log::warn!("No data read from candidates.");
-                                    break;
-                                },
-                                Err(error) => {
-                                    log::error!("Failed to read a row from candidates: {}", error);
-                                    break;
-                                }
-                            }
-                        }
-                    },
-                    Err(err) => {
-                        log::error!("could not read line from candidate database: {}", err);
-                    }
-                }
-            },
-            Err(error) => {
-                log::error!("Could not prepare statement {}: {}", query_candidates, error);
-                return v;
+        if v.config != config {
+            let num_videos: u32 =
+                connection.query_row("SELECT COUNT(candidate_id) FROM videostore_candidates", params![], |row| row.get(0))?;
+            if num_videos > 0 {
+                return Err(crate::Error::Conflict(format!(
+                    "the videos in the database were hashed with {:?} instead of {:?}", v.config, config)));
             }
         }
+        if v.sensitivity != sensitivity
+            || v.num_seconds_between_screenshots != num_seconds_between_screenshots
+            || v.min_similar_screenshots_in_sequence != min_similar_screenshots_in_sequence
+            || v.candidate_cache.max_candidates != max_candidates_in_cache
+            || stored_config != Some(config)
+        {
+            // change the parameters in the database
+            let query_delete = "DELETE FROM videostore_parameters WHERE config_id = 1";
+            let retval = connection.execute(query_delete, params![])?;
+            log::warn!("Deleted {} data from parameters.", retval);
+            let retval = connection.execute(
+                "INSERT INTO videostore_parameters (config_id, sensitivity, start_directory, num_threads, num_seconds_between_screenshots, min_similar_screenshots_in_sequence, max_candidates_in_cache, hash_scale, hash_top_coefs, hash_filter, max_dhash_distance, max_histogram_distance, hash_perceptual, hash_apply_orientation) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                params![&1, &sensitivity.score_threshold, &start_directory, &num_threads, &num_seconds_between_screenshots, &min_similar_screenshots_in_sequence, &max_candidates_in_cache, &config.scale, &config.top_coefs, &config.filter.code(), &sensitivity.max_dhash_distance, &sensitivity.max_histogram_distance, &config.perceptual.bits(), &config.apply_orientation],
+            )?;
+            log::warn!("Inserted {} data into parameter.", retval);
+            v.sensitivity = sensitivity;
+            v.start_directory = start_directory.to_string();
+            v.num_threads = num_threads;
+            v.num_seconds_between_screenshots = num_seconds_between_screenshots;
+            v.min_similar_screenshots_in_sequence = min_similar_screenshots_in_sequence;
+            v.candidate_cache.max_candidates = max_candidates_in_cache;
+        }
+        v.config = config;
+
+        let query_candidates = "SELECT candidate_id, filename, video_id FROM videostore_candidates";
+        let mut statement = connection.prepare(query_candidates)?;
+        let mut rows = statement.query(params![])?;
+        while let Some(row) = rows.next()? {
+            let candidate_id: usize = row.get(0)?;
+            let id: String = row.get(1)?;
+            let video_id: u32 = row.get(2)?;
+            v.ids.insert(id, candidate_id - 1);
+            v.video_ids.insert(video_id, candidate_id - 1);
+            v.num_candidates += 1;
+        }
+        drop(rows);
+        drop(statement);
+
         // fill the num_indices  and number of indices values
-        let query_index_location_count = "SELECT location, COUNT(index_id) FROM videostore_indices GROUP BY location";
-        match connection.prepare(query_index_location_count) {
-            Ok(mut statement) => {
-                match statement.query(params![]) {
-                    Ok(mut rows) => {
-                        loop {
-                            match rows.next() {
-                                Ok(Some(row)) => {
-                                    let location;
-                                    let num_entries;
-                                    match row.get(0) {
-                                        Ok(s) => location = s,
-                                        Err(error) => {
-                                            log::error!("Failed to read location for indices: {}", error);
-                                            continue;
-                                        }
-                                    }
-                                    match row.get(1) {
-                                        Ok(s) => num_entries = s,
-                                        Err(error) => {
-                                            log::error!("Failed to read count for indices: {}", error);
-                                            continue;
-                                        }
-                                    }
-                                    v.num_indices.insert(location, num_entries);
-                                },
-                                Ok(None) => {
-                                    log::warn!("No data read from indices.");
-                                    break;
-                                },
-                                Err(error) => {
-                                    log::error!("Failed to read a row from indices: {}", error);
-                                    break;
-                                }
-                            }
-                        }
-                    },
-                    Err(err) => {
-                        log::error!("could not read line from indices database: {}", err);
-                    }
-                }
-            },
-            Err(error) => {
-                log::error!("Could not prepare statement {}: {}", query_index_location_count, error);
-                return v;
-            }
+        let query_index_location_count =
+            "SELECT location, COUNT(index_id) FROM videostore_indices GROUP BY location";
+        let mut statement = connection.prepare(query_index_location_count)?;
+        let mut rows = statement.query(params![])?;
+        while let Some(row) = rows.next()? {
+            let location: u32 = row.get(0)?;
+            let num_entries: usize = row.get(1)?;
+            v.num_indices.insert(location, num_entries);
         }
+        drop(rows);
+        drop(statement);
 
         let query_index_count = "SELECT COUNT(index_id) FROM videostore_indices";
-        match connection.prepare(query_index_count) {
-            Ok(mut statement) => {
-                match statement.query(params![]) {
-                    Ok(mut rows) => {
-                        loop {
-                            match rows.next() {
-                                Ok(Some(row)) => {
-                                    match row.get(0) {
-                                        Ok(s) => v.num_index_values = s,
-                                        Err(error) => {
-                                            log::error!("Failed to read count for indices: {}", error);
-                                            continue;
-                                        }
-                                    }
-                                },
-                                Ok(None) => {
-                                    log::warn!("No data read from indices.");
-                                    break;
-                                },
-                                Err(error) => {
-                                    log::error!("Failed to read a row from indices: {}", error);
-                                    break;
-                                }
-                            }
-                        }
-                    },
-                    Err(err) => {
-                        log::error!("could not read line from indices database: {}", err);
-                    }
-                }
-            },
-            Err(error) => {
-                log::error!("Could not prepare statement {}: {}", query_index_count, error);
-                return v;
-            }
-        }
-        v
+        v.num_index_values = connection.query_row(query_index_count, params![], |row| row.get(0))?;
+        Ok(v)
     }
 
     pub fn has(&self, id: &str) -> bool {
-        if self.ids.contains_key(id) {
-            return true;
+        self.ids.contains_key(id)
+    }
+
+    pub fn sensitivity(&self) -> crate::sensitivity::Sensitivity {
+        self.sensitivity
+    }
+
+    /// config returns the parameters the screenshots of the store are hashed with.
+    pub fn config(&self) -> crate::hashconfig::HashConfig {
+        self.config
+    }
+
+    /// check_config returns an error if a screenshot of the video was hashed with
+    /// another config than the store's.
+    fn check_config(&self, video: &crate::videocandidate::VideoCandidate) -> crate::Result<()> {
+        for screenshot in video.screenshots.iter() {
+            self.config.check(&screenshot.hash.config)?;
         }
-        false
+        Ok(())
     }
 
     /// Add adds an image (via its hash) to the store.
     /// The provided ID of the video and the index of the screenshot is the value
     /// that will be returned as the result of a similarity query. If an ID is
-    /// already in the store, it is not added again.
+    /// already in the store, it is not added again. Videos whose screenshots were
+    /// hashed with another config than the store's are rejected.
     pub fn add(
         &mut self,
         connection: &mut rusqlite::Connection,
         id: &str,
         video: &crate::videocandidate::VideoCandidate,
         _runtime: u32,
-    ) {
+    ) -> crate::Result<()> {
+        self.check_config(video)?;
         if self.ids.contains_key(id) {
-            return;
-        }
-        let candidate_id;
-        if !self.ids.contains_key(id) {
-            candidate_id = self.num_candidates + 1;
-            let mut blob = Vec::new();
-            video.encode(&mut blob);
-            log::warn!("Inserting Video information of length {} and data size of {} MegaBytes", video.runtime, blob.len() / 1024 / 1024);
-            match connection.execute(
-                "INSERT INTO videostore_candidates (candidate_id, filename, video_id, data) VALUES (?1, ?2, ?3, ?4)",
-                params![&candidate_id, &video.id, &video.index, &blob],
-            ) {
-                Ok(_retval) => {}, //log::warn!("Inserted {} video with ID {} and location {} into candidates.", video.id, video.index, candidate_id),
-                Err(error) => {
-                    log::error!("Failed to insert video {} into  database: {}", video.id, error);
-                    return;
-                }
-            }
-            self.num_candidates += 1;
-            self.video_ids.insert(video.index, candidate_id as usize);
-            self.ids.insert(id.to_string(), candidate_id as usize);
+            return Ok(());
         }
-        for i in 0..video.screenshots.len() {
-            let hash = &video.screenshots[i].hash;
+        let candidate_id = self.num_candidates + 1;
+        let mut blob = Vec::new();
+        video.encode(&mut blob);
+        log::warn!(
+            "Inserting Video information of length {} and data size of {} MegaBytes",
+            video.runtime,
+            blob.len() / 1024 / 1024
+        );
+        connection.execute(
+            "INSERT INTO videostore_candidates (candidate_id, filename, video_id, data) VALUES (?1, ?2, ?3, ?4)",
+            params![&candidate_id, &video.id, &video.index, &blob],
+        )?;
+        self.num_candidates += 1;
+        self.video_ids.insert(video.index, candidate_id as usize);
+        self.ids.insert(id.to_string(), candidate_id as usize);
+        self.modified = true;
+
+        for screenshot in video.screenshots.iter() {
+            let hash = &screenshot.hash;
             if hash.matrix.coefs.len() < 2 {
-                return;
+                return Ok(());
             }
             for coefindex in 1..hash.matrix.coefs.len() {
                 let coef = &hash.matrix.coefs[coefindex];
@@ -571,31 +446,23 @@ impl VideoStore {
                     if colorcoef < 0.0 {
                         sign = 1;
                     }
-                    let location = sign * IMAGESCALE * IMAGESCALE * crate::haar::COLOURCHANNELS
+                    let location = sign * self.config.scale * self.config.scale * crate::haar::COLOURCHANNELS
                         + coefindex as u32 * crate::haar::COLOURCHANNELS
-                        + colorindex as u32 + 1;
-                    if !self.num_indices.contains_key(&location) {
-                        self.num_indices.insert(location, 0);
-                    }
-                    let arrayindex = self.num_indices[&location] as u32 + 1;
+                        + colorindex as u32
+                        + 1;
+                    let num_entries = self.num_indices.entry(location).or_insert(0);
+                    let arrayindex = *num_entries as u32 + 1;
                     let index_id = self.num_index_values + 1;
-                    match connection.execute(
+                    connection.execute(
                         "INSERT INTO videostore_indices (index_id, location, arrayindex, filename, video_id, screenshot_id, runtime) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-                        params![&index_id, location, &arrayindex, &video.id, &video.index, &video.screenshots[i].screenshot_id, &video.runtime],
-                    ) {
-                        Ok(_retval) => {}, //log::warn!("Inserted screenshot {} video_id {} into indices at location {} as {} element.", video.screenshots[i].screenshot_id, video.index, location, arrayindex),
-                        Err(error) => {
-                            log::error!("Failed to insert video {} into  database: {}", video.id, error);
-                            return;
-                        }
-        
-                    }
-                    *self.num_indices.get_mut(&location).unwrap() += 1;
+                        params![&index_id, location, &arrayindex, &video.id, &video.index, &screenshot.screenshot_id, &video.runtime],
+                    )?;
+                    *num_entries += 1;
                     self.num_index_values += 1;
                 }
             }
         }
-        self.modified = true;
+        Ok(())
     }
 
     pub fn ids(&self) -> Vec<String> {
@@ -611,84 +478,67 @@ impl VideoStore {
     /// index will be removed from all index lists. This also means that Size() will
     /// not decrease. This is an expensive operation. If the provided ID could not be
     /// found, nothing happens.
-    pub fn delete(&mut self, connection: &mut rusqlite::Connection, id: &str) {
+    pub fn delete(&mut self, connection: &mut rusqlite::Connection, id: &str) -> crate::Result<()> {
         if !self.ids.contains_key(id) {
-            return;
+            return Ok(());
         }
         // Get the index.
-        //let index = self.ids[id];
-        let query = "SELECT video_id FROM videostore_candidates WHERE filename = ?1";
-        match connection.prepare(query) {
-            Ok(mut statement) => {
-                match statement.query(params![id]) {
-                    Ok(mut rows) => {
-                        while let Ok(Some(row)) = rows.next() {
-                            let s_opt = row.get(1);
-                            if s_opt.is_ok() {
-                                let video_id = s_opt.unwrap();
-                                self.video_ids.remove(&video_id);
-                            }
-                        }
-                    },
-                    Err(err) => {
-                        log::error!("could not read line from videostore_candidates database: {}", err);
-                    }
-                }
-            },
-            Err(error) => {
-                log::error!("Failed to get video_id for {} from database: {}", id, error);
-                return;
+        {
+            let query = "SELECT video_id FROM videostore_candidates WHERE filename = ?1";
+            let mut statement = connection.prepare(query)?;
+            let mut rows = statement.query(params![id])?;
+            while let Some(row) = rows.next()? {
+                let video_id: u32 = row.get(0)?;
+                self.video_ids.remove(&video_id);
             }
         }
         self.modified = true;
         // clear the entry in the candidates list without deleting it
-        let ret = connection.execute(
+        connection.execute(
             "DELETE FROM videostore_candidates WHERE filename = ?1",
             params![&id],
-        );
-        if ret.is_err() {
-            log::error!("Failed to delete candidate {}!", id);
-            return;
-        }
+        )?;
         self.ids.remove(id);
 
         // Remove from all index lists.
-        let ret = connection.execute(
+        connection.execute(
             "DELETE FROM videostore_indices WHERE filename = ?1",
             params![&id],
-        );
-        if ret.is_err() {
-            log::error!("Failed to delete indices for {}!", id);
-            return;
-        }
+        )?;
+        Ok(())
     }
 
     /// Exchange exchanges the ID of an image for a new one. If the old ID could not
     /// be found, nothing happens. If the new ID already existed prior to the
     /// exchange, the function returns immediately.
     ///
-    pub fn exchange(&mut self, connection: &mut rusqlite::Connection, oldid: &str, newid: &str) -> bool {
+    pub fn exchange(
+        &mut self,
+        connection: &mut rusqlite::Connection,
+        oldid: &str,
+        newid: &str,
+    ) -> crate::Result<bool> {
         if !self.ids.contains_key(oldid) {
-            return false;
+            return Ok(false);
         }
         if self.ids.contains_key(newid) {
-            return false;
+            return Ok(false);
         }
-        let index = self.ids[oldid];
+        // Update the candidate.
+        connection.execute(
+            "UPDATE videostore_candidates SET filename = ?1 WHERE filename = ?2",
+            params![&newid, &oldid],
+        )?;
+        connection.execute(
+            "UPDATE videostore_indices SET filename = ?1 WHERE filename = ?2",
+            params![&newid, &oldid],
+        )?;
         // update the ids
+        let index = self.ids[oldid];
         self.ids.remove(oldid);
         self.ids.insert(newid.to_string(), index);
-
-        // Update the candidate.
-        let ret = connection.execute(
-            "UPDATE videostore_candidates filename = ?1 WHERE filename = ?2",
-            params![&newid, &oldid],
-        );
-        if ret.is_err() {
-            log::error!("Failed to update candidate {}!", oldid);
-            return false;
-        }
-        true
+        self.modified = true;
+        Ok(true)
     }
 
     /// Find all similar screenshots for a single screenshot
@@ -698,20 +548,16 @@ impl VideoStore {
         hash: &crate::hash::Hash,
         video_ids: &std::collections::BTreeMap<u32, usize>,
         screenshot_index_global: usize,
-        video_screenshot_to_score_map: &Vec<Vec<usize>>,
-        scoreid_to_video_screenshot_map: &Vec<ScreenshotIndex>,
-    ) -> crate::videomatches::VideoMatches {
+        video_screenshot_to_score_map: &[Vec<usize>],
+        scoreid_to_video_screenshot_map: &[ScreenshotIndex],
+    ) -> crate::Result<crate::videomatches::VideoMatches> {
         let mut ms = crate::videomatches::VideoMatches::new();
         // build a mapping of video, screenshot to a global image index
         if self.num_candidates == 0 {
-            return ms;
+            return Ok(ms);
         }
         // prepare the scoring vector where we can rate any existing screenshot
-        let mut scores: Vec<f64> = Vec::new();
-        scores.reserve(screenshot_index_global);
-        for _ in 0..screenshot_index_global {
-            scores.push(f64::NAN);
-        }
+        let mut scores: Vec<f64> = vec![f64::NAN; screenshot_index_global];
 
         // Examine hash buckets.
         for coefindex in 0..hash.matrix.coefs.len() {
@@ -741,22 +587,25 @@ impl VideoStore {
                 if colorcoef < 0.0 {
                     sign = 1;
                 }
-                let location = sign * IMAGESCALE * IMAGESCALE * crate::haar::COLOURCHANNELS
+                let location = sign * self.config.scale * self.config.scale * crate::haar::COLOURCHANNELS
                     + coefindex as u32 * crate::haar::COLOURCHANNELS
-                    + colorindex as u32 + 1;
-                let arr = &self.return_indice(client, location);
-                for i in 0..arr.len() {
-                    let matchscreenshot = arr[i].clone();
+                    + colorindex as u32
+                    + 1;
+                let arr = self.return_indice(client, location)?;
+                for matchscreenshot in arr.iter() {
                     if !video_ids.contains_key(&matchscreenshot.video_id) {
                         continue;
                     }
                     let video_pos = video_ids[&matchscreenshot.video_id];
                     if video_pos >= video_screenshot_to_score_map.len()
-                        || video_screenshot_to_score_map[video_pos].len()
-                            == 0
+                        || video_screenshot_to_score_map[video_pos].is_empty()
                     {
-                        log::error!("Search Matches failed to lookup the video position {} by its Index {}", video_pos, matchscreenshot.video_id);
-                        return ms;
+                        log::error!(
+                            "Search Matches failed to lookup the video position {} by its Index {}",
+                            video_pos,
+                            matchscreenshot.video_id
+                        );
+                        return Ok(ms);
                     }
                     if matchscreenshot.screenshot_id < 1 {
                         continue;
@@ -765,12 +614,13 @@ impl VideoStore {
                     if screenshot_pos >= video_screenshot_to_score_map[video_pos].len() {
                         continue;
                     }
-                    let screenshot_index_global = video_screenshot_to_score_map[video_pos][screenshot_pos];
+                    let screenshot_index_global =
+                        video_screenshot_to_score_map[video_pos][screenshot_pos];
                     if scores[screenshot_index_global].is_nan() {
                         // calculated initial score
                         let mut score: f64 = 0.0;
-                        for colorid in 0..coef.c.len() {
-                            score += WEIGHTS[colorid][0];
+                        for weights in WEIGHTS.iter().take(coef.c.len()) {
+                            score += weights[0];
                         }
                         scores[screenshot_index_global] = score;
                     }
@@ -781,50 +631,49 @@ impl VideoStore {
             }
         }
         // Create matches. If the dhash_distance is lower than the sensitivity threshold it is a *valid* match.
-        for index in 0..scores.len() {
-            if !scores[index].is_nan() {
-                if scores[index] > self.sensitivity {
-                    continue;
-                }
-                let mut m = crate::videomatches::VideoMatch::new();
-                let video_id = scoreid_to_video_screenshot_map[index].video_id;
-                let screenshot_id = scoreid_to_video_screenshot_map[index].screenshot_id;
-                let screenshot;
-                if self.candidate_cache.max_candidates > 0 {
-                    if !self.candidate_cache.contains(video_id) {
-                        let (_, candidate) = self.return_candidate(client, video_id);
-                        log::warn!("Found Match {}", candidate.id);
-                        self.candidate_cache.add(candidate);
-                    }
-                    m.id = self.candidate_cache.map[&video_id].id.clone();
-                    let screenshot_pos = screenshot_id as usize - 1;
-                    screenshot = self.candidate_cache.map[&video_id].screenshots[screenshot_pos].clone();
-                } else {
-                    let (_, candidate) = self.return_candidate(client, video_id);
+        for (index, score) in scores.iter().enumerate() {
+            if score.is_nan() || *score > self.sensitivity.score_threshold {
+                continue;
+            }
+            let mut m = crate::videomatches::VideoMatch::new();
+            let video_id = scoreid_to_video_screenshot_map[index].video_id;
+            let screenshot_id = scoreid_to_video_screenshot_map[index].screenshot_id;
+            let screenshot_pos = screenshot_id as usize - 1;
+            let screenshot;
+            if self.candidate_cache.max_candidates > 0 {
+                if !self.candidate_cache.contains(video_id) {
+                    let (_, candidate) = self.return_candidate(client, video_id)?;
                     log::warn!("Found Match {}", candidate.id);
-                    m.id = candidate.id.clone();
-                    let screenshot_pos = screenshot_id as usize - 1;
-                    screenshot = candidate.screenshots[screenshot_pos].clone();
-                }
-                m.video_id = video_id;
-                m.screenshot_id = screenshot_id;
-                m.timecode = screenshot.timecode;
-                m.score = scores[index];
-                m.ratio_diff = screenshot.hash.ratio.log(10.0).abs() - hash.ratio.log(10.0);
-                m.dhash_distance =
-                    crate::hamming::hamming_distance(screenshot.hash.dhash[0], hash.dhash[0])
-                        + crate::hamming::hamming_distance(screenshot.hash.dhash[1], hash.dhash[1]);
-                m.histogram_distance =
-                    crate::hamming::hamming_distance(screenshot.hash.histogram, hash.histogram);
-                if m.score < self.sensitivity {
-                    ms.m.push(m);
+                    self.candidate_cache.add(candidate);
                 }
+                m.id = self.candidate_cache.map[&video_id].id.clone();
+                screenshot = self.candidate_cache.map[&video_id].screenshots[screenshot_pos].clone();
+            } else {
+                let (_, candidate) = self.return_candidate(client, video_id)?;
+                log::warn!("Found Match {}", candidate.id);
+                m.id = candidate.id.clone();
+                screenshot = candidate.screenshots[screenshot_pos].clone();
+            }
+            m.video_id = video_id;
+            m.screenshot_id = screenshot_id;
+            m.timecode = screenshot.timecode;
+            m.score = *score;
+            m.ratio_diff = (screenshot.hash.ratio.log(10.0) - hash.ratio.log(10.0)).abs();
+            m.dhash_distance =
+                crate::hamming::hamming_distance(screenshot.hash.dhash[0], hash.dhash[0])
+                    + crate::hamming::hamming_distance(screenshot.hash.dhash[1], hash.dhash[1]);
+            m.histogram_distance =
+                crate::hamming::hamming_distance(screenshot.hash.histogram, hash.histogram);
+            if m.score < self.sensitivity.score_threshold
+                && self.sensitivity.accepts(m.dhash_distance, m.histogram_distance)
+            {
+                ms.m.push(m);
             }
         }
         // sort the vector so the first match is the one with the lowest value --> the best match
         // The number of matches should be small, so the bubble sort is about the fastest algorithm.
         ms.sort();
-        ms
+        Ok(ms)
     }
 
     /// create a score value for a *similar* video in comparison to duration, resulution. ...
@@ -837,18 +686,19 @@ impl VideoStore {
         screenshot_id: u32,
         num_matches: usize,
         time_between_screenshots: u32,
-    ) -> crate::videomatches::VideoMatch {
+    ) -> crate::Result<crate::videomatches::VideoMatch> {
         let mut m = crate::videomatches::VideoMatch::new();
-        let (_, matched) = self.return_candidate(client, match_id);
+        let (_, matched) = self.return_candidate(client, match_id)?;
         m.id = matched.id.clone();
         m.video_id = matched.index;
         m.screenshot_id = screenshot_id;
-        m.timecode = screenshot_id * time_between_screenshots as u32;
+        m.timecode = screenshot_id * time_between_screenshots;
+        let width_diff = new_video.width as f64 - matched.width as f64;
         m.score = -60.0                                                                                // base value
                     - 100.0 * (num_matches as f64 * time_between_screenshots as f64) / matched.runtime as f64 // the longer the similar part, the better the match
-                    + ((new_video.width - matched.width) * (new_video.width - matched.width)) as f64;  // if the resolution is higher the match gets better
+                    + width_diff * width_diff; // if the resolution is higher the match gets better
 
-        m
+        Ok(m)
     }
 
     /// Query performs a similarity search on the given image hashes and returns
@@ -863,12 +713,13 @@ impl VideoStore {
         &mut self,
         client: &mut rusqlite::Connection,
         video: &crate::videocandidate::VideoCandidate,
-    ) -> crate::videomatches::VideoMatches {
+    ) -> crate::Result<crate::videomatches::VideoMatches> {
         let mut ms = crate::videomatches::VideoMatches::new();
+        self.check_config(video)?;
         if self.num_candidates == 0 {
-            return ms;
+            return Ok(ms);
         }
-        let mut sequences = std::collections::BTreeMap::new();
+        let mut sequences: std::collections::BTreeMap<u32, Vec<u32>> = std::collections::BTreeMap::new();
         let mut active_sequence_counter = std::collections::BTreeMap::new();
 
         // prepare data structures
@@ -877,9 +728,9 @@ impl VideoStore {
         let mut video_ids: std::collections::BTreeMap<u32, usize> = std::collections::BTreeMap::new();
         let mut screenshot_index_global: usize = 0;
         for video_id in 1..self.num_candidates + 1 {
-            let (_candidate_id, candidate) = self.return_candidate(client, video_id);
+            let (_candidate_id, candidate) = self.return_candidate(client, video_id)?;
             let video_pos = video_screenshot_to_score_map.len();
-            video_ids.insert(candidate.index, video_id  as usize - 1);
+            video_ids.insert(candidate.index, video_id as usize - 1);
             video_screenshot_to_score_map.push(Vec::new());
             for screenshot_pos in 0..candidate.screenshots.len() {
                 video_screenshot_to_score_map[video_pos].push(screenshot_index_global);
@@ -894,67 +745,55 @@ impl VideoStore {
         }
 
         // search for each screenshot of the current video in the store
-        let report_interval = (5 * 60 / self.num_seconds_between_screenshots) as usize;
+        let report_interval = std::cmp::max(1, 5 * 60 / self.num_seconds_between_screenshots.max(1)) as usize;
         for screenshot_pos in 0..video.screenshots.len() {
             let hash = &video.screenshots[screenshot_pos].hash;
             let matches = self.search_matches(
-                                client, 
-                                hash, 
+                                client,
+                                hash,
                                 &video_ids,
                                 screenshot_index_global,
                                 &video_screenshot_to_score_map,
                                 &scoreid_to_video_screenshot_map,
-                            );
+                            )?;
             let mut previous_videos = std::collections::BTreeSet::new();
             for (key, _) in sequences.iter() {
                 previous_videos.insert(*key);
             }
-            let mut new_videos= std::collections::BTreeSet::new();
+            let mut new_videos = std::collections::BTreeSet::new();
             if screenshot_pos % report_interval == 0 {
-                log::warn!("Comparing new video with the databse. Currently at position {} Minutes into the video", 
-                            (screenshot_pos as u32 / self.min_similar_screenshots_in_sequence) as u32);
+                log::warn!("Comparing new video with the databse. Currently at position {} Minutes into the video",
+                            screenshot_pos as u32 / self.min_similar_screenshots_in_sequence.max(1));
             }
-            for i in 0..matches.len() {
-                let video_id = matches.m[i].video_id;
+            for videomatch in matches.m.iter() {
+                let video_id = videomatch.video_id;
                 new_videos.insert(video_id);
-                let screenshot_id = matches.m[i].screenshot_id;
-                if !active_sequence_counter.contains_key(&video_id) {
-                    active_sequence_counter.insert(video_id, 1 as u32);
-                } else {
-                    if let Some(x) = active_sequence_counter.get_mut(&video_id) {
-                        *x += 1;
-                    }
-                }
-                if !sequences.contains_key(&video_id) {
-                    sequences.insert(video_id, Vec::from([screenshot_id]));
-                } else {
-                    if let Some(x) = sequences.get_mut(&video_id) {
-                        if x[x.len() - 1] + 1 == screenshot_id {
-                            x.push(screenshot_id);
-                            break;
-                        } else {
-                            // broken sequence
-                            x.clear();
-                            x.push(screenshot_id);
-                            break;
-                        }
+                let screenshot_id = videomatch.screenshot_id;
+                *active_sequence_counter.entry(video_id).or_insert(0_u32) += 1;
+                if let Some(x) = sequences.get_mut(&video_id) {
+                    if x[x.len() - 1] + 1 != screenshot_id {
+                        // broken sequence
+                        x.clear();
                     }
+                    x.push(screenshot_id);
+                    break;
                 }
+                sequences.insert(video_id, Vec::from([screenshot_id]));
             }
             // remove the videos from the sequence that were not a match
-            let dropped_videos = previous_videos.difference(&new_videos);
-            for id in dropped_videos {
+            let dropped_videos: Vec<u32> = previous_videos.difference(&new_videos).cloned().collect();
+            for id in dropped_videos.iter() {
                 // check if the sequence was longer than a minute amd add the ones long enough
                 if sequences[id].len() > 5 {
                     let videomatch =
                         self.rate_match(
-                            client, 
-                            &video, 
-                            *id, 
-                            screenshot_pos as u32, 
+                            client,
+                            video,
+                            *id,
+                            screenshot_pos as u32,
                             sequences[id].len(),
-                            self.num_seconds_between_screenshots);
-                    if videomatch.id.len() != 0 {
+                            self.num_seconds_between_screenshots)?;
+                    if !videomatch.id.is_empty() {
                         ms.m.push(videomatch);
                     }
                 } else {
@@ -962,159 +801,71 @@ impl VideoStore {
                 }
                 sequences.remove(id);
             }
-            new_videos.clear();
         }
         // done parsing, add everything with more than 5 matches in a row to the list
         for (video_id, v) in sequences {
             // check if the sequence was longer than a minute
             if v.len() >= self.min_similar_screenshots_in_sequence as usize {
                 let mut m = crate::videomatches::VideoMatch::new();
-                let matchedvideo;
-                if self.candidate_cache.contains(video_id) {
-                    matchedvideo = self.candidate_cache.map[&video_id].clone();
+                let matchedvideo = if self.candidate_cache.contains(video_id) {
+                    self.candidate_cache.map[&video_id].clone()
                 } else {
-                    (_, matchedvideo) = self.return_candidate(client, video_id);
-                }
+                    self.return_candidate(client, video_id)?.1
+                };
                 m.id = matchedvideo.id.clone();
                 m.video_id = matchedvideo.index;
                 m.screenshot_id = 0;
                 m.timecode = 0;
+                let width_diff = video.width as f64 - matchedvideo.width as f64;
                 m.score = -60.0                                                                            // base value
                             - 100.0 * (v.len() as f64 * 10.0) / matchedvideo.runtime as f64                 // the longer the similar part, the better the match
-                            + ((video.width - matchedvideo.width) * (video.width - matchedvideo.width)) as f64; // if the resolution is higher the match gets better
-                if m.id.len() != 0 {
+                            + width_diff * width_diff; // if the resolution is higher the match gets better
+                if !m.id.is_empty() {
                     ms.m.push(m);
                 }
             }
         }
         ms.sort();
-        ms
+        Ok(ms)
     }
 
     pub fn return_candidate(
         &self,
         connection: &mut rusqlite::Connection,
         video_id: u32,
-    ) -> (u32, crate::videocandidate::VideoCandidate) {
+    ) -> crate::Result<(u32, crate::videocandidate::VideoCandidate)> {
         let mut v = crate::videocandidate::VideoCandidate::new();
         let mut candidate_id = 0;
         let query = "SELECT candidate_id, data FROM videostore_candidates WHERE video_id = ?1";
-        match connection.prepare(query) {
-            Ok(mut statement) => {
-                match statement.query(params![&video_id]) {
-                    Ok(mut rows) => {
-                        loop {
-                            match rows.next() {
-                                Ok(Some(row)) => {
-                                    match row.get(0) {
-                                        Ok(s) => {
-                                            candidate_id = s;
-                                        },
-                                        Err(error) => {
-                                            log::error!("Failed to read candiate_id for video: {}", error);
-                                            continue;
-                                        }
-                                    }
-                                    match row.get(1) {
-                                        Ok(s) => {
-                                            let blob: Vec<u8> = s;
-                                            v.decode(&mut std::io::Cursor::new(blob));
-                                        },
-                                        Err(error) => {
-                                            log::error!("Failed to read binary data for video: {}", error);
-                                            continue;
-                                        }
-                                    }
-                                },
-                                Ok(None) => {
-                                    //log::warn!("No data read from candidates.");
-                                    break;
-                                },
-                                Err(error) => {
-                                    log::error!("Failed to read a row from candidates: {}", error);
-                                    break;
-                                }
-                            }
-                        }
-                    },
-                    Err(err) => {
-                        log::error!("could not read line from videostore_candidates database: {}", err);
-                    }
-                }
-            },
-            Err(err) => {
-                log::error!("could not prepare SQL statement: {}", err);
-            }
+        let mut statement = connection.prepare(query)?;
+        let mut rows = statement.query(params![&video_id])?;
+        while let Some(row) = rows.next()? {
+            candidate_id = row.get(0)?;
+            let blob: Vec<u8> = row.get(1)?;
+            v = crate::videocandidate::VideoCandidate::new();
+            v.decode(&mut std::io::Cursor::new(blob))?;
         }
-        (candidate_id, v)
+        Ok((candidate_id, v))
     }
 
     pub fn return_indice(
         &self,
         connection: &mut rusqlite::Connection,
         location: u32,
-    ) -> Vec<ScreenshotIndex> {
+    ) -> crate::Result<Vec<ScreenshotIndex>> {
         let mut v = Vec::new();
         let query = "SELECT filename, video_id, screenshot_id, runtime FROM videostore_indices WHERE location = ?1";
-        match connection.prepare(query) {
-            Ok(mut statement) => {
-                match statement.query(params![&location]) {
-                    Ok(mut rows) => {
-                        loop {
-                            match rows.next() {
-                                Ok(Some(row)) => {
-                                    let mut s = ScreenshotIndex::new();
-                                    match row.get(0) {
-                                        Ok(val) => s.id = val,
-                                        Err(error) => {
-                                            log::error!("Failed to read id for video: {}", error);
-                                            continue;
-                                        }
-                                    }
-                                    match row.get(1) {
-                                        Ok(val) => s.video_id = val,
-                                        Err(error) => {
-                                            log::error!("Failed to read video_id for video: {}", error);
-                                            continue;
-                                        }
-                                    }
-                                    match row.get(2) {
-                                        Ok(val) => s.screenshot_id = val,
-                                        Err(error) => {
-                                            log::error!("Failed to read screenshot_id for video: {}", error);
-                                            continue;
-                                        }
-                                    }
-                                    match row.get(3) {
-                                        Ok(val) => s.runtime = val,
-                                        Err(error) => {
-                                            log::error!("Failed to read runtime for video: {}", error);
-                                            continue;
-                                        }
-                                    }
-                                    v.push(s);
-                                },
-                                Ok(None) => {
-                                    //log::warn!("No data read from indices.");
-                                    break;
-                                },
-                                Err(error) => {
-                                    log::error!("Failed to read a row from indices: {}", error);
-                                    break;
-                                }
-                            }
-                        }
-                    },
-                    Err(err) => {
-                        log::error!("could not read line from videostore_indices database: {}", err);
-                    }
-                }
-            },
-            Err(err) => {
-                log::error!("could not prepare SQL statement: {}", err);
-            }
+        let mut statement = connection.prepare(query)?;
+        let mut rows = statement.query(params![&location])?;
+        while let Some(row) = rows.next()? {
+            let mut s = ScreenshotIndex::new();
+            s.id = row.get(0)?;
+            s.video_id = row.get(1)?;
+            s.screenshot_id = row.get(2)?;
+            s.runtime = row.get(3)?;
+            v.push(s);
         }
-        v
+        Ok(v)
     }
 
     pub fn size(&self) -> usize {
@@ -1132,65 +883,49 @@ impl VideoStore {
         crate::marshal::store_hash_u32_usize(&self.video_ids, to);
         crate::marshal::store_hash_u32_usize(&self.num_indices, to);
         crate::marshal::store_u32(self.num_index_values, to);
-        crate::marshal::store_f64(self.sensitivity, to);
+        self.sensitivity.encode(to);
         crate::marshal::store_bool(self.modified, to);
     }
 
     // decode data structure from binary stream
-    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) {
-        self.num_candidates = crate::marshal::restore_u32(from);
+    pub fn decode(&mut self, from: &mut std::io::Cursor<Vec<u8>>) -> crate::Result<()> {
+        self.num_candidates = crate::marshal::restore_u32(from)?;
         self.ids
-            .extend(crate::marshal::restore_hash_string_usize(from));
+            .extend(crate::marshal::restore_hash_string_usize(from)?);
         self.video_ids
-            .extend(crate::marshal::restore_hash_u32_usize(from));
+            .extend(crate::marshal::restore_hash_u32_usize(from)?);
         self.num_indices
-            .extend(crate::marshal::restore_hash_u32_usize(from));
-        self.num_index_values = crate::marshal::restore_u32(from);
-        self.sensitivity = crate::marshal::restore_f64(from);
-        self.modified = crate::marshal::restore_bool(from);
+            .extend(crate::marshal::restore_hash_u32_usize(from)?);
+        self.num_index_values = crate::marshal::restore_u32(from)?;
+        self.sensitivity.decode(from)?;
+        self.modified = crate::marshal::restore_bool(from)?;
         self.modified = false;
+        Ok(())
     }
 
     // Write binary stream to file
-    pub fn dump_binary(&self, storefile: &str) {
+    pub fn dump_binary(&self, storefile: &str) -> crate::Result<()> {
         let mut buffer = Vec::new();
         self.encode(&mut buffer);
         let path = std::path::Path::new(&storefile);
-        let display = path.display();
-        // Open a file in write-only mode, returns `io::Result<File>`
-        let mut write_file = match std::fs::File::create(&path) {
-            Err(why) => panic!("couldn't create {}: {}", display, why),
-            Ok(write_file) => write_file,
-        };
-        let ret = write_file.write(&mut buffer);
-        if ret.is_err() {
-            log::error!("failed to write binary data to file {}!", &storefile)
-        }
-        let ret = write_file.flush();
-        if ret.is_err() {
-            log::error!(
-                "failed to empty the write buffer for binary data to file {}!",
-                &storefile
-            )
-        }
+        let mut write_file = std::fs::File::create(path)?;
+        write_file.write_all(&buffer)?;
+        write_file.flush()?;
+        Ok(())
     }
 
     // read binary stream from file
-    pub fn slurp_binary(&mut self, storefile: &str, client: &mut rusqlite::Connection) {
+    pub fn slurp_binary(
+        &mut self,
+        storefile: &str,
+        client: &mut rusqlite::Connection,
+    ) -> crate::Result<()> {
         let path = std::path::Path::new(&storefile);
-        let display = path.display();
-        let mut input_file = match std::fs::File::open(&path) {
-            Err(why) => panic!("couldn't open {}: {}", display, why),
-            Ok(read_file) => read_file,
-        };
+        let mut input_file = std::fs::File::open(path)?;
         let mut buf = Vec::new();
-        let ret = input_file.read_to_end(&mut buf);
-        if ret.is_err() {
-            log::error!("Failed to read test file back in.");
-            return;
-        }
+        input_file.read_to_end(&mut buf)?;
         let mut read_file = std::io::Cursor::new(buf);
-        self.decode(&mut read_file);
+        self.decode(&mut read_file)?;
         let mut keys = Vec::new();
         for (key, _val) in self.ids.iter_mut() {
             keys.push(key.to_string());
@@ -1199,9 +934,10 @@ impl VideoStore {
             let filepath = std::path::Path::new(&key);
             if !filepath.is_file() {
                 // video has vanished, remove it from the store
-                self.delete(client, &key);
+                self.delete(client, &key)?;
             }
         }
+        Ok(())
     }
 }
 
@@ -1211,7 +947,7 @@ impl VideoStore {
 /// 
 pub fn connect(
     dbpath: &str,
-) -> Result<rusqlite::Connection, rusqlite::Error> {
+) -> crate::Result<rusqlite::Connection> {
     let path = std::path::Path::new(dbpath);
     let connection;
     if !path.is_file() {
@@ -1228,7 +964,7 @@ pub fn connect(
             Ok(_ret) => {},
             Err(error) => {
                 log::error!("Failed to create table candidates: {}", error);
-                return Err(error);
+                return Err(error.into());
             }
         }
         match connection.execute(
@@ -1237,7 +973,7 @@ pub fn connect(
             Ok(_ret) => {},
             Err(error) => {
                 log::error!("Failed to create index on candidates: {}", error);
-                return Err(error);
+                return Err(error.into());
             }
         }
         match connection.execute(
@@ -1246,7 +982,7 @@ pub fn connect(
             Ok(_) => {},
             Err(error) => {
                 log::error!("Failed to create index on candidates: {}", error);
-                return Err(error);
+                return Err(error.into());
             }
         }
         match connection.execute("
@@ -1263,7 +999,7 @@ pub fn connect(
             Ok(_ret) => {},
             Err(error) => {
                 log::error!("Failed to create table indices: {}", error);
-                return Err(error);
+                return Err(error.into());
             }
         }
         match connection.execute(
@@ -1272,7 +1008,7 @@ pub fn connect(
             Ok(_ret) => {},
             Err(error) => {
                 log::error!("Failed to create index on indices: {}", error);
-                return Err(error);
+                return Err(error.into());
             }
         }
         match connection.execute(
@@ -1281,7 +1017,7 @@ pub fn connect(
             Ok(_ret) => {},
             Err(error) => {
                 log::error!("Failed to create index on indices: {}", error);
-                return Err(error);
+                return Err(error.into());
             }
         }
         match connection.execute(
@@ -1290,7 +1026,7 @@ pub fn connect(
             Ok(_ret) => {},
             Err(error) => {
                 log::error!("Failed to create index on indices: {}", error);
-                return Err(error);
+                return Err(error.into());
             }
         }
         match connection.execute("
@@ -1301,28 +1037,62 @@ pub fn connect(
                 num_threads UNSIGNED BIG INT,
                 num_seconds_between_screenshots UNSIGNED BIG INT,
                 min_similar_screenshots_in_sequence UNSIGNED BIG INT,
-                max_candidates_in_cache UNSIGNED BIG INT
+                max_candidates_in_cache UNSIGNED BIG INT,
+                hash_scale UNSIGNED BIG INT,
+                hash_top_coefs UNSIGNED BIG INT,
+                hash_filter UNSIGNED BIG INT,
+                max_dhash_distance BIGINT,
+                max_histogram_distance BIGINT,
+                hash_perceptual UNSIGNED BIG INT,
+                hash_apply_orientation BOOLEAN
             )", [],
         ) {
             Ok(_ret) => {},
             Err(error) => {
                 log::error!("Failed to create table parameters: {}", error);
-                return Err(error);
+                return Err(error.into());
             }
         }
         match connection.execute(
             "INSERT INTO videostore_parameters (config_id, sensitivity, start_directory, num_threads, num_seconds_between_screenshots, min_similar_screenshots_in_sequence, max_candidates_in_cache) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            params![&1, &-60, &"./", &2, &10, &6, &100],
+            params![&1, &crate::sensitivity::SCORE_THRESHOLD_LOOSE, &"./", &2, &10, &6, &100],
         ) {
             Ok(retval) => log::warn!("Inserted {} data into parameter.", retval),
             Err(error) => {
                 log::error!("Failed to insert data into parameter database: {}", error);
-                return Err(error);
+                return Err(error.into());
             }
         }
 
     } else {
         connection = Connection::open(dbpath)?;
+        add_parameter_columns(&connection)?;
     }
     Ok(connection)
 }
+
+/// add_parameter_columns adds the hash config and sensitivity columns to the
+/// parameters table of a database that was created before they existed. They stay
+/// empty, which stands for the default config and no limits of the secondary metrics.
+fn add_parameter_columns(connection: &rusqlite::Connection) -> crate::Result<()> {
+    let mut statement = connection.prepare("PRAGMA table_info(videostore_parameters)")?;
+    let columns = statement
+        .query_map(params![], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let added = [
+        ("hash_scale", "UNSIGNED BIG INT"),
+        ("hash_top_coefs", "UNSIGNED BIG INT"),
+        ("hash_filter", "UNSIGNED BIG INT"),
+        ("max_dhash_distance", "BIGINT"),
+        ("max_histogram_distance", "BIGINT"),
+        ("hash_perceptual", "UNSIGNED BIG INT"),
+        ("hash_apply_orientation", "BOOLEAN"),
+    ];
+    for (column, datatype) in added {
+        if !columns.iter().any(|name| name == column) {
+            connection.execute(&format!("ALTER TABLE videostore_parameters ADD COLUMN {} {}", column, datatype), params![])?;
+            log::warn!("Added column {} to the parameters.", column);
+        }
+    }
+    Ok(())
+}